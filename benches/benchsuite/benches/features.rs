@@ -0,0 +1,78 @@
+use cargo::core::resolver::features::RequestedFeatures;
+use cargo::core::resolver::{self, CliFeatures, ReplayRegistry, ResolveOpts, VersionPreferences};
+use cargo::core::{Dependency, PackageId, SourceId, Summary};
+use cargo::util::interning::InternedString;
+use cargo::util::PartialVersion;
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::collections::BTreeMap;
+
+/// Number of dependencies of the root package.
+const DEPS: usize = 50;
+/// Number of features of each dependency, all enabled by the root.
+const FEATURES: usize = 200;
+
+fn summary(
+    name: &str,
+    source_id: SourceId,
+    deps: Vec<Dependency>,
+    features: &BTreeMap<InternedString, Vec<InternedString>>,
+) -> Summary {
+    let pkg_id = PackageId::new(name, "1.0.0", source_id).unwrap();
+    Summary::new(
+        pkg_id,
+        deps,
+        features,
+        None::<&String>,
+        None::<PartialVersion>,
+    )
+    .unwrap()
+}
+
+/// Benchmark of a resolve where every package is activated with a large set
+/// of features, which makes the options `build_deps` caches on expensive.
+fn big_feature_set(c: &mut Criterion) {
+    let source_id =
+        SourceId::from_url("registry+https://github.com/rust-lang/crates.io-index").unwrap();
+    let dep_features: BTreeMap<_, _> = (0..FEATURES)
+        .map(|i| (InternedString::new(&format!("f{i}")), Vec::new()))
+        .collect();
+
+    let mut deps = Vec::new();
+    let mut queries = Vec::new();
+    let mut root_features = Vec::new();
+    for i in 0..DEPS {
+        let name = format!("dep{i}");
+        let dep = Dependency::parse(name.as_str(), Some("1.0"), source_id).unwrap();
+        queries.push((
+            dep.clone(),
+            vec![summary(&name, source_id, Vec::new(), &dep_features)],
+        ));
+        deps.push(dep);
+        root_features.extend((0..FEATURES).map(|f| InternedString::new(&format!("{name}/f{f}"))));
+    }
+    let root_table = BTreeMap::from([(InternedString::new("all"), root_features)]);
+    let root = summary("root", source_id, deps, &root_table);
+    let cli_features = CliFeatures::from_command_line(&["all".to_string()], false, false).unwrap();
+    let opts = ResolveOpts::new(false, RequestedFeatures::CliFeatures(cli_features));
+    let mut registry = ReplayRegistry::new(queries);
+    let version_prefs = VersionPreferences::default();
+
+    c.bench_function("big_feature_set", |b| {
+        b.iter(|| {
+            resolver::resolve(
+                &[(root.clone(), opts.clone())],
+                &[],
+                &mut registry,
+                &version_prefs,
+                None,
+                false,
+                None,
+                None,
+            )
+            .unwrap();
+        })
+    });
+}
+
+criterion_group!(benches, big_feature_set);
+criterion_main!(benches);
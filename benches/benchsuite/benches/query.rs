@@ -0,0 +1,59 @@
+use cargo::core::resolver::{RegistryQueryer, ReplayRegistry, VersionPreferences};
+use cargo::core::{Dependency, PackageId, PackageIdSpec, SourceId, Summary};
+use cargo::util::PartialVersion;
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::collections::BTreeMap;
+use std::task::Poll;
+
+/// Number of versions the registry returns for the queried package.
+const VERSIONS: u64 = 10_000;
+
+fn candidates(source_id: SourceId) -> Vec<Summary> {
+    (0..VERSIONS)
+        .map(|patch| {
+            let version = format!("1.0.{patch}");
+            let pkg_id = PackageId::new("foo", version.as_str(), source_id).unwrap();
+            Summary::new(
+                pkg_id,
+                Vec::new(),
+                &BTreeMap::new(),
+                None::<&String>,
+                None::<PartialVersion>,
+            )
+            .unwrap()
+        })
+        .collect()
+}
+
+/// Benchmark of `RegistryQueryer::query` on a package with many versions,
+/// with no replacements and with a replacement for an unrelated package.
+fn query(c: &mut Criterion) {
+    let source_id =
+        SourceId::from_url("registry+https://github.com/rust-lang/crates.io-index").unwrap();
+    let dep = Dependency::parse("foo", Some("1.0"), source_id).unwrap();
+    let mut registry = ReplayRegistry::new([(dep.clone(), candidates(source_id))]);
+    let version_prefs = VersionPreferences::default();
+    let unrelated = vec![(
+        PackageIdSpec::parse("bar").unwrap(),
+        Dependency::parse("bar", Some("1.0"), source_id).unwrap(),
+    )];
+
+    let mut group = c.benchmark_group("query");
+    for (name, replacements) in [
+        ("no_replacements", &[][..]),
+        ("replacements", &unrelated[..]),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                // A new queryer each time, so nothing comes from its cache.
+                let mut queryer =
+                    RegistryQueryer::new(&mut registry, replacements, &version_prefs, false, None);
+                assert!(matches!(queryer.query(&dep, false), Poll::Ready(Ok(_))));
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, query);
+criterion_main!(benches);
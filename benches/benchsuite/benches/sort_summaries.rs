@@ -0,0 +1,58 @@
+use cargo::core::resolver::{VersionOrdering, VersionPreferences};
+use cargo::core::{PackageId, SourceId, Summary};
+use cargo::util::PartialVersion;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use std::collections::BTreeMap;
+
+/// Number of versions in the candidate list.
+const VERSIONS: u64 = 10_000;
+
+fn candidates(source_id: SourceId) -> Vec<Summary> {
+    (0..VERSIONS)
+        .map(|i| {
+            // Shuffled, with some build metadata thrown in to exercise the tiebreaks.
+            let patch = (i * 7919) % VERSIONS;
+            let version = if i % 10 == 0 {
+                format!("1.0.{patch}+build{i}")
+            } else {
+                format!("1.0.{patch}")
+            };
+            let pkg_id = PackageId::new("foo", version.as_str(), source_id).unwrap();
+            Summary::new(
+                pkg_id,
+                Vec::new(),
+                &BTreeMap::new(),
+                None::<&String>,
+                None::<PartialVersion>,
+            )
+            .unwrap()
+        })
+        .collect()
+}
+
+/// Benchmark of `VersionPreferences::sort_summaries` on a package with many versions.
+fn sort_summaries(c: &mut Criterion) {
+    let source_id =
+        SourceId::from_url("registry+https://github.com/rust-lang/crates.io-index").unwrap();
+    let summaries = candidates(source_id);
+    let mut version_prefs = VersionPreferences::default();
+    version_prefs.prefer_package_id(summaries[VERSIONS as usize / 2].package_id());
+
+    let mut group = c.benchmark_group("sort_summaries");
+    for (name, ordering) in [
+        ("maximum", VersionOrdering::MaximumVersionsFirst),
+        ("minimum", VersionOrdering::MinimumVersionsFirst),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter_batched_ref(
+                || summaries.clone(),
+                |summaries| version_prefs.sort_summaries(summaries, ordering, false),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, sort_summaries);
+criterion_main!(benches);
@@ -11,10 +11,12 @@ use std::task::Poll;
 use std::time::Instant;
 
 use cargo::core::dependency::DepKind;
-use cargo::core::resolver::{self, ResolveOpts, VersionPreferences};
+use cargo::core::resolver::features::RequestedFeatures;
+use cargo::core::resolver::{self, CliFeatures, ResolveOpts, VersionPreferences};
 use cargo::core::source::{GitReference, QueryKind, SourceId};
 use cargo::core::Resolve;
 use cargo::core::{Dependency, PackageId, Registry, Summary};
+use cargo::util::interning::InternedString;
 use cargo::util::{CargoResult, Config, Graph, IntoUrl, PartialVersion};
 
 use proptest::collection::{btree_map, vec};
@@ -197,6 +199,7 @@ pub fn resolve_with_config_raw(
         Some(config),
         true,
         max_rust_version,
+        None,
     );
 
     // The largest test in our suite takes less then 30 sec.
@@ -562,7 +565,7 @@ macro_rules! pkg {
     })
 }
 
-fn registry_loc() -> SourceId {
+pub fn registry_loc() -> SourceId {
     lazy_static::lazy_static! {
         static ref EXAMPLE_DOT_COM: SourceId =
             SourceId::for_registry(&"https://example.com".into_url().unwrap()).unwrap();
@@ -591,6 +594,30 @@ pub fn pkg_dep<T: ToPkgId>(name: T, dep: Vec<Dependency>) -> Summary {
     .unwrap()
 }
 
+pub fn pkg_features<T: ToPkgId>(
+    name: T,
+    dep: Vec<Dependency>,
+    features: &[(&str, &[&str])],
+) -> Summary {
+    let features: BTreeMap<_, _> = features
+        .iter()
+        .map(|(name, values)| {
+            (
+                InternedString::new(name),
+                values.iter().map(|v| InternedString::new(v)).collect(),
+            )
+        })
+        .collect();
+    Summary::new(
+        name.to_pkgid(),
+        dep,
+        &features,
+        None::<&String>,
+        None::<PartialVersion>,
+    )
+    .unwrap()
+}
+
 pub fn pkg_id(name: &str) -> PackageId {
     PackageId::new(name, "1.0.0", registry_loc()).unwrap()
 }
@@ -656,6 +683,13 @@ pub fn dep_kind(name: &str, kind: DepKind) -> Dependency {
     dep(name).set_kind(kind).clone()
 }
 
+/// Options for resolving a root package with the given `--features`.
+pub fn resolve_opts(features: &[&str]) -> ResolveOpts {
+    let features: Vec<String> = features.iter().map(|f| f.to_string()).collect();
+    let cli_features = CliFeatures::from_command_line(&features, false, false).unwrap();
+    ResolveOpts::new(false, RequestedFeatures::CliFeatures(cli_features))
+}
+
 pub fn registry(pkgs: Vec<Summary>) -> Vec<Summary> {
     pkgs
 }
@@ -0,0 +1,610 @@
+//! Tests for the feature helpers the resolver exposes.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::rc::Rc;
+
+use cargo::core::dependency::DepKind;
+use cargo::core::resolver::features::RequestedFeatures;
+use cargo::core::resolver::{
+    cli_feature_uses, diff_build_deps_results, feature_origins, features_from_cli,
+    minimal_features_enabling_dep, order_deps_by_constrainedness, requirement_errors,
+    resolve_features_reporting_skipped, union_edge_features, ActivateError, ActivateResult,
+    BuildDepsDiff, BuildDepsResult, CliFeatureUse, CliFeatures, DepInfo, FeatureOrigin,
+    FeatureUnion, RequirementError, RequirementWarning, RequirementsBuilder, ResolveOpts,
+};
+use cargo::core::{Dependency, FeatureValue, PackageId, Summary};
+use cargo::util::interning::InternedString;
+use cargo::util::Config;
+
+use resolver_tests::{dep_req, pkg, pkg_features, registry_loc, resolve_opts};
+
+fn resolve_features(
+    parent: Option<PackageId>,
+    s: &Summary,
+    opts: &ResolveOpts,
+) -> ActivateResult<(
+    HashSet<InternedString>,
+    Vec<(Dependency, Rc<BTreeSet<InternedString>>)>,
+    Vec<RequirementWarning>,
+)> {
+    resolve_features_reporting_skipped(parent, s, opts, None)
+}
+
+#[test]
+fn test_deep_feature_chain() {
+    let names: Vec<String> = (0..100_000).map(|i| format!("f{}", i)).collect();
+    let values: Vec<Vec<&str>> = (0..names.len())
+        .map(|i| names.get(i + 1).map(|n| n.as_str()).into_iter().collect())
+        .collect();
+    let table: Vec<(&str, &[&str])> = names
+        .iter()
+        .zip(values.iter())
+        .map(|(name, values)| (name.as_str(), values.as_slice()))
+        .collect();
+    let s = pkg_features("foo", Vec::new(), &table);
+
+    let (features, _, _) = resolve_features(None, &s, &resolve_opts(&["f0"])).unwrap();
+    assert_eq!(features.len(), names.len());
+}
+
+#[test]
+fn test_deprecated_feature() {
+    let mut s = pkg_features(
+        "foo",
+        Vec::new(),
+        &[("old", &["new"]), ("new", &[]), ("default", &["old"])],
+    );
+    s.set_deprecated_features([InternedString::new("old")]);
+
+    let (_, _, warnings) = resolve_features(None, &s, &resolve_opts(&[])).unwrap();
+    assert!(warnings.is_empty());
+
+    let (features, _, warnings) = resolve_features(None, &s, &resolve_opts(&["old"])).unwrap();
+    assert!(features.contains("new"));
+    assert_eq!(
+        warnings,
+        [RequirementWarning::DeprecatedFeature {
+            package: s.package_id(),
+            feature: InternedString::new("old"),
+            enabled_by: None,
+        }]
+    );
+}
+
+#[test]
+fn test_all_optional_deps() {
+    let mut bar = dep_req("bar", "1.0");
+    bar.set_optional(true);
+    let s = pkg_features("foo", vec![bar], &[("a", &["dep:bar"])]);
+
+    let (_, deps, _) = resolve_features(None, &s, &resolve_opts(&[])).unwrap();
+    assert!(deps.is_empty());
+
+    let mut all = resolve_opts(&[]);
+    all.all_optional_deps = true;
+    let (features, deps, _) = resolve_features(None, &s, &all).unwrap();
+    assert!(features.is_empty());
+    assert_eq!(deps.len(), 1);
+    assert_eq!(deps[0].0.name_in_toml(), "bar");
+}
+
+#[test]
+fn test_no_default_features() {
+    let s = pkg_features(
+        "foo",
+        Vec::new(),
+        &[("default", &["std"]), ("std", &["alloc"]), ("alloc", &[])],
+    );
+    let features = |opts: &ResolveOpts| {
+        let mut features: Vec<_> = resolve_features(None, &s, opts)
+            .unwrap()
+            .0
+            .into_iter()
+            .map(|f| f.to_string())
+            .collect();
+        features.sort();
+        features
+    };
+
+    let defaults = ResolveOpts::new(
+        false,
+        RequestedFeatures::CliFeatures(CliFeatures::new_all(false)),
+    );
+    assert_eq!(features(&defaults), ["alloc", "default", "std"]);
+
+    let mut minimal = defaults.clone();
+    minimal.no_default_features = true;
+    assert!(features(&minimal).is_empty());
+
+    let mut explicit = resolve_opts(&["default"]);
+    explicit.no_default_features = true;
+    assert_eq!(features(&explicit), ["alloc", "default", "std"]);
+}
+
+#[test]
+fn test_diff_build_deps_results() {
+    let bar = dep_req("bar", "1.0");
+    let result = |features: &[&str], deps: &[&Dependency]| -> BuildDepsResult {
+        Rc::new((
+            features.iter().map(|f| InternedString::new(f)).collect(),
+            Rc::new(
+                deps.iter()
+                    .map(|&dep| (dep.clone(), Rc::new(Vec::new()), Rc::default(), dep.kind()))
+                    .collect(),
+            ),
+        ))
+    };
+    let foo = PackageId::new("foo", "1.0.0", registry_loc()).unwrap();
+    let same = PackageId::new("same", "1.0.0", registry_loc()).unwrap();
+
+    let before = HashMap::from([(foo, result(&["a"], &[])), (same, result(&["a"], &[]))]);
+    let after = HashMap::from([(foo, result(&["b"], &[&bar])), (same, result(&["a"], &[]))]);
+
+    let diff = diff_build_deps_results(&before, &after);
+    assert_eq!(diff.len(), 1);
+    assert_eq!(
+        diff[&foo],
+        BuildDepsDiff {
+            added_features: BTreeSet::from([InternedString::new("b")]),
+            removed_features: BTreeSet::from([InternedString::new("a")]),
+            added_deps: BTreeSet::from([InternedString::new("bar")]),
+            removed_deps: BTreeSet::new(),
+        }
+    );
+}
+
+#[test]
+fn test_cli_feature_uses() {
+    let s = pkg_features("foo", Vec::new(), &[("a", &["b"]), ("b", &[])]);
+    let uses = cli_feature_uses(None, &s, &resolve_opts(&["a", "b", "typo"])).unwrap();
+    assert_eq!(
+        uses,
+        [
+            (FeatureValue::new("a".into()), CliFeatureUse::Exercised),
+            (FeatureValue::new("b".into()), CliFeatureUse::Redundant),
+            (FeatureValue::new("typo".into()), CliFeatureUse::Missing),
+        ]
+    );
+}
+
+#[test]
+fn test_features_from_cli() {
+    let s = pkg_features(
+        "foo",
+        Vec::new(),
+        &[("default", &["a"]), ("a", &["b"]), ("b", &[]), ("c", &[])],
+    );
+    let input = ["c".to_string(), "nope".to_string(), "bar/x".to_string()];
+    let (features, errors) = features_from_cli(&s, &input, true);
+    let mut features: Vec<_> = features.iter().map(|f| f.as_str()).collect();
+    features.sort();
+    assert_eq!(features, ["a", "b", "c", "default"]);
+    assert_eq!(
+        errors,
+        [
+            (
+                "nope".to_string(),
+                RequirementError::MissingFeature(InternedString::new("nope"))
+            ),
+            (
+                "bar/x".to_string(),
+                RequirementError::MissingDependency(InternedString::new("bar"))
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_redundant_weak_dep_feature() {
+    let mut bar = dep_req("bar", "1.0");
+    bar.set_optional(true);
+    let s = pkg_features(
+        "foo",
+        vec![bar],
+        &[("a", &["bar?/x"]), ("b", &["bar/x"]), ("c", &["bar?/y"])],
+    );
+
+    let (_, _, warnings) = resolve_features(None, &s, &resolve_opts(&["a", "c"])).unwrap();
+    assert!(warnings.is_empty());
+
+    let (_, _, warnings) = resolve_features(None, &s, &resolve_opts(&["a", "b", "c"])).unwrap();
+    assert_eq!(
+        warnings,
+        [RequirementWarning::RedundantWeakDepFeature {
+            package: s.package_id(),
+            dep: InternedString::new("bar"),
+            feature: InternedString::new("x"),
+        }]
+    );
+}
+
+#[test]
+fn test_feature_alias() {
+    let mut explicit = dep_req("explicit", "1.0");
+    explicit.set_optional(true);
+    let mut implicit = dep_req("implicit", "1.0");
+    implicit.set_optional(true);
+    let mut s = pkg_features("foo", vec![explicit, implicit], &[("x", &["dep:explicit"])]);
+    s.set_feature_aliases([
+        (InternedString::new("old"), InternedString::new("explicit")),
+        (
+            InternedString::new("legacy"),
+            InternedString::new("implicit"),
+        ),
+    ]);
+    let enabled = |features: &[&str]| {
+        let (features, deps, _) = resolve_features(None, &s, &resolve_opts(features)).unwrap();
+        let mut features: Vec<_> = features.iter().map(|f| f.as_str()).collect();
+        features.sort();
+        let deps: Vec<_> = deps
+            .iter()
+            .map(|(d, _)| d.name_in_toml().as_str())
+            .collect();
+        (features, deps)
+    };
+
+    assert_eq!(enabled(&["old"]), (vec!["old"], vec!["explicit"]));
+    assert_eq!(enabled(&["x", "old"]), (vec!["old", "x"], vec!["explicit"]));
+    // The implicit feature of the aliased dependency is not enabled.
+    assert_eq!(enabled(&["legacy"]), (vec!["legacy"], vec!["implicit"]));
+    assert_eq!(
+        enabled(&["implicit", "legacy"]),
+        (vec!["implicit", "legacy"], vec!["implicit"])
+    );
+}
+
+#[test]
+fn test_per_package_overrides() {
+    use cargo::core::resolver::ExtraFeatures;
+
+    let foo = pkg_features("foo", Vec::new(), &[("a", &[]), ("b", &["a"])]);
+    let mut opts = resolve_opts(&[]);
+    let extra = ExtraFeatures {
+        features: BTreeSet::from([InternedString::new("b")]),
+    };
+    opts.set_extra_features(InternedString::new("foo"), extra);
+
+    let (features, _, _) = resolve_features(None, &foo, &opts).unwrap();
+    let mut features: Vec<_> = features.iter().map(|f| f.as_str()).collect();
+    features.sort();
+    assert_eq!(features, ["a", "b"]);
+
+    let other = pkg(("other", "1.0.0"));
+    let (features, _, _) = resolve_features(None, &other, &opts).unwrap();
+    assert!(features.is_empty());
+
+    let dep_opts = opts.for_dependency(RequestedFeatures::DepFeatures {
+        features: Rc::default(),
+        uses_default_features: false,
+    });
+    let (features, _, _) = resolve_features(Some(other.package_id()), &foo, &dep_opts).unwrap();
+    assert_eq!(features.len(), 2);
+}
+
+#[test]
+fn test_no_implicit_features() {
+    let mut bar = dep_req("bar", "1.0");
+    bar.set_optional(true);
+    let mut baz = dep_req("baz", "1.0");
+    baz.set_optional(true);
+    let s = pkg_features(
+        "foo",
+        vec![bar, baz],
+        &[("a", &["bar"]), ("b", &["dep:baz"]), ("c", &["bar/x"])],
+    );
+    let strict = |features: &[&str]| {
+        let mut opts = resolve_opts(features);
+        opts.no_implicit_features = true;
+        opts
+    };
+
+    assert!(resolve_features(None, &s, &resolve_opts(&["a"])).is_ok());
+    assert!(resolve_features(None, &s, &strict(&["b"])).is_ok());
+    for features in [&["a"][..], &["bar"], &["c"]] {
+        let Err(ActivateError::Fatal(e)) = resolve_features(None, &s, &strict(features)) else {
+            panic!("expected {:?} to be rejected", features)
+        };
+        assert!(e.to_string().contains("`dep:bar`"));
+    }
+}
+
+#[test]
+fn test_minimal_features_enabling_dep() {
+    let mut heavy = dep_req("heavy", "1.0");
+    heavy.set_optional(true);
+    let light = dep_req("light", "1.0");
+    let mut extra = dep_req("extra", "1.0");
+    extra.set_optional(true);
+    let mut s = pkg_features(
+        "foo",
+        vec![heavy, light, extra],
+        &[
+            ("default", &["full"]),
+            ("full", &["a", "b"]),
+            ("a", &[]),
+            ("b", &["dep:heavy"]),
+            ("c", &[]),
+            ("d", &["dep:extra"]),
+        ],
+    );
+    s.set_feature_preconditions([(InternedString::new("d"), [InternedString::new("c")].into())]);
+    let minimal = |name: &str| minimal_features_enabling_dep(&s, InternedString::new(name));
+
+    // `full` and `default` enable it as well, but along with more features.
+    assert_eq!(minimal("heavy"), Some(vec![InternedString::new("b")]));
+    assert_eq!(minimal("light"), Some(Vec::new()));
+    assert_eq!(
+        minimal("extra"),
+        Some(vec![InternedString::new("c"), InternedString::new("d")])
+    );
+    assert_eq!(minimal("missing"), None);
+}
+
+#[test]
+fn test_requirement_errors() {
+    let mut s = pkg_features(
+        "foo",
+        Vec::new(),
+        &[
+            ("a", &["a", "b"]),
+            ("b", &[]),
+            ("c", &[]),
+            ("d", &["b", "c"]),
+            ("e", &["d"]),
+        ],
+    );
+    let group = ["b", "c"].map(InternedString::new).into_iter().collect();
+    s.set_exclusive_feature_groups([group]);
+
+    let name = InternedString::new;
+    assert_eq!(
+        requirement_errors(&s),
+        [
+            (name("a"), RequirementError::Cycle(name("a"))),
+            (
+                name("d"),
+                RequirementError::MutuallyExclusive(name("b"), name("c"))
+            ),
+        ]
+    );
+    assert!(requirement_errors(&pkg_features(
+        "foo",
+        Vec::new(),
+        &[("a", &["b"]), ("b", &[])]
+    ))
+    .is_empty());
+}
+
+#[test]
+fn test_requirements_builder() {
+    let mut bar = dep_req("bar", "1.0");
+    bar.set_optional(true);
+    let mut s = pkg_features(
+        "foo",
+        vec![bar],
+        &[
+            ("a", &["b"]),
+            ("b", &[]),
+            ("c", &["b", "dep:bar", "bar/x"]),
+            ("d", &[]),
+        ],
+    );
+    let group = ["a", "d"].map(InternedString::new).into_iter().collect();
+    s.set_exclusive_feature_groups([group]);
+    let name = InternedString::new;
+    let sorted = |features: &HashSet<InternedString>| {
+        let mut features: Vec<_> = features.iter().map(|f| f.as_str()).collect();
+        features.sort();
+        features
+    };
+
+    let mut builder = RequirementsBuilder::new(&s);
+    builder.add_feature(name("a")).unwrap();
+    builder.add_feature(name("c")).unwrap();
+    assert_eq!(sorted(builder.features()), ["a", "b", "c"]);
+    assert_eq!(builder.deps()[&name("bar")], BTreeSet::from([name("x")]));
+    assert_eq!(
+        builder.add_feature(name("d")),
+        Err(RequirementError::MutuallyExclusive(name("a"), name("d")))
+    );
+    assert_eq!(sorted(builder.features()), ["a", "b", "c"]);
+
+    // `b` is still enabled by `c`.
+    assert!(builder.remove_feature(name("a")));
+    assert_eq!(sorted(builder.features()), ["b", "c"]);
+    assert!(!builder.remove_feature(name("a")));
+    builder.add_feature(name("d")).unwrap();
+
+    assert!(builder.remove_feature(name("c")));
+    assert_eq!(sorted(builder.features()), ["d"]);
+    assert!(builder.deps().is_empty());
+    assert_eq!(builder.selected().collect::<Vec<_>>(), [name("d")]);
+}
+
+#[test]
+fn test_union_edge_features() {
+    let set = |features: &[&str]| -> Rc<BTreeSet<InternedString>> {
+        Rc::new(features.iter().map(|f| InternedString::new(f)).collect())
+    };
+    let a = PackageId::new("a", "1.0.0", registry_loc()).unwrap();
+    let b = PackageId::new("b", "1.0.0", registry_loc()).unwrap();
+    let (from_a, from_b, none) = (set(&["x", "y"]), set(&["y"]), set(&[]));
+
+    let union = union_edge_features([(b, &from_b), (a, &from_a), (a, &from_a), (b, &none)]);
+    let x = InternedString::new("x");
+    let y = InternedString::new("y");
+    assert_eq!(union.features, BTreeSet::from([x, y]));
+    assert_eq!(union.requested_by[&x], [a]);
+    assert_eq!(union.requested_by[&y], [a, b]);
+
+    assert_eq!(union_edge_features([]), FeatureUnion::default());
+}
+
+#[test]
+fn test_resolve_features_reporting_skipped() {
+    let mut bar = dep_req("bar", "1.0");
+    bar.set_optional(true);
+    let mut baz = dep_req("baz", "1.0");
+    baz.set_optional(true);
+    let qux = dep_req("qux", "1.0");
+    let s = pkg_features(
+        "foo",
+        vec![bar, baz, qux],
+        &[("b", &["dep:bar"]), ("z", &["dep:baz"])],
+    );
+
+    let mut skipped = BTreeSet::new();
+    let (_, deps, _) =
+        resolve_features_reporting_skipped(None, &s, &resolve_opts(&["b"]), Some(&mut skipped))
+            .unwrap();
+    assert_eq!(deps.len(), 2);
+    assert_eq!(skipped, BTreeSet::from([InternedString::new("baz")]));
+}
+
+#[test]
+fn test_order_deps_by_constrainedness() {
+    let info = |name: &str, candidates: usize| -> DepInfo {
+        let dep = dep_req(name, "1.0");
+        let candidates = (0..candidates)
+            .map(|patch| pkg((name, format!("1.0.{patch}"))))
+            .collect();
+        (dep, Rc::new(candidates), Rc::default(), DepKind::Normal)
+    };
+    let mut deps = vec![info("a", 3), info("b", 1), info("c", 2), info("d", 1)];
+
+    order_deps_by_constrainedness(&mut deps);
+    let names: Vec<_> = deps
+        .iter()
+        .map(|d| d.0.package_name().to_string())
+        .collect();
+    assert_eq!(names, ["b", "d", "c", "a"]);
+}
+
+#[test]
+fn test_feature_origins() {
+    let s = pkg_features(
+        "foo",
+        Vec::new(),
+        &[
+            ("default", &["std"]),
+            ("std", &["alloc"]),
+            ("alloc", &[]),
+            ("serde", &["alloc"]),
+        ],
+    );
+    let name = InternedString::new;
+
+    let mut opts = resolve_opts(&["serde"]);
+    if let RequestedFeatures::CliFeatures(cli) = &mut opts.features {
+        cli.uses_default_features = true;
+    }
+    let origins = feature_origins(None, &s, &opts).unwrap();
+    assert_eq!(
+        origins,
+        BTreeMap::from([
+            (name("alloc"), FeatureOrigin::Explicit),
+            (name("default"), FeatureOrigin::DefaultFeatures),
+            (name("serde"), FeatureOrigin::Explicit),
+            (name("std"), FeatureOrigin::DefaultFeatures),
+        ])
+    );
+
+    let opts = opts.for_dependency(RequestedFeatures::DepFeatures {
+        features: Rc::new(BTreeSet::from([name("std")])),
+        uses_default_features: true,
+    });
+    let origins = feature_origins(Some(s.package_id()), &s, &opts).unwrap();
+    assert_eq!(origins[&name("std")], FeatureOrigin::Explicit);
+    assert_eq!(origins[&name("default")], FeatureOrigin::DefaultFeatures);
+}
+
+#[test]
+fn test_all_deps_superset() {
+    let mut bar = dep_req("bar", "1.0");
+    bar.set_optional(true);
+    let mut baz = dep_req("baz", "1.0");
+    baz.set_optional(true);
+    let qux = dep_req("qux", "1.0");
+    let mut s = pkg_features(
+        "foo",
+        vec![bar, baz, qux],
+        &[("b", &["bar/std", "qux/a"]), ("z", &["dep:baz", "qux/b"])],
+    );
+    let group = ["b", "z"].map(InternedString::new).into_iter().collect();
+    s.set_exclusive_feature_groups([group]);
+
+    let mut opts = resolve_opts(&[]);
+    opts.all_deps_superset = true;
+    let (features, deps, _) = resolve_features(None, &s, &opts).unwrap();
+    assert_eq!(features.len(), 2);
+    let deps: Vec<_> = deps
+        .iter()
+        .map(|(dep, features)| {
+            let features: Vec<_> = features.iter().map(|f| f.to_string()).collect();
+            (dep.name_in_toml().to_string(), features)
+        })
+        .collect();
+    assert_eq!(
+        deps,
+        [
+            ("bar".to_string(), vec!["std".to_string()]),
+            ("baz".to_string(), vec![]),
+            ("qux".to_string(), vec!["a".to_string(), "b".to_string()]),
+        ]
+    );
+}
+
+#[test]
+fn test_serialized_requirement_error() {
+    let package_id = pkg_features("foo", Vec::new(), &[]).package_id();
+    let err = RequirementError::MutuallyExclusive("std".into(), "no_std".into());
+    assert_eq!(
+        serde_json::to_value(err.to_serialized(package_id)).unwrap(),
+        serde_json::json!({
+            "schema_version": 1,
+            "kind": "mutually-exclusive",
+            "package_id": package_id,
+            "features": ["std", "no_std"],
+            "message": "features `std` and `no_std` cannot be enabled together",
+        })
+    );
+
+    let err = RequirementError::MissingDependency("serde".into());
+    let serialized = serde_json::to_value(err.to_serialized(package_id)).unwrap();
+    assert_eq!(serialized["kind"], "missing-dependency");
+    assert_eq!(serialized["dependency"], "serde");
+    assert!(serialized.get("features").is_none());
+}
+
+#[test]
+fn test_cli_features_from_env() {
+    let mut config = Config::default().unwrap();
+    config.set_env(HashMap::from([
+        ("CI_FEATURES".to_string(), "a, b/c".to_string()),
+        ("CI_NO_DEFAULT_FEATURES".to_string(), "true".to_string()),
+    ]));
+    let cli = CliFeatures::from_env(&config, "CI").unwrap();
+    assert!(!cli.all_features);
+    assert!(!cli.uses_default_features);
+    assert_eq!(cli.features.len(), 2);
+
+    let cli = CliFeatures::from_env(&config, "OTHER").unwrap();
+    assert_eq!(cli, CliFeatures::new_all(false));
+
+    config.set_env(HashMap::from([(
+        "CI_ALL_FEATURES".to_string(),
+        "yes".to_string(),
+    )]));
+    let err = CliFeatures::from_env(&config, "CI").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "`CI_ALL_FEATURES` must be `true` or `false`, but is `yes`"
+    );
+
+    config.set_env(HashMap::from([
+        ("CI_ALL_FEATURES".to_string(), "true".to_string()),
+        ("CI_FEATURES".to_string(), "a/b/c".to_string()),
+    ]));
+    let err = CliFeatures::from_env(&config, "CI").unwrap_err();
+    assert_eq!(err.to_string(), "failed to parse `CI_FEATURES`");
+}
@@ -0,0 +1,1529 @@
+//! Tests for the `RegistryQueryer` API, driven through a `ReplayRegistry`.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, SystemTime};
+
+use cargo::core::dependency::DepKind;
+use cargo::core::resolver::{
+    AmbiguousSourceError, ConflictKind, ConflictPolicy, ExtraFeatures, MinimalOrderingCounts,
+    RegistryQueryer, ReplacementStatus, ReplayRegistry, ResolveCancelled, TraceOutcome,
+    VersionPreferences, WhyNot,
+};
+use cargo::core::source::QueryKind;
+use cargo::core::{
+    Dependency, Edition, MergedRegistry, PackageId, PackageIdSpec, Registry, SourceId, Summary,
+};
+use cargo::util::interning::InternedString;
+use cargo::util::{CargoResult, PartialVersion};
+use cargo_platform::Cfg;
+
+use resolver_tests::{dep_req, pkg, pkg_dep, pkg_features, registry_loc, resolve_opts, ToPkgId};
+
+#[test]
+fn test_record_and_replay_queries() {
+    let bar = dep_req("bar", "1.0");
+    let log = vec![(
+        bar.clone(),
+        vec![pkg(("bar", "1.0.0")), pkg(("bar", "1.1.0"))],
+    )];
+    let mut registry = ReplayRegistry::new(log.clone());
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    queryer.record_queries();
+
+    let Poll::Ready(Ok(candidates)) = queryer.query(&bar, false) else {
+        panic!("expected the replayed candidates")
+    };
+    assert_eq!(candidates.len(), 2);
+    assert_eq!(queryer.recorded_queries(), log.as_slice());
+
+    let baz = dep_req("baz", "1.0");
+    assert!(matches!(queryer.query(&baz, false), Poll::Ready(Err(_))));
+}
+
+#[test]
+fn test_assumed_rust_version() {
+    let bar = dep_req("bar", "1.0");
+    let declared = Summary::new(
+        PackageId::new("bar", "1.0.0", registry_loc()).unwrap(),
+        Vec::new(),
+        &BTreeMap::new(),
+        None::<&String>,
+        Some("1.50".parse().unwrap()),
+    )
+    .unwrap();
+    let undeclared = pkg(("bar", "1.1.0"));
+    let mut registry = ReplayRegistry::new([(bar.clone(), vec![declared, undeclared.clone()])]);
+    let version_prefs = VersionPreferences::default();
+    let max = Some("1.55".parse().unwrap());
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, max);
+
+    let Poll::Ready(Ok(candidates)) = queryer.query(&bar, false) else {
+        panic!("expected the replayed candidates")
+    };
+    assert_eq!(candidates.len(), 2);
+
+    queryer.set_assumed_rust_version(Some("1.56".parse().unwrap()));
+    let Poll::Ready(Ok(candidates)) = queryer.query(&bar, false) else {
+        panic!("expected the replayed candidates")
+    };
+    assert_eq!(candidates.len(), 1);
+    assert_eq!(candidates[0].version().to_string(), "1.0.0");
+    assert_eq!(queryer.msrv_filtered()[&bar], [undeclared.package_id()]);
+}
+
+#[test]
+fn test_query_grouped() {
+    let other = SourceId::from_url("registry+https://example.com/index").unwrap();
+    let bar = dep_req("bar", "1.0");
+    let mirrored = PackageId::new("bar", "1.1.0", other).unwrap();
+    let mirrored = pkg_dep(mirrored, Vec::new());
+    let mut registry = ReplayRegistry::new([(
+        bar.clone(),
+        vec![pkg(("bar", "1.0.0")), mirrored, pkg(("bar", "1.2.0"))],
+    )]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+
+    let Poll::Ready(Ok(groups)) = queryer.query_grouped(&bar) else {
+        panic!("expected the replayed candidates")
+    };
+    let groups: Vec<_> = groups
+        .iter()
+        .map(|(id, summaries)| {
+            let versions: Vec<_> = summaries.iter().map(|s| s.version().to_string()).collect();
+            (*id, versions)
+        })
+        .collect();
+    assert_eq!(
+        groups,
+        [
+            (
+                registry_loc(),
+                vec!["1.2.0".to_string(), "1.0.0".to_string()]
+            ),
+            (other, vec!["1.1.0".to_string()]),
+        ]
+    );
+
+    let from_source = |queryer: &mut RegistryQueryer<'_>, source| {
+        let Poll::Ready(Ok(candidates)) = queryer.query_from_source(&bar, source) else {
+            panic!("expected the replayed candidates")
+        };
+        candidates
+            .iter()
+            .map(|s| s.version().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        from_source(&mut queryer, registry_loc()),
+        ["1.2.0", "1.0.0"]
+    );
+    assert_eq!(from_source(&mut queryer, other), ["1.1.0"]);
+    let unknown = SourceId::from_url("registry+https://example.com/other").unwrap();
+    assert!(from_source(&mut queryer, unknown).is_empty());
+}
+
+#[test]
+fn test_override_error_lists_versions() {
+    let other = SourceId::from_url("registry+https://example.com/index").unwrap();
+    let bar = dep_req("bar", "1.0");
+    let replacement = Dependency::parse("bar", Some("=1.0.0"), other).unwrap();
+    let any_version = Dependency::parse("bar", Some("*"), other).unwrap();
+    let at_other = |version: &str| {
+        let pkg_id = PackageId::new("bar", version, other).unwrap();
+        pkg_dep(pkg_id, Vec::new())
+    };
+    let mut registry = ReplayRegistry::new([
+        (bar.clone(), vec![pkg(("bar", "1.0.0"))]),
+        (replacement.clone(), Vec::new()),
+        (any_version, vec![at_other("1.1.0"), at_other("1.2.0")]),
+    ]);
+    let replacements = [(PackageIdSpec::parse("bar@1.0.0").unwrap(), replacement)];
+    let version_prefs = VersionPreferences::default();
+    let mut queryer =
+        RegistryQueryer::new(&mut registry, &replacements, &version_prefs, false, None);
+    queryer.record_queries();
+
+    let Poll::Ready(Err(e)) = queryer.query(&bar, false) else {
+        panic!("expected the override to fail")
+    };
+    let e = e.to_string();
+    assert!(e.contains("versions available: 1.2.0, 1.1.0"), "{}", e);
+    assert!(e.contains("version needed: 1.0.0"), "{}", e);
+    // Looking up the available versions is not a query of the resolve.
+    let recorded: Vec<_> = queryer
+        .recorded_queries()
+        .iter()
+        .map(|(dep, _)| dep.version_req().to_string())
+        .collect();
+    assert_eq!(recorded, ["^1.0", "=1.0.0"]);
+}
+
+#[test]
+fn test_explain() {
+    let bar = dep_req("bar", "1.0");
+    let new_rust = Summary::new(
+        PackageId::new("bar", "1.1.0", registry_loc()).unwrap(),
+        Vec::new(),
+        &BTreeMap::new(),
+        None::<&String>,
+        Some("1.70".parse().unwrap()),
+    )
+    .unwrap();
+    let mut registry =
+        ReplayRegistry::new([(bar.clone(), vec![pkg(("bar", "1.0.0")), new_rust.clone()])]);
+    let version_prefs = VersionPreferences::default();
+    let max = Some("1.60".parse().unwrap());
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, max);
+    queryer.record_queries();
+
+    let Poll::Ready(Ok(explanation)) = queryer.explain(None, &bar) else {
+        panic!("expected an explanation")
+    };
+    assert_eq!(explanation.registry_candidates.len(), 2);
+    assert_eq!(explanation.msrv_filtered, [new_rust.package_id()]);
+    assert_eq!(explanation.candidates.len(), 1);
+    // Only the query the resolver would make is recorded.
+    assert_eq!(queryer.recorded_queries().len(), 1);
+}
+
+#[test]
+fn test_minimal_ordering_counts() {
+    let bar = dep_req("bar", "1.0");
+    let queries = || [(bar.clone(), vec![pkg(("bar", "1.0.0"))])];
+    let version_prefs = VersionPreferences::default();
+
+    let mut registry = ReplayRegistry::new(queries());
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    assert!(queryer.query(&bar, true).is_ready());
+    assert!(queryer.query(&bar, true).is_ready());
+    assert!(queryer.query(&bar, false).is_ready());
+    assert_eq!(
+        queryer.minimal_ordering_counts(),
+        MinimalOrderingCounts {
+            first_minimal_version: 1,
+            minimal_versions: 0,
+        }
+    );
+
+    let mut registry = ReplayRegistry::new(queries());
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, true, None);
+    assert!(queryer.query(&bar, true).is_ready());
+    assert!(queryer.query(&bar, false).is_ready());
+    assert_eq!(
+        queryer.minimal_ordering_counts(),
+        MinimalOrderingCounts {
+            first_minimal_version: 0,
+            minimal_versions: 2,
+        }
+    );
+}
+
+#[test]
+fn test_export_registry_cache() {
+    let mut bar = dep_req("bar", "1.0");
+    bar.set_optional(true).set_features(["x"]);
+    let mut foo = pkg_features("foo", vec![bar], &[("std", &["dep:bar", "bar/y"])]);
+    foo.set_checksum("abc".to_string());
+    foo.set_license(InternedString::new("MIT OR Apache-2.0"));
+    foo.set_edition(Edition::Edition2018);
+    let mut foo_dep = dep_req("foo", "1.0");
+    foo_dep.lock_version(foo.version());
+    let version_prefs = VersionPreferences::default();
+
+    let mut registry = ReplayRegistry::new([(foo_dep.clone(), vec![foo.clone()])]);
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    assert!(queryer.query(&foo_dep, false).is_ready());
+    let exported = queryer.export_registry_cache();
+    let json = serde_json::to_string(&exported).unwrap();
+
+    // The registry knows nothing, everything comes from the import.
+    let mut registry = ReplayRegistry::new([]);
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    queryer
+        .import_registry_cache(serde_json::from_str(&json).unwrap())
+        .unwrap();
+    let Poll::Ready(Ok(candidates)) = queryer.query(&foo_dep, false) else {
+        panic!("expected the imported candidates");
+    };
+    assert_eq!(candidates.len(), 1);
+    let imported = &candidates[0];
+    assert_eq!(imported.package_id(), foo.package_id());
+    assert_eq!(imported.dependencies(), foo.dependencies());
+    assert_eq!(imported.features(), foo.features());
+    assert_eq!(imported.implicit_features(), foo.implicit_features());
+    assert_eq!(imported.checksum(), Some("abc"));
+    assert_eq!(imported.license().as_deref(), Some("MIT OR Apache-2.0"));
+    assert_eq!(imported.edition(), Some(Edition::Edition2018));
+    assert_eq!(queryer.export_registry_cache().len(), 1);
+}
+
+#[test]
+fn test_replacement_report() {
+    let other = SourceId::from_url("registry+https://example.com/index").unwrap();
+    let bar = dep_req("bar", "1.0");
+    let baz = dep_req("baz", "1.0");
+    let bar_replacement = Dependency::parse("bar", Some("=1.0.0"), other).unwrap();
+    let baz_replacement = dep_req("baz", "=1.0.0");
+    let other_bar = pkg_dep(PackageId::new("bar", "1.0.0", other).unwrap(), Vec::new());
+    let mut registry = ReplayRegistry::new([
+        (bar.clone(), vec![pkg(("bar", "1.0.0"))]),
+        (baz.clone(), vec![pkg(("baz", "1.0.0"))]),
+        (bar_replacement.clone(), vec![other_bar.clone()]),
+        (baz_replacement.clone(), vec![pkg(("baz", "1.0.0"))]),
+    ]);
+    let unused = Dependency::parse("qux", Some("=1.0.0"), other).unwrap();
+    let replacements = [
+        (PackageIdSpec::parse("bar@1.0.0").unwrap(), bar_replacement),
+        (PackageIdSpec::parse("baz@1.0.0").unwrap(), baz_replacement),
+        (PackageIdSpec::parse("qux@1.0.0").unwrap(), unused),
+    ];
+    let version_prefs = VersionPreferences::default();
+    let mut queryer =
+        RegistryQueryer::new(&mut registry, &replacements, &version_prefs, false, None);
+    assert!(queryer.query(&bar, false).is_ready());
+    assert!(queryer.query(&baz, false).is_ready());
+
+    let report = queryer.replacement_report();
+    let statuses: Vec<_> = report.outcomes.iter().map(|o| o.status()).collect();
+    assert_eq!(
+        statuses,
+        [
+            ReplacementStatus::Applied,
+            ReplacementStatus::SameSource,
+            ReplacementStatus::Unused
+        ]
+    );
+    let bar_id = pkg(("bar", "1.0.0")).package_id();
+    assert_eq!(
+        report.outcomes[0].applied,
+        [(bar_id, other_bar.package_id())]
+    );
+    let baz_id = pkg(("baz", "1.0.0")).package_id();
+    assert_eq!(report.outcomes[1].same_source, [baz_id]);
+
+    let queried = queryer.queried_overrides();
+    assert_eq!(
+        queried,
+        [(bar_id, &replacements[0].1), (baz_id, &replacements[1].1)]
+    );
+}
+
+#[test]
+fn test_forbid_yanked() {
+    let parent = dep_req("parent", "1.0");
+    let bar_new = dep_req("bar", "=1.1.0");
+    let bar_old = dep_req("bar", "=1.0.0");
+    let root = pkg_features("foo", vec![parent.clone()], &[]);
+    let parent_new = pkg_dep(("parent", "1.1.0"), vec![bar_new.clone()]);
+    let parent_old = pkg_dep(("parent", "1.0.0"), vec![bar_old.clone()]);
+    let yanked = pkg(("bar", "1.1.0"));
+    let mut version_prefs = VersionPreferences::default();
+    version_prefs.mark_yanked(yanked.package_id());
+    let resolve = |parents: Vec<Summary>, forbid_yanked| {
+        let mut registry = ReplayRegistry::new([
+            (parent.clone(), parents),
+            (bar_new.clone(), vec![yanked.clone()]),
+            (bar_old.clone(), vec![pkg(("bar", "1.0.0"))]),
+        ]);
+        let mut root_opts = resolve_opts(&[]);
+        root_opts.forbid_yanked = forbid_yanked;
+        cargo::core::resolver::resolve(
+            &[(root.clone(), root_opts)],
+            &[],
+            &mut registry,
+            &version_prefs,
+            None,
+            false,
+            None,
+            None,
+        )
+    };
+    let both = || vec![parent_new.clone(), parent_old.clone()];
+
+    let resolved = resolve(both(), false).unwrap();
+    assert!(resolved.contains(&yanked.package_id()));
+
+    // The newest `parent` needs the yanked `bar`, so it is backtracked over.
+    let resolved = resolve(both(), true).unwrap();
+    assert!(resolved.contains(&parent_old.package_id()));
+    assert!(!resolved.contains(&yanked.package_id()));
+
+    let e = resolve(vec![parent_new.clone()], true)
+        .unwrap_err()
+        .to_string();
+    assert!(
+        e.contains("the matching versions of `bar` are yanked, which is not allowed"),
+        "{}",
+        e
+    );
+}
+
+#[test]
+fn test_forbid_downgrade() {
+    let bar = dep_req("bar", "<1.2");
+    let root = pkg_features("foo", vec![bar.clone()], &[]);
+    let mut version_prefs = VersionPreferences::default();
+    version_prefs.prefer_package_id(("bar", "1.2.0").to_pkgid());
+    let resolve = |forbid_downgrade| {
+        let mut registry = ReplayRegistry::new([(bar.clone(), vec![pkg(("bar", "1.1.0"))])]);
+        let mut root_opts = resolve_opts(&[]);
+        root_opts.forbid_downgrade = forbid_downgrade;
+        cargo::core::resolver::resolve(
+            &[(root.clone(), root_opts)],
+            &[],
+            &mut registry,
+            &version_prefs,
+            None,
+            false,
+            None,
+            None,
+        )
+    };
+
+    assert!(resolve(false).is_ok());
+    let e = resolve(true).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "`bar` would be downgraded from 1.2.0 to 1.1.0, which is not allowed"
+    );
+
+    // Only older, semver compatible versions are downgrades.
+    let mut registry = ReplayRegistry::new([]);
+    let queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    let incompatible = ("bar", "0.9.0").to_pkgid();
+    let newer = ("bar", "1.3.0").to_pkgid();
+    assert!(queryer.check_not_downgraded([incompatible, newer]).is_ok());
+}
+
+#[test]
+fn test_forbid_ambiguous_sources() {
+    let other = SourceId::from_url("registry+https://example.com/index").unwrap();
+    let bar = dep_req("bar", "1.0");
+    let other_bar = pkg_dep(PackageId::new("bar", "1.0.1", other).unwrap(), Vec::new());
+    let mut registry = ReplayRegistry::new([(bar.clone(), vec![pkg(("bar", "1.0.0")), other_bar])]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    queryer.set_forbid_ambiguous_sources(true);
+
+    let Poll::Ready(Err(e)) = queryer.query(&bar, false) else {
+        panic!("expected the sources to be reported as ambiguous")
+    };
+    let e = e.downcast::<AmbiguousSourceError>().unwrap();
+    assert_eq!(e.name, "bar");
+    let mut sources = vec![registry_loc(), other];
+    sources.sort();
+    assert_eq!(e.sources, sources);
+
+    queryer.set_forbid_ambiguous_sources(false);
+    let Poll::Ready(Ok(candidates)) = queryer.query(&bar, false) else {
+        panic!("expected both candidates")
+    };
+    assert_eq!(candidates.len(), 2);
+}
+
+#[test]
+fn test_allowed_replacement_sources() {
+    let other = SourceId::from_url("registry+https://example.com/index").unwrap();
+    let bar = dep_req("bar", "1.0");
+    let bar_replacement = Dependency::parse("bar", Some("=1.0.0"), other).unwrap();
+    let other_bar = pkg_dep(PackageId::new("bar", "1.0.0", other).unwrap(), Vec::new());
+    let mut registry = ReplayRegistry::new([
+        (bar.clone(), vec![pkg(("bar", "1.0.0"))]),
+        (bar_replacement.clone(), vec![other_bar]),
+    ]);
+    let replacements = [(PackageIdSpec::parse("bar@1.0.0").unwrap(), bar_replacement)];
+    let version_prefs = VersionPreferences::default();
+    let mut queryer =
+        RegistryQueryer::new(&mut registry, &replacements, &version_prefs, false, None);
+
+    queryer.set_allowed_replacement_sources([registry_loc()]);
+    let Poll::Ready(Err(e)) = queryer.query(&bar, false) else {
+        panic!("expected the replacement to be rejected")
+    };
+    assert!(e.to_string().contains("not an allowed source"), "{e}");
+
+    queryer.set_allowed_replacement_sources([other]);
+    assert!(matches!(queryer.query(&bar, true), Poll::Ready(Ok(_))));
+}
+
+#[test]
+fn test_pinned_summaries() {
+    let bar = dep_req("bar", "1.0");
+    let bar2 = dep_req("bar", "2.0");
+    let pinned = pkg(("bar", "1.0.1"));
+    // Nothing is recorded, so any registry query would fail.
+    let mut registry = ReplayRegistry::new([]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    queryer.set_pinned_summaries(HashMap::from([(pinned.package_id(), pinned.clone())]));
+
+    let Poll::Ready(Ok(candidates)) = queryer.query(&bar, false) else {
+        panic!("expected the pinned summary")
+    };
+    assert_eq!(candidates.len(), 1);
+    assert_eq!(candidates[0].package_id(), pinned.package_id());
+
+    let Poll::Ready(Err(e)) = queryer.query(&bar2, false) else {
+        panic!("expected the pin to conflict with the requirement")
+    };
+    assert_eq!(
+        e.to_string(),
+        "`bar` is pinned to version 1.0.1, which does not satisfy the requirement `^2.0`"
+    );
+    // `has_candidate` does not ask the registry either.
+    let bar_1_0_1 = dep_req("bar", "=1.0.1");
+    assert!(matches!(
+        queryer.has_candidate(&bar_1_0_1),
+        Poll::Ready(Ok(true))
+    ));
+    assert!(matches!(queryer.has_candidate(&bar2), Poll::Ready(Err(_))));
+}
+
+#[test]
+fn test_published_before() {
+    let day = Duration::from_secs(24 * 60 * 60);
+    let snapshot = SystemTime::UNIX_EPOCH + 100 * day;
+    let mut old = pkg(("bar", "1.0.0"));
+    old.set_published(snapshot - day);
+    let mut new = pkg(("bar", "1.1.0"));
+    new.set_published(snapshot + day);
+    let unknown = pkg(("bar", "1.2.0"));
+
+    let bar = dep_req("bar", "1.0");
+    let mut registry = ReplayRegistry::new([(bar.clone(), vec![old, new, unknown])]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+
+    let versions = |queryer: &mut RegistryQueryer<'_>| {
+        let Poll::Ready(Ok(candidates)) = queryer.query(&bar, false) else {
+            panic!("expected the replayed candidates")
+        };
+        candidates
+            .iter()
+            .map(|s| s.version().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(versions(&mut queryer), ["1.2.0", "1.1.0", "1.0.0"]);
+    // The candidates cached so far are not reused.
+    queryer.set_published_before(snapshot, false);
+    assert_eq!(versions(&mut queryer), ["1.0.0"]);
+    queryer.set_published_before(snapshot, true);
+    assert_eq!(versions(&mut queryer), ["1.2.0", "1.0.0"]);
+}
+
+#[test]
+fn test_published_before_between_resolves() {
+    let day = Duration::from_secs(24 * 60 * 60);
+    let snapshot = SystemTime::UNIX_EPOCH + 100 * day;
+    let mut old = pkg(("bar", "1.0.0"));
+    old.set_published(snapshot - day);
+    let mut new = pkg(("bar", "1.1.0"));
+    new.set_published(snapshot + day);
+
+    let bar = dep_req("bar", "1.0");
+    let root = pkg_features("foo", vec![bar.clone()], &[]);
+    let mut registry = ReplayRegistry::new([(bar.clone(), vec![old, new])]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+
+    let bar_version = |queryer: &mut RegistryQueryer<'_>| {
+        let resolve = cargo::core::resolver::resolve_with_queryer(
+            &[(root.clone(), resolve_opts(&[]))],
+            queryer,
+            None,
+            false,
+        )
+        .unwrap();
+        let bar = resolve.iter().find(|id| id.name() == "bar").unwrap();
+        bar.version().to_string()
+    };
+
+    assert_eq!(bar_version(&mut queryer), "1.1.0");
+    // The dependencies `foo` was resolved with are worked out again.
+    queryer.set_published_before(snapshot, false);
+    assert_eq!(bar_version(&mut queryer), "1.0.0");
+}
+
+#[test]
+fn test_license_filter() {
+    let bar = dep_req("bar", "1.0");
+    let licensed = |version: &str, license: &str| {
+        let mut s = pkg(("bar", version));
+        s.set_license(InternedString::new(license));
+        s
+    };
+    let mut registry = ReplayRegistry::new([(
+        bar.clone(),
+        vec![
+            licensed("1.0.0", "MIT OR Apache-2.0"),
+            licensed("1.0.1", "GPL-3.0"),
+            pkg(("bar", "1.0.2")),
+        ],
+    )]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    queryer.set_license_filter(|license| !license.contains("GPL"), false);
+
+    let Poll::Ready(Ok(candidates)) = queryer.query(&bar, false) else {
+        panic!("expected candidates")
+    };
+    let versions: Vec<_> = candidates.iter().map(|s| s.version().to_string()).collect();
+    assert_eq!(versions, ["1.0.0"]);
+    let filtered: Vec<_> = queryer.license_filtered()[&bar]
+        .iter()
+        .map(|id| id.version().to_string())
+        .collect();
+    assert_eq!(filtered, ["1.0.1", "1.0.2"]);
+
+    queryer.set_license_filter(|license| !license.contains("GPL"), true);
+    let Poll::Ready(Ok(candidates)) = queryer.query(&bar, false) else {
+        panic!("expected candidates")
+    };
+    assert_eq!(candidates.len(), 2);
+}
+
+#[test]
+fn test_query_latencies() {
+    let bar = dep_req("bar", "1.0");
+    let mut registry = ReplayRegistry::new([(bar.clone(), vec![pkg(("bar", "1.0.0"))])]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    assert!(queryer.query_latencies().is_empty());
+
+    assert!(queryer.query(&bar, false).is_ready());
+    let name = InternedString::new("bar");
+    let first = queryer.query_latencies()[&name];
+
+    // A cache hit does not touch the registry, so it is not timed.
+    assert!(queryer.query(&bar, false).is_ready());
+    assert_eq!(queryer.query_latencies()[&name], first);
+    assert_eq!(queryer.query_latencies().len(), 1);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "`query` for `baz` after `finalize`")]
+fn test_finalize_rejects_new_queries() {
+    let baz = dep_req("baz", "1.0");
+    let mut registry = ReplayRegistry::new([(baz.clone(), vec![pkg(("baz", "1.0.0"))])]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    queryer.finalize();
+    let _ = queryer.query(&baz, false);
+}
+
+#[test]
+fn test_warm_from_lock() {
+    let bar = dep_req("bar", "=1.0.0");
+    let baz = dep_req("baz", "=1.0.0");
+    let mut registry = ReplayRegistry::new([
+        (bar.clone(), vec![pkg(("bar", "1.0.0"))]),
+        (baz.clone(), vec![pkg(("baz", "1.0.0"))]),
+    ]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+
+    // The `baz` entry is stale, it does not match the dependency.
+    let warmed = queryer.warm_from_lock(&[
+        (bar.clone(), vec![pkg(("bar", "1.0.0"))]),
+        (baz.clone(), vec![pkg(("baz", "1.1.0"))]),
+    ]);
+    assert_eq!(warmed, 1);
+
+    let Poll::Ready(Ok(candidates)) = queryer.query(&bar, false) else {
+        panic!("expected the warmed candidates")
+    };
+    assert_eq!(
+        candidates[0].package_id(),
+        pkg(("bar", "1.0.0")).package_id()
+    );
+    assert!(queryer.query_latencies().is_empty());
+    assert_eq!(queryer.candidate_counts(&bar).map(|c| c.survived), Some(1));
+
+    assert!(queryer.query(&baz, false).is_ready());
+    assert!(queryer
+        .query_latencies()
+        .contains_key(&InternedString::new("baz")));
+}
+
+#[test]
+fn test_warm_from_lock_like_query() {
+    let bar = dep_req("bar", "=1.0.0");
+    let baz = dep_req("baz", "=1.0.0");
+    let local = SourceId::for_path(std::path::Path::new("/local/bar")).unwrap();
+    let pinned = pkg(("baz", "1.0.0"));
+    // Nothing is recorded, so any registry query would fail.
+    let mut registry = ReplayRegistry::new([]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    queryer.set_substitution(|s| {
+        (s.name() == "bar").then(|| s.clone().override_id(s.package_id().with_source_id(local)))
+    });
+    queryer.set_pinned_summaries(HashMap::from([(pinned.package_id(), pinned.clone())]));
+
+    // `baz` is pinned, so its entry is not needed.
+    let warmed = queryer.warm_from_lock(&[
+        (bar.clone(), vec![pkg(("bar", "1.0.0"))]),
+        (baz.clone(), vec![pkg(("baz", "1.0.0"))]),
+    ]);
+    assert_eq!(warmed, 1);
+
+    let Poll::Ready(Ok(candidates)) = queryer.query(&bar, false) else {
+        panic!("expected the warmed candidates")
+    };
+    assert_eq!(candidates[0].source_id(), local);
+}
+
+#[test]
+fn test_why_not() {
+    let bar = dep_req("bar", "1.0");
+    let mut gpl = pkg(("bar", "1.0.1"));
+    gpl.set_license(InternedString::new("GPL-3.0"));
+    let too_new = Summary::new(
+        PackageId::new("bar", "1.0.3", registry_loc()).unwrap(),
+        Vec::new(),
+        &BTreeMap::new(),
+        None::<&String>,
+        Some("1.80".parse::<PartialVersion>().unwrap()),
+    )
+    .unwrap();
+    let mut registry = ReplayRegistry::new([(
+        bar.clone(),
+        vec![pkg(("bar", "1.0.0")), gpl, pkg(("bar", "1.0.2")), too_new],
+    )]);
+    let version_prefs = VersionPreferences::default();
+    let max_rust_version = Some("1.70".parse::<PartialVersion>().unwrap());
+    let mut queryer =
+        RegistryQueryer::new(&mut registry, &[], &version_prefs, false, max_rust_version);
+    queryer.set_license_filter(|license| !license.contains("GPL"), true);
+    assert!(queryer.query(&bar, false).is_ready());
+
+    let name = InternedString::new("bar");
+    let why_not = |version: &str| queryer.why_not(name, &version.parse().unwrap());
+    assert_eq!(why_not("1.0.3"), WhyNot::RustVersion(bar.clone()));
+    assert_eq!(why_not("1.0.1"), WhyNot::Filtered(bar.clone()));
+    assert_eq!(
+        why_not("1.0.0"),
+        WhyNot::LostTiebreak {
+            dependency: bar.clone(),
+            ranked_ahead: vec![pkg(("bar", "1.0.2")).package_id()],
+        }
+    );
+    assert_eq!(
+        why_not("1.0.0").to_string(),
+        format!(
+            "it was a candidate for `bar = \"^1.0\"`, but `{}` was preferred",
+            pkg(("bar", "1.0.2")).package_id()
+        )
+    );
+    assert_eq!(why_not("2.0.0"), WhyNot::NotRequested);
+}
+
+#[test]
+fn test_substitution() {
+    let bar = dep_req("bar", "1.0");
+    let local = SourceId::for_path(std::path::Path::new("/local/bar")).unwrap();
+    let mut registry = ReplayRegistry::new([(
+        bar.clone(),
+        vec![pkg(("bar", "1.0.0")), pkg(("bar", "1.0.1"))],
+    )]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    queryer.set_substitution(|s| {
+        (s.version().patch == 1)
+            .then(|| s.clone().override_id(s.package_id().with_source_id(local)))
+    });
+
+    let Poll::Ready(Ok(candidates)) = queryer.query(&bar, false) else {
+        panic!("expected candidates")
+    };
+    let sources: Vec<_> = candidates
+        .iter()
+        .map(|s| (s.version().to_string(), s.source_id() == local))
+        .collect();
+    assert_eq!(
+        sources,
+        [("1.0.1".to_string(), true), ("1.0.0".to_string(), false)]
+    );
+
+    queryer.set_substitution(|s| Some(pkg(("baz", s.version().to_string()))));
+    let (registry_time, _) = queryer.timing_breakdown();
+    assert!(matches!(queryer.has_candidate(&bar), Poll::Ready(Err(_))));
+    assert_eq!(queryer.timing_breakdown().0, registry_time);
+    let Poll::Ready(Err(err)) = queryer.query(&bar, false) else {
+        panic!("expected an error")
+    };
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "the substitution for `{}` is `{}`, \
+             which does not have the same name and version",
+            pkg(("bar", "1.0.0")).package_id(),
+            pkg(("baz", "1.0.0")).package_id()
+        )
+    );
+}
+
+#[test]
+fn test_prerelease_friendly() {
+    let bar = dep_req("bar", "1.0");
+    let mut any_version = bar.clone();
+    any_version.set_any_version();
+    let all = || {
+        vec![
+            pkg(("bar", "1.0.0")),
+            pkg(("bar", "1.1.0-alpha")),
+            pkg(("bar", "2.0.0-alpha")),
+        ]
+    };
+    let mut registry = ReplayRegistry::new([
+        (bar.clone(), vec![pkg(("bar", "1.0.0"))]),
+        (any_version, all()),
+    ]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+
+    let versions = |queryer: &mut RegistryQueryer<'_>| {
+        let Poll::Ready(Ok(candidates)) = queryer.query(&bar, false) else {
+            panic!("expected candidates")
+        };
+        candidates
+            .iter()
+            .map(|s| s.version().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(versions(&mut queryer), ["1.0.0"]);
+    queryer.set_prerelease_friendly(true);
+    assert_eq!(versions(&mut queryer), ["1.1.0-alpha", "1.0.0"]);
+    assert!(matches!(queryer.has_candidate(&bar), Poll::Ready(Ok(true))));
+}
+
+#[test]
+fn test_resolve_direct_deps() {
+    let bar = dep_req("bar", "1.0");
+    let baz = dep_req("baz", "1.0");
+    let qux = dep_req("qux", "1.0");
+    // `qux` is not recorded, so the test fails if `bar` gets activated.
+    let bar_1_1 = pkg_dep(("bar", "1.1.0"), vec![qux]);
+    let mut registry = ReplayRegistry::new([
+        (bar.clone(), vec![pkg(("bar", "1.0.0")), bar_1_1.clone()]),
+        (baz.clone(), vec![pkg(("baz", "1.0.0"))]),
+    ]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+
+    // `foo` is not activated, so neither its limit nor the trace apply.
+    queryer.record_trace(10);
+    queryer.set_version_limit(InternedString::new("foo"), 0);
+
+    let root = pkg_features("foo", vec![bar.clone(), baz.clone()], &[]);
+    let Poll::Ready(Ok(mut picked)) = queryer.resolve_direct_deps(&root, &resolve_opts(&[])) else {
+        panic!("expected the direct dependencies to resolve")
+    };
+    picked.sort_by_key(|(dep, _)| dep.package_name());
+    assert_eq!(picked, [(bar, bar_1_1), (baz, pkg(("baz", "1.0.0")))]);
+    assert!(queryer.trace().unwrap().nodes.is_empty());
+
+    let missing = dep_req("missing", "1.0");
+    let mut registry = ReplayRegistry::new([(missing.clone(), Vec::new())]);
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    let root = pkg_features("foo", vec![missing], &[]);
+    let Poll::Ready(Err(e)) = queryer.resolve_direct_deps(&root, &resolve_opts(&[])) else {
+        panic!("expected a missing dependency to fail")
+    };
+    assert!(
+        e.to_string()
+            .starts_with("no matching package named `missing` found"),
+        "{}",
+        e
+    );
+}
+
+#[test]
+fn test_replacement_observer() {
+    let other = SourceId::from_url("registry+https://example.com/index").unwrap();
+    let bar = dep_req("bar", "1.0");
+    let replacement = Dependency::parse("bar", Some("=1.0.0"), other).unwrap();
+    let other_bar = pkg_dep(PackageId::new("bar", "1.0.0", other).unwrap(), Vec::new());
+    let mut registry = ReplayRegistry::new([
+        (bar.clone(), vec![pkg(("bar", "1.0.0"))]),
+        (replacement.clone(), vec![other_bar.clone()]),
+    ]);
+    let replacements = [(PackageIdSpec::parse("bar@1.0.0").unwrap(), replacement)];
+    let version_prefs = VersionPreferences::default();
+    let mut queryer =
+        RegistryQueryer::new(&mut registry, &replacements, &version_prefs, false, None);
+    let seen = Rc::new(std::cell::RefCell::new(Vec::new()));
+    let observer_seen = seen.clone();
+    queryer.set_replacement_observer(Some(Rc::new(move |id: &PackageId, s: &Summary| {
+        observer_seen.borrow_mut().push((*id, s.package_id()))
+    })));
+
+    // Both orderings go through the override, but it is one replacement.
+    assert!(queryer.query(&bar, false).is_ready());
+    assert!(queryer.query(&bar, true).is_ready());
+    assert_eq!(
+        *seen.borrow(),
+        [(pkg(("bar", "1.0.0")).package_id(), other_bar.package_id())]
+    );
+}
+
+#[test]
+fn test_resolve_with_queryer() {
+    let a = dep_req("a", "1.0");
+    let b = dep_req("b", "1.0");
+    let a_dep = dep_req("a-dep", "1.0");
+    let b_dep = dep_req("b-dep", "1.0");
+    let root = pkg_features("foo", vec![a.clone(), b.clone()], &[]);
+    let version_prefs = VersionPreferences::default();
+    // The order `a-dep` and `b-dep` are queried in, with `first` resolved
+    // before the other dependency of `foo`.
+    let queried = |first: &str| {
+        let mut registry = ReplayRegistry::new([
+            (
+                a.clone(),
+                vec![pkg_dep(("a", "1.0.0"), vec![a_dep.clone()])],
+            ),
+            (
+                b.clone(),
+                vec![pkg_dep(("b", "1.0.0"), vec![b_dep.clone()])],
+            ),
+            (a_dep.clone(), vec![pkg(("a-dep", "1.0.0"))]),
+            (b_dep.clone(), vec![pkg(("b-dep", "1.0.0"))]),
+        ]);
+        let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+        let first = InternedString::new(first);
+        queryer.set_dep_priority(move |dep| (dep.package_name() == first) as i32);
+        queryer.record_queries();
+        let resolve = cargo::core::resolver::resolve_with_queryer(
+            &[(root.clone(), resolve_opts(&[]))],
+            &mut queryer,
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(resolve.iter().count(), 5);
+        queryer
+            .recorded_queries()
+            .iter()
+            .map(|(dep, _)| dep.package_name().to_string())
+            .filter(|name| name.ends_with("-dep"))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(queried("a"), ["a-dep", "b-dep"]);
+    assert_eq!(queried("b"), ["b-dep", "a-dep"]);
+}
+
+#[test]
+fn test_resolution_trace() {
+    let mut bar = dep_req("bar", "1.0");
+    bar.set_features(["x"]);
+    let root = pkg_features("foo", vec![bar.clone()], &[("a", &[])]);
+    // Only the older `bar` has the feature asked for.
+    let mut registry = ReplayRegistry::new([(
+        bar,
+        vec![
+            pkg_features(("bar", "1.0.0"), vec![], &[("x", &[])]),
+            pkg(("bar", "1.1.0")),
+        ],
+    )]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    let resolve = |queryer: &mut RegistryQueryer<'_>| {
+        cargo::core::resolver::resolve_with_queryer(
+            &[(root.clone(), resolve_opts(&["a"]))],
+            queryer,
+            None,
+            false,
+        )
+        .unwrap();
+    };
+
+    resolve(&mut queryer);
+    assert!(queryer.trace().is_none());
+
+    queryer.record_trace(3);
+    resolve(&mut queryer);
+    let trace = queryer.trace().unwrap();
+    assert_eq!(trace.nodes.len(), 3);
+    assert!(!trace.truncated);
+    assert_eq!(trace.nodes[0].parent, None);
+    assert_eq!(trace.nodes[0].features, ["a"]);
+    assert!(matches!(
+        trace.nodes[0].outcome,
+        TraceOutcome::Activated { dependencies: 1 }
+    ));
+    assert_eq!(trace.nodes[1].parent, Some(0));
+    assert_eq!(trace.nodes[1].package_id, ("bar", "1.1.0").to_pkgid());
+    assert!(matches!(
+        trace.nodes[1].outcome,
+        TraceOutcome::Backtracked { .. }
+    ));
+    assert_eq!(trace.nodes[2].parent, Some(0));
+    assert_eq!(trace.nodes[2].package_id, ("bar", "1.0.0").to_pkgid());
+
+    resolve(&mut queryer);
+    let trace = queryer.trace().unwrap();
+    assert_eq!(trace.nodes.len(), 3);
+    assert!(trace.truncated);
+
+    let json = serde_json::to_value(trace).unwrap();
+    assert_eq!(json["nodes"][1]["outcome"]["kind"], "backtracked");
+    assert_eq!(json["truncated"], true);
+}
+
+#[test]
+fn test_version_limit() {
+    // The newest `app` wants another major of `syn` than the root.
+    let syn1 = pkg(("syn", "1.0.0"));
+    let syn2 = pkg(("syn", "2.0.0"));
+    let app_new = pkg_dep(("app", "1.1.0"), vec![dep_req("syn", "1")]);
+    let app_old = pkg_dep(("app", "1.0.0"), vec![dep_req("syn", "2")]);
+    let root = pkg_dep("root", vec![dep_req("app", "1"), dep_req("syn", "2")]);
+    let version_prefs = VersionPreferences::default();
+    let syn = InternedString::new("syn");
+    let resolve = |limit: Option<usize>| {
+        let mut registry = ReplayRegistry::new([
+            (dep_req("app", "1"), vec![app_new.clone(), app_old.clone()]),
+            (dep_req("syn", "1"), vec![syn1.clone()]),
+            (dep_req("syn", "2"), vec![syn2.clone()]),
+        ]);
+        let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+        if let Some(max) = limit {
+            queryer.set_version_limit(syn, max);
+        }
+        let resolve = cargo::core::resolver::resolve_with_queryer(
+            &[(root.clone(), resolve_opts(&[]))],
+            &mut queryer,
+            None,
+            false,
+        )?;
+        let mut ids: Vec<_> = resolve.iter().collect();
+        ids.sort();
+        let activated = queryer.activated_versions(syn).cloned();
+        CargoResult::Ok((ids, activated))
+    };
+
+    let (ids, activated) = resolve(None).unwrap();
+    assert_eq!(
+        ids,
+        [
+            app_new.package_id(),
+            root.package_id(),
+            syn1.package_id(),
+            syn2.package_id()
+        ]
+    );
+    assert_eq!(activated, None);
+
+    // With a single `syn` allowed, `app` is backtracked to the version that
+    // unifies with the root.
+    let (ids, activated) = resolve(Some(1)).unwrap();
+    assert_eq!(
+        ids,
+        [app_old.package_id(), root.package_id(), syn2.package_id()]
+    );
+    assert_eq!(activated, Some([syn2.version().clone()].into()));
+
+    // With none allowed, there is nothing to backtrack to.
+    assert!(resolve(Some(0)).is_err());
+}
+
+#[test]
+fn test_cancel_resolve() {
+    let bar = dep_req("bar", "1.0");
+    let root = pkg_features("foo", vec![bar.clone()], &[]);
+    let version_prefs = VersionPreferences::default();
+    let resolve = |cancelled| {
+        let mut registry = ReplayRegistry::new([(bar.clone(), vec![pkg(("bar", "1.0.0"))])]);
+        cargo::core::resolver::resolve(
+            &[(root.clone(), resolve_opts(&[]))],
+            &[],
+            &mut registry,
+            &version_prefs,
+            None,
+            false,
+            None,
+            Some(Arc::new(AtomicBool::new(cancelled))),
+        )
+    };
+
+    assert!(resolve(false).is_ok());
+    let e = resolve(true).unwrap_err();
+    assert!(e.is::<ResolveCancelled>(), "{}", e);
+}
+
+#[test]
+fn test_merged_registry_polls_every_registry() {
+    let bar = dep_req("bar", "1.0");
+    // The first registry knows nothing about `bar`, so it fails.
+    let first = ReplayRegistry::new([]);
+    let second = ReplayRegistry::new([(bar.clone(), vec![pkg(("bar", "1.0.0"))])]);
+    let mut registry = MergedRegistry::new(vec![Box::new(first), Box::new(second)]);
+
+    let mut found = Vec::new();
+    let ready = registry.query(&bar, QueryKind::Exact, &mut |s| found.push(s.package_id()));
+    assert!(matches!(ready, Poll::Ready(Err(_))));
+    assert_eq!(found, [("bar", "1.0.0").to_pkgid()]);
+}
+
+/// Returns `Pending` for the first `pending` queries, then defers to `inner`.
+struct SlowRegistry {
+    inner: ReplayRegistry,
+    pending: usize,
+}
+
+impl Registry for SlowRegistry {
+    fn query(
+        &mut self,
+        dep: &Dependency,
+        kind: QueryKind,
+        f: &mut dyn FnMut(Summary),
+    ) -> Poll<CargoResult<()>> {
+        if self.pending > 0 {
+            self.pending -= 1;
+            return Poll::Pending;
+        }
+        self.inner.query(dep, kind, f)
+    }
+
+    fn describe_source(&self, source: SourceId) -> String {
+        self.inner.describe_source(source)
+    }
+
+    fn is_replaced(&self, source: SourceId) -> bool {
+        self.inner.is_replaced(source)
+    }
+
+    fn block_until_ready(&mut self) -> CargoResult<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_resolve_after_pending() {
+    let bar = dep_req("bar", "1.0");
+    let root = pkg_features("foo", vec![bar.clone()], &[]);
+    let mut registry = SlowRegistry {
+        inner: ReplayRegistry::new([(bar, vec![pkg(("bar", "1.0.0"))])]),
+        pending: 1,
+    };
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    let progress = Rc::new(RefCell::new(Vec::new()));
+    let reported = Rc::clone(&progress);
+    queryer.set_build_deps_progress(move |pkg_id, ready, pending| {
+        let pending: Vec<_> = pending
+            .iter()
+            .map(|dep| dep.package_name().to_string())
+            .collect();
+        reported
+            .borrow_mut()
+            .push((pkg_id.name().to_string(), ready.len(), pending))
+    });
+
+    let resolve = cargo::core::resolver::resolve_with_queryer(
+        &[(root, resolve_opts(&[]))],
+        &mut queryer,
+        None,
+        false,
+    )
+    .unwrap();
+    assert_eq!(resolve.iter().count(), 2);
+    // The incomplete result for `foo` is not served from the cache, so the
+    // second pass picks up `bar` once it is ready.
+    assert_eq!(
+        *progress.borrow(),
+        [
+            ("foo".to_string(), 0, vec!["bar".to_string()]),
+            ("foo".to_string(), 1, vec![]),
+            ("bar".to_string(), 0, vec![]),
+        ]
+    );
+    assert!(queryer.pending_deps().is_empty());
+}
+
+#[test]
+fn test_build_deps_reports_kind() {
+    let bar = dep_req("bar", "1.0");
+    let mut baz = dep_req("baz", "1.0");
+    baz.set_kind(DepKind::Build);
+    let root = pkg_features("foo", vec![bar.clone(), baz.clone()], &[]);
+    let mut registry = ReplayRegistry::new([
+        (bar, vec![pkg(("bar", "1.0.0"))]),
+        (baz, vec![pkg(("baz", "1.0.0"))]),
+    ]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    let kinds = Rc::new(RefCell::new(Vec::new()));
+    let reported = Rc::clone(&kinds);
+    queryer.set_build_deps_progress(move |pkg_id, ready, _| {
+        if pkg_id.name() == "foo" {
+            reported.borrow_mut().extend(
+                ready
+                    .iter()
+                    .map(|(dep, _, _, kind)| (dep.package_name().to_string(), *kind)),
+            );
+        }
+    });
+
+    cargo::core::resolver::resolve_with_queryer(
+        &[(root, resolve_opts(&[]))],
+        &mut queryer,
+        None,
+        false,
+    )
+    .unwrap();
+    let mut kinds = kinds.take();
+    kinds.sort();
+    assert_eq!(
+        kinds,
+        [
+            ("bar".to_string(), DepKind::Normal),
+            ("baz".to_string(), DepKind::Build)
+        ]
+    );
+}
+
+#[test]
+fn test_cancel_caches_nothing() {
+    let bar = dep_req("bar", "1.0");
+    let root = pkg_features("foo", vec![bar.clone()], &[]);
+    let mut registry = ReplayRegistry::new([(bar.clone(), vec![pkg(("bar", "1.0.0"))])]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    let cancel = Arc::new(AtomicBool::new(false));
+    queryer.set_cancel(Arc::clone(&cancel));
+    let resolve = |queryer: &mut RegistryQueryer<'_>| {
+        cargo::core::resolver::resolve_with_queryer(
+            &[(root.clone(), resolve_opts(&[]))],
+            queryer,
+            None,
+            false,
+        )
+    };
+
+    cancel.store(true, Ordering::Relaxed);
+    let Poll::Ready(Err(e)) = queryer.query(&bar, false) else {
+        panic!("expected the query to be cancelled");
+    };
+    assert!(e.is::<ResolveCancelled>(), "{}", e);
+    let e = resolve(&mut queryer).unwrap_err();
+    assert!(e.is::<ResolveCancelled>(), "{}", e);
+
+    // Nothing was cached by the cancelled calls.
+    cancel.store(false, Ordering::Relaxed);
+    assert_eq!(resolve(&mut queryer).unwrap().iter().count(), 2);
+}
+
+#[test]
+fn test_prefer_smaller_graph() {
+    let bar = dep_req("bar", "1.0");
+    let baz = dep_req("baz", "1.0");
+    let root = pkg_features("foo", vec![bar.clone()], &[]);
+    let candidates = vec![
+        pkg(("bar", "1.0.0")),
+        pkg_dep(("bar", "1.1.0"), vec![baz.clone()]),
+        pkg(("bar", "1.2.0")),
+    ];
+    // The order the candidates for `bar` are tried in.
+    let versions = |version_prefs: &VersionPreferences, prefer_smaller_graph| {
+        let mut registry = ReplayRegistry::new([
+            (bar.clone(), candidates.clone()),
+            (baz.clone(), vec![pkg(("baz", "1.0.0"))]),
+        ]);
+        let mut queryer = RegistryQueryer::new(&mut registry, &[], version_prefs, false, None);
+        queryer.set_prefer_smaller_graph(prefer_smaller_graph);
+        let ranked = Rc::new(RefCell::new(Vec::new()));
+        let reported = Rc::clone(&ranked);
+        queryer.set_build_deps_progress(move |pkg_id, ready, _| {
+            if pkg_id.name() == "foo" {
+                *reported.borrow_mut() =
+                    ready[0].1.iter().map(|s| s.version().to_string()).collect();
+            }
+        });
+        cargo::core::resolver::resolve_with_queryer(
+            &[(root.clone(), resolve_opts(&[]))],
+            &mut queryer,
+            None,
+            false,
+        )
+        .unwrap();
+        ranked.take()
+    };
+
+    let version_prefs = VersionPreferences::default();
+    assert_eq!(versions(&version_prefs, false), ["1.2.0", "1.1.0", "1.0.0"]);
+    assert_eq!(versions(&version_prefs, true), ["1.2.0", "1.0.0", "1.1.0"]);
+
+    // Preferences still come first.
+    let mut version_prefs = VersionPreferences::default();
+    version_prefs.prefer_package_id(("bar", "1.1.0").to_pkgid());
+    assert_eq!(versions(&version_prefs, true), ["1.1.0", "1.2.0", "1.0.0"]);
+}
+
+#[test]
+fn test_slow_resolve_warning() {
+    let bar = dep_req("bar", "1.0");
+    let baz = dep_req("baz", "1.0");
+    let root = pkg_features("foo", vec![bar.clone(), baz.clone()], &[]);
+    let mut registry = ReplayRegistry::new([
+        (bar, vec![pkg(("bar", "1.0.0")), pkg(("bar", "1.0.1"))]),
+        (baz, vec![pkg(("baz", "1.0.0"))]),
+    ]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    let warnings = Rc::new(RefCell::new(Vec::new()));
+    let reported = Rc::clone(&warnings);
+    queryer.set_slow_resolve_warning(2, move |warning| {
+        reported.borrow_mut().push(warning.to_string())
+    });
+
+    // `foo`, `bar` and `baz` are activated, but the warning is only given once.
+    let resolve = cargo::core::resolver::resolve_with_queryer(
+        &[(root, resolve_opts(&[]))],
+        &mut queryer,
+        None,
+        false,
+    )
+    .unwrap();
+    assert_eq!(resolve.iter().count(), 3);
+    assert_eq!(
+        *warnings.borrow(),
+        [
+            "resolution is taking unusually long (2 activations so far); \
+             the likely culprit is `bar` with 2 candidates"
+        ]
+    );
+}
+
+#[test]
+fn test_has_candidate() {
+    let bar = dep_req("bar", "1.0");
+    let baz = dep_req("baz", "1.0");
+    let mut registry = ReplayRegistry::new([
+        (bar.clone(), vec![pkg(("bar", "1.0.0"))]),
+        (baz.clone(), vec![]),
+    ]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+
+    assert!(matches!(queryer.has_candidate(&bar), Poll::Ready(Ok(true))));
+    assert!(matches!(
+        queryer.has_candidate(&baz),
+        Poll::Ready(Ok(false))
+    ));
+    assert!(queryer.export_registry_cache().is_empty());
+
+    assert!(queryer.query(&bar, false).is_ready());
+    assert!(matches!(queryer.has_candidate(&bar), Poll::Ready(Ok(true))));
+}
+
+#[test]
+fn test_peek_candidates() {
+    let snapshot = SystemTime::UNIX_EPOCH + Duration::from_secs(24 * 60 * 60);
+    let old = pkg(("bar", "1.0.0"));
+    let mut new = pkg(("bar", "1.1.0"));
+    new.set_published(snapshot + Duration::from_secs(1));
+    let mut gpl = pkg(("bar", "1.2.0"));
+    gpl.set_license(InternedString::new("GPL-3.0"));
+    let bar = dep_req("bar", "1.0");
+    let mut registry = ReplayRegistry::new([(bar.clone(), vec![old, new, gpl])]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, true, None);
+    queryer.record_queries();
+    queryer.set_license_filter(|license| !license.contains("GPL"), true);
+    queryer.set_published_before(snapshot, true);
+    let name = InternedString::new("bar");
+    let newer = "1.1.0".parse().unwrap();
+
+    let Poll::Ready(Ok(peeked)) = queryer.peek_candidates(&bar, false) else {
+        panic!("expected the replayed candidates")
+    };
+    assert_eq!(peeked.len(), 1);
+    // Neither the caches nor the reports saw the peek.
+    assert!(queryer.export_registry_cache().is_empty());
+    assert!(queryer.candidate_counts(&bar).is_none());
+    assert!(queryer.license_filtered().is_empty());
+    assert_eq!(queryer.why_not(name, &newer), WhyNot::NotRequested);
+    assert!(queryer.recorded_queries().is_empty());
+    assert!(queryer.query_latencies().is_empty());
+    assert_eq!(queryer.timing_breakdown().0, Duration::ZERO);
+    assert_eq!(
+        queryer.minimal_ordering_counts(),
+        MinimalOrderingCounts::default()
+    );
+
+    let Poll::Ready(Ok(candidates)) = queryer.query(&bar, false) else {
+        panic!("expected the replayed candidates")
+    };
+    assert_eq!(peeked, *candidates);
+    assert_eq!(queryer.license_filtered().len(), 1);
+    assert_eq!(queryer.why_not(name, &newer), WhyNot::Filtered(bar.clone()));
+    assert_eq!(queryer.recorded_queries().len(), 1);
+    assert_ne!(
+        queryer.minimal_ordering_counts(),
+        MinimalOrderingCounts::default()
+    );
+}
+
+#[test]
+fn test_invalidate_subtree() {
+    let bar = dep_req("bar", "1.0");
+    let baz = dep_req("baz", "1.0");
+    let root = pkg_features("foo", vec![bar.clone()], &[]);
+    let other = pkg(("other", "1.0.0"));
+    let mut registry = ReplayRegistry::new([
+        (bar.clone(), vec![pkg(("bar", "1.0.0"))]),
+        (baz.clone(), vec![pkg(("baz", "1.0.0"))]),
+    ]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    let computed = Rc::new(RefCell::new(Vec::new()));
+    let reported = Rc::clone(&computed);
+    queryer.set_build_deps_progress(move |pkg_id, _, _| {
+        reported.borrow_mut().push(pkg_id.name().to_string())
+    });
+    let resolve = |queryer: &mut RegistryQueryer<'_>, root: &Summary| {
+        cargo::core::resolver::resolve_with_queryer(
+            &[(root.clone(), resolve_opts(&[]))],
+            queryer,
+            None,
+            false,
+        )
+        .unwrap();
+    };
+
+    resolve(&mut queryer, &root);
+    resolve(&mut queryer, &other);
+    assert!(queryer.query(&baz, false).is_ready());
+    assert_eq!(*computed.borrow(), ["foo", "bar", "other"]);
+
+    queryer.invalidate_subtree(root.package_id());
+    queryer.record_queries();
+    computed.borrow_mut().clear();
+    resolve(&mut queryer, &root);
+    resolve(&mut queryer, &other);
+    assert!(queryer.query(&baz, false).is_ready());
+    // Only `foo` and what it depends on are looked up again.
+    assert_eq!(*computed.borrow(), ["foo", "bar"]);
+    let queried: Vec<_> = queryer
+        .recorded_queries()
+        .iter()
+        .map(|(dep, _)| dep.package_name().to_string())
+        .collect();
+    assert_eq!(queried, ["bar"]);
+}
+
+#[test]
+fn test_finalize() {
+    let bar = dep_req("bar", "1.0");
+    let root = pkg_features("foo", vec![bar.clone()], &[]);
+    let mut registry = ReplayRegistry::new([(bar.clone(), vec![pkg(("bar", "1.0.0"))])]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    let computed = Rc::new(RefCell::new(0));
+    let reported = Rc::clone(&computed);
+    queryer.set_build_deps_progress(move |_, _, _| *reported.borrow_mut() += 1);
+    let resolve = |queryer: &mut RegistryQueryer<'_>| {
+        cargo::core::resolver::resolve_with_queryer(
+            &[(root.clone(), resolve_opts(&[]))],
+            queryer,
+            None,
+            false,
+        )
+        .unwrap()
+    };
+
+    assert_eq!(resolve(&mut queryer).iter().count(), 2);
+    assert_eq!(*computed.borrow(), 2);
+    queryer.finalize();
+    queryer.record_queries();
+
+    // Cached answers are still served.
+    assert_eq!(resolve(&mut queryer).iter().count(), 2);
+    assert_eq!(*computed.borrow(), 2);
+    assert!(queryer.query(&bar, false).is_ready());
+    assert!(queryer.recorded_queries().is_empty());
+}
+
+#[test]
+fn test_equivalent_opts_share_cache_entries() {
+    let root = pkg_features("foo", vec![], &[("small", &[])]);
+    let mut registry = ReplayRegistry::new([]);
+    let version_prefs = VersionPreferences::default();
+    let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+    let computed = Rc::new(RefCell::new(0));
+    let reported = Rc::clone(&computed);
+    queryer.set_build_deps_progress(move |_, _, _| *reported.borrow_mut() += 1);
+    let cfg = |s: &str| -> Cfg { s.parse().unwrap() };
+
+    let mut a = resolve_opts(&[]);
+    a.set_target_cfg(Some(vec![cfg("unix"), cfg("target_os = \"linux\"")]));
+    let mut b = resolve_opts(&[]);
+    b.set_target_cfg(Some(vec![
+        cfg("target_os = \"linux\""),
+        cfg("unix"),
+        cfg("unix"),
+    ]));
+    b.set_extra_features(InternedString::new("bar"), ExtraFeatures::default());
+    b.set_conflict_policy(
+        ConflictKind::RequiredDependencyAsFeature,
+        ConflictPolicy::Warn,
+    );
+    b.set_conflict_policy(
+        ConflictKind::RequiredDependencyAsFeature,
+        ConflictPolicy::Error,
+    );
+    assert_eq!(a, b);
+
+    for opts in [a, b] {
+        cargo::core::resolver::resolve_with_queryer(
+            &[(root.clone(), opts)],
+            &mut queryer,
+            None,
+            false,
+        )
+        .unwrap();
+    }
+    assert_eq!(*computed.borrow(), 1);
+}
+
+#[test]
+fn test_merged_registry() {
+    let other = SourceId::from_url("registry+https://example.com/index").unwrap();
+    let bar = dep_req("bar", "1.0");
+    let other_bar = pkg_dep(PackageId::new("bar", "1.0.1", other).unwrap(), Vec::new());
+    let first = ReplayRegistry::new([(bar.clone(), vec![pkg(("bar", "1.0.0"))])]);
+    let second = SlowRegistry {
+        inner: ReplayRegistry::new([(bar.clone(), vec![pkg(("bar", "1.0.0")), other_bar])]),
+        pending: 1,
+    };
+    let mut registry = MergedRegistry::new(vec![Box::new(first), Box::new(second)]);
+
+    assert!(registry.query_vec(&bar, QueryKind::Exact).is_pending());
+    let Poll::Ready(Ok(summaries)) = registry.query_vec(&bar, QueryKind::Exact) else {
+        panic!("expected both registries to be ready")
+    };
+    let mut ids: Vec<_> = summaries
+        .iter()
+        .map(|s| (s.version().to_string(), s.source_id()))
+        .collect();
+    ids.sort();
+    assert_eq!(
+        ids,
+        [
+            ("1.0.0".to_string(), registry_loc()),
+            ("1.0.1".to_string(), other)
+        ]
+    );
+}
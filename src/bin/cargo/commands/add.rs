@@ -297,6 +297,9 @@ fn parse_dependencies(config: &Config, matches: &ArgMatches) -> CargoResult<Vec<
             FeatureValue::Dep { .. } => {
                 anyhow::bail!("feature `{feature}` is not allowed to use explicit `dep:` syntax",)
             }
+            FeatureValue::TargetFeature { .. } => {
+                anyhow::bail!("feature `{feature}` is not allowed to use `cfg(...):` syntax")
+            }
             FeatureValue::DepFeature {
                 dep_name,
                 dep_feature,
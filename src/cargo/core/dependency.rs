@@ -1,7 +1,7 @@
 use cargo_platform::Platform;
 use semver::VersionReq;
 use serde::ser;
-use serde::Serialize;
+use serde::{de, Deserialize, Serialize};
 use std::borrow::Cow;
 use std::fmt;
 use std::path::PathBuf;
@@ -129,6 +129,23 @@ impl ser::Serialize for DepKind {
     }
 }
 
+impl<'de> de::Deserialize<'de> for DepKind {
+    fn deserialize<D>(d: D) -> Result<DepKind, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        match Option::<String>::deserialize(d)?.as_deref() {
+            None => Ok(DepKind::Normal),
+            Some("dev") => Ok(DepKind::Development),
+            Some("build") => Ok(DepKind::Build),
+            Some(kind) => Err(de::Error::custom(format_args!(
+                "unknown dependency kind `{}`",
+                kind
+            ))),
+        }
+    }
+}
+
 impl Dependency {
     /// Attempt to create a `Dependency` from an entry in the manifest.
     pub fn parse(
@@ -323,6 +340,13 @@ impl Dependency {
         self
     }
 
+    /// Lets the dependency match every version, unlike a `*` requirement which
+    /// does not match pre-releases.
+    pub fn set_any_version(&mut self) -> &mut Dependency {
+        Rc::make_mut(&mut self.inner).req = OptVersionReq::Any;
+        self
+    }
+
     pub fn set_platform(&mut self, platform: Option<Platform>) -> &mut Dependency {
         Rc::make_mut(&mut self.inner).platform = platform;
         self
@@ -483,6 +483,9 @@ features! {
 
     // Allow specifying rustflags directly in a profile
     (stable, workspace_inheritance, "1.64", "reference/unstable.html#workspace-inheritance"),
+
+    // Allow feature values that only apply on some targets, like `cfg(windows):dep/feat`
+    (unstable, cfg_features, "", "reference/unstable.html#cfg-features"),
 }
 
 pub struct Feature {
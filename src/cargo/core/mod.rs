@@ -5,11 +5,11 @@ pub use self::manifest::{Manifest, Target, TargetKind};
 pub use self::package::{Package, PackageSet};
 pub use self::package_id::PackageId;
 pub use self::package_id_spec::PackageIdSpec;
-pub use self::registry::Registry;
+pub use self::registry::{MergedRegistry, Registry, RegistryCapabilities};
 pub use self::resolver::{Resolve, ResolveVersion};
 pub use self::shell::{Shell, Verbosity};
 pub use self::source::{GitReference, QueryKind, Source, SourceId, SourceMap};
-pub use self::summary::{FeatureMap, FeatureValue, Summary};
+pub use self::summary::{FeatureEdge, FeatureMap, FeatureValue, Summary};
 pub use self::workspace::{
     find_workspace_root, resolve_relative_path, MaybePackage, Workspace, WorkspaceConfig,
     WorkspaceRootConfig,
@@ -33,6 +33,101 @@ pub trait Registry {
 
     /// Block until all outstanding Poll::Pending requests are Poll::Ready.
     fn block_until_ready(&mut self) -> CargoResult<()>;
+
+    /// What this registry supports beyond single queries, nothing by default.
+    fn capabilities(&self) -> RegistryCapabilities {
+        RegistryCapabilities::default()
+    }
+}
+
+/// What a [`Registry`] supports beyond answering one query at a time.
+///
+/// The default is a basic registry.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RegistryCapabilities {
+    /// Queries that return `Poll::Pending` are fetched together by
+    /// `block_until_ready`, so asking for many dependencies up front costs
+    /// about as much as asking for one.
+    pub batch_queries: bool,
+}
+
+/// A [`Registry`] answering queries with the summaries of several others,
+/// each package id only reported once, by the first registry that has it.
+///
+/// A query is pending as long as any of the registries is pending on it, but
+/// all of them are still asked so that they can fetch in the meantime. The
+/// same goes for a registry failing: the error of the first one that failed
+/// is returned once all of them were asked.
+pub struct MergedRegistry<'a> {
+    registries: Vec<Box<dyn Registry + 'a>>,
+}
+
+impl<'a> MergedRegistry<'a> {
+    pub fn new(registries: Vec<Box<dyn Registry + 'a>>) -> MergedRegistry<'a> {
+        MergedRegistry { registries }
+    }
+}
+
+impl<'a> Registry for MergedRegistry<'a> {
+    fn query(
+        &mut self,
+        dep: &Dependency,
+        kind: QueryKind,
+        f: &mut dyn FnMut(Summary),
+    ) -> Poll<CargoResult<()>> {
+        let mut seen = HashSet::new();
+        let mut pending = false;
+        let mut error = None;
+        for registry in &mut self.registries {
+            let ready = registry.query(dep, kind, &mut |s| {
+                if seen.insert(s.package_id()) {
+                    f(s)
+                }
+            });
+            match ready {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => {
+                    error.get_or_insert(e);
+                }
+                Poll::Pending => pending = true,
+            }
+        }
+        if let Some(e) = error {
+            Poll::Ready(Err(e))
+        } else if pending {
+            Poll::Pending
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn describe_source(&self, source: SourceId) -> String {
+        match self.registries.first() {
+            Some(registry) => registry.describe_source(source),
+            None => source.to_string(),
+        }
+    }
+
+    fn is_replaced(&self, source: SourceId) -> bool {
+        self.registries.iter().any(|r| r.is_replaced(source))
+    }
+
+    fn block_until_ready(&mut self) -> CargoResult<()> {
+        for registry in &mut self.registries {
+            registry.block_until_ready()?;
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> RegistryCapabilities {
+        RegistryCapabilities {
+            batch_queries: !self.registries.is_empty()
+                && self
+                    .registries
+                    .iter()
+                    .all(|r| r.capabilities().batch_queries),
+        }
+    }
 }
 
 /// This structure represents a registry of known packages. It internally
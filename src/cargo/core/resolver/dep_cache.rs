@@ -9,24 +9,38 @@
 //!
 //! This module impl that cache in all the gory details
 
+use crate::core::dependency::DepKind;
 use crate::core::resolver::context::Context;
-use crate::core::resolver::errors::describe_path_in_context;
-use crate::core::resolver::types::{ConflictReason, DepInfo, FeaturesSet};
+use crate::core::resolver::errors::{
+    describe_path_in_context, AmbiguousSourceError, ResolveCancelled,
+};
+use crate::core::resolver::types::{
+    ConflictMap, ConflictReason, DepInfo, FeaturesSet, SerializedConflict,
+};
 use crate::core::resolver::{
-    ActivateError, ActivateResult, CliFeatures, RequestedFeatures, ResolveOpts, VersionOrdering,
-    VersionPreferences,
+    ActivateError, ActivateResult, CliFeatures, ConflictKind, ConflictPolicy, PreferenceInfluence,
+    RequestedFeatures, Resolve, ResolveOpts, VersionOrdering, VersionPreferences,
 };
 use crate::core::{
-    Dependency, FeatureValue, PackageId, PackageIdSpec, QueryKind, Registry, Summary,
+    Dependency, FeatureValue, PackageId, PackageIdSpec, QueryKind, Registry, SourceId, Summary,
 };
 use crate::util::errors::CargoResult;
 use crate::util::interning::InternedString;
-use crate::util::PartialVersion;
+use crate::util::{OptVersionReq, PartialVersion};
 
 use anyhow::Context as _;
-use std::collections::{BTreeSet, HashMap, HashSet};
+use cargo_platform::{CfgExpr, Platform};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::mem;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::task::Poll;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tracing::debug;
 
 pub struct RegistryQueryer<'a> {
@@ -37,20 +51,346 @@ pub struct RegistryQueryer<'a> {
     /// versions first. That allows `cargo update -Z minimal-versions` which will
     /// specify minimum dependency versions to be used.
     minimal_versions: bool,
+    /// whether candidates are sorted with `VersionOrdering::OldestPublished`,
+    /// which takes precedence over `minimal_versions`
+    oldest_published: bool,
+    /// whether candidates bringing in fewer dependencies are tried first,
+    /// see `set_prefer_smaller_graph`
+    prefer_smaller_graph: bool,
+    /// how many dependencies a candidate has with the given options
+    dep_counts: HashMap<(PackageId, Rc<ResolveOpts>), usize>,
     max_rust_version: Option<PartialVersion>,
+    /// whether candidates from more than one source are an error
+    forbid_ambiguous_sources: bool,
+    /// the summaries `query` answers with instead of asking the registry, by
+    /// package name, see `set_pinned_summaries`
+    pinned: HashMap<InternedString, Vec<(PackageId, Summary)>>,
+    /// the only sources replacements may come from, see `set_allowed_replacement_sources`
+    allowed_replacement_sources: Option<HashSet<SourceId>>,
+    /// whether activating a version older than the locked one is an error
+    /// the `rust-version` assumed for candidates that do not declare one when
+    /// checking them against `max_rust_version`
+    assumed_rust_version: Option<PartialVersion>,
+    /// If set, only versions published before this time are candidates.
+    published_before: Option<SystemTime>,
+    /// Whether versions without a publish time are kept when `published_before` is set.
+    include_unknown_published: bool,
+    /// how `query` asks the registry for the candidates of a dependency
+    query_kind: QueryKind,
+    /// whether pre-releases are candidates like any other version, see
+    /// `set_prerelease_friendly`
+    prerelease_friendly: bool,
     /// a cache of `Candidate`s that fulfil a `Dependency` (and whether `first_minimal_version`)
     registry_cache: HashMap<(Dependency, bool), Poll<Rc<Vec<Summary>>>>,
+    /// the candidates of each `Dependency` that were dropped for needing a newer
+    /// rust than `max_rust_version`
+    msrv_filtered: HashMap<Dependency, Vec<PackageId>>,
+    /// decides which license expressions are allowed, and whether versions
+    /// without a license are kept, see `set_license_filter`
+    license_filter: Option<LicenseFilter<'a>>,
+    /// the candidates dropped for their license, by dependency
+    license_filtered: HashMap<Dependency, Vec<PackageId>>,
+    /// the candidates dropped for being published after `published_before`,
+    /// by dependency
+    snapshot_filtered: HashMap<Dependency, Vec<PackageId>>,
+    /// when set to `true` from elsewhere, queries fail with `ResolveCancelled`
+    cancel: Option<Arc<AtomicBool>>,
+    /// how many queries sorted minimum versions first, and why
+    minimal_ordering_counts: MinimalOrderingCounts,
+    /// how many candidates of each `Dependency` were left after filtering
+    candidate_counts: HashMap<Dependency, CandidateCounts>,
+    /// a cache of the unfiltered `Summary`s a replacement `Dependency` points at
+    replacement_cache: HashMap<Dependency, Poll<Rc<Vec<Summary>>>>,
     /// a cache of `Dependency`s that are required for a `Summary`
     ///
     /// HACK: `first_minimal_version` is not kept in the cache key is it is 1:1 with
     /// `parent.is_none()` (the first element of the cache key) as it doesn't change through
     /// execution.
-    summary_cache: HashMap<
-        (Option<PackageId>, Summary, ResolveOpts),
-        (Rc<(HashSet<InternedString>, Rc<Vec<DepInfo>>)>, bool),
-    >,
+    ///
+    /// The options are kept behind an `Rc` so making a key does not copy them.
+    summary_cache: HashMap<(Option<PackageId>, Summary, Rc<ResolveOpts>), (BuildDepsResult, bool)>,
+    /// the complete entries of `summary_cache`, without their ready flags,
+    /// once `finalize` was called
+    finalized: Option<HashMap<(Option<PackageId>, Summary, Rc<ResolveOpts>), BuildDepsResult>>,
     /// all the cases we ended up using a supplied replacement
     used_replacements: HashMap<PackageId, Summary>,
+    /// the index in `replacements` of the entry each package matched, whether
+    /// or not it was used
+    matched_replacements: HashMap<PackageId, usize>,
+    /// the dependency each package matching a `[replace]` entry was looked up
+    /// with in the replacement source, see `queried_overrides`
+    queried_overrides: HashMap<PackageId, Dependency>,
+    /// every activation attempt and backtrack, if requested with `record_trace`
+    trace: Option<ResolutionTrace>,
+    /// the conflicts found while building deps, if requested with `record_conflicts`
+    conflicts: Option<Vec<SerializedConflict>>,
+    /// told about each conflict found while building deps, see `set_conflict_observer`
+    conflict_observer: Option<Rc<dyn Fn(&ConflictReason)>>,
+    /// told about each replacement as it is applied, see `set_replacement_observer`
+    replacement_observer: Option<Rc<dyn Fn(&PackageId, &Summary)>>,
+    /// ranks dependencies of a package to try before the candidate-count ordering
+    dep_priority: Option<Box<dyn Fn(&Dependency) -> i32 + 'a>>,
+    /// may swap candidates for others after `[replace]`, see `set_substitution`
+    substitution: Option<Box<dyn Fn(&Summary) -> Option<Summary> + 'a>>,
+    /// told about the deps of a package as soon as `build_deps` works them out
+    build_deps_progress: Option<Box<dyn FnMut(PackageId, &[DepInfo], &[Dependency]) + 'a>>,
+    /// the dependencies still pending in the last call to `build_deps`
+    pending_deps: Vec<InternedString>,
+    /// how many times `build_deps` was called
+    activations: usize,
+    /// the most versions of a package name that may be activated at once,
+    /// see `set_version_limit`
+    version_limits: HashMap<InternedString, usize>,
+    /// the activated versions of each limited package name, as of the last
+    /// call to `build_deps` for that name
+    activated_versions: HashMap<InternedString, BTreeSet<Version>>,
+    /// the activation count past which the callback is told the resolve is
+    /// slow, see `set_slow_resolve_warning`
+    slow_resolve: Option<(usize, Box<dyn FnMut(&SlowResolveWarning) + 'a>)>,
+    /// the feature problems that were only warned about
+    warnings: BTreeSet<RequirementWarning>,
+    /// every completed registry query, if requested with `record_queries`
+    queries: Option<Vec<(Dependency, Vec<Summary>)>>,
+    /// time spent waiting on the `registry`
+    registry_time: Duration,
+    /// time spent waiting on the `registry` in `query`, by package name
+    query_latencies: HashMap<InternedString, Duration>,
+    /// time spent in `build_deps` outside of the `registry`
+    compute_time: Duration,
+}
+
+/// A problem with the features of a package that does not stop the
+/// resolver from using it.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RequirementWarning {
+    /// `feature` was treated as enabling the dependency of the same name,
+    /// because of a [`ConflictPolicy::Warn`].
+    DependencyAsFeature {
+        package: PackageId,
+        feature: InternedString,
+        kind: ConflictKind,
+    },
+    /// A deprecated `feature` was asked for by `enabled_by`, or on the
+    /// command line if `None`.
+    DeprecatedFeature {
+        package: PackageId,
+        feature: InternedString,
+        enabled_by: Option<PackageId>,
+    },
+    /// `feature` of `dep` is enabled both with `dep/feature` and
+    /// `dep?/feature`, so the weak reference has no effect.
+    RedundantWeakDepFeature {
+        package: PackageId,
+        dep: InternedString,
+        feature: InternedString,
+    },
+    /// Features `first` and `second` are in an exclusive group, but were both
+    /// enabled by `--all-features`, which asks for every feature regardless.
+    ExclusiveFeaturesEnabled {
+        package: PackageId,
+        first: InternedString,
+        second: InternedString,
+    },
+}
+
+impl RequirementWarning {
+    pub fn package_id(&self) -> PackageId {
+        match self {
+            RequirementWarning::DependencyAsFeature { package, .. }
+            | RequirementWarning::DeprecatedFeature { package, .. }
+            | RequirementWarning::RedundantWeakDepFeature { package, .. }
+            | RequirementWarning::ExclusiveFeaturesEnabled { package, .. } => *package,
+        }
+    }
+}
+
+impl fmt::Display for RequirementWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequirementWarning::DependencyAsFeature {
+                package,
+                feature,
+                kind: ConflictKind::RequiredDependencyAsFeature,
+            } => write!(
+                f,
+                "package `{}` does not have feature `{}`, using its required \
+                 dependency of that name instead",
+                package, feature
+            ),
+            RequirementWarning::DependencyAsFeature {
+                package,
+                feature,
+                kind: ConflictKind::NonImplicitDependencyAsFeature,
+            } => write!(
+                f,
+                "package `{}` does not have feature `{}`, enabling its optional \
+                 dependency of that name instead",
+                package, feature
+            ),
+            RequirementWarning::DeprecatedFeature {
+                package,
+                feature,
+                enabled_by: Some(enabled_by),
+            } => write!(
+                f,
+                "feature `{}` of package `{}` is deprecated, but `{}` enables it",
+                feature, package, enabled_by
+            ),
+            RequirementWarning::DeprecatedFeature {
+                package,
+                feature,
+                enabled_by: None,
+            } => write!(
+                f,
+                "feature `{}` of package `{}` is deprecated",
+                feature, package
+            ),
+            RequirementWarning::RedundantWeakDepFeature {
+                package,
+                dep,
+                feature,
+            } => write!(
+                f,
+                "package `{}` enables both `{}/{}` and `{}?/{}`, \
+                 the weak reference has no effect",
+                package, dep, feature, dep, feature
+            ),
+            RequirementWarning::ExclusiveFeaturesEnabled {
+                package,
+                first,
+                second,
+            } => write!(
+                f,
+                "features `{}` and `{}` of package `{}` are not meant to be enabled \
+                 together, but `--all-features` enables both",
+                first, second, package
+            ),
+        }
+    }
+}
+
+/// How many `RegistryQueryer::query` calls sorted the candidates minimum
+/// versions first, by what asked for it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MinimalOrderingCounts {
+    /// Sorted that way only because of `first_minimal_version`.
+    pub first_minimal_version: usize,
+    /// Sorted that way because of the resolve-wide `minimal_versions`.
+    pub minimal_versions: usize,
+}
+
+/// How many versions the registry had for a dependency, and how many of them
+/// `RegistryQueryer::query` kept.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CandidateCounts {
+    /// Everything the registry returned.
+    pub returned: usize,
+    /// Versions dropped for needing a newer rust than `max_rust_version`.
+    pub msrv_filtered: usize,
+    /// Versions left as candidates.
+    pub survived: usize,
+}
+
+/// What the callback of `RegistryQueryer::set_slow_resolve_warning` is told.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowResolveWarning {
+    /// How many times `build_deps` was called so far.
+    pub activations: usize,
+    /// The dependency with the most candidates, and how many it has, as the
+    /// best guess of what is slowing the resolve down.
+    pub culprit: Option<(Dependency, usize)>,
+}
+
+impl fmt::Display for SlowResolveWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "resolution is taking unusually long ({} activations so far)",
+            self.activations
+        )?;
+        if let Some((dep, candidates)) = &self.culprit {
+            write!(
+                f,
+                "; the likely culprit is `{}` with {} candidates",
+                dep.package_name(),
+                candidates
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The steps `RegistryQueryer::query` took for a single dependency edge,
+/// as returned by [`RegistryQueryer::explain`].
+#[derive(Debug)]
+pub struct QueryExplanation {
+    /// The package that depends on `dep`, or `None` for a root.
+    pub parent: Option<PackageId>,
+    /// The dependency that was looked up.
+    pub dep: Dependency,
+    /// Everything the registry returned for `dep`, before any filtering.
+    pub registry_candidates: Vec<Summary>,
+    /// Candidates dropped because they need a newer rust than `max_rust_version`.
+    pub msrv_filtered: Vec<PackageId>,
+    /// The order the remaining candidates were sorted in.
+    pub ordering: VersionOrdering,
+    /// The candidates in the order the resolver will try them.
+    pub candidates: Rc<Vec<Summary>>,
+}
+
+/// Why a version of a package was not picked, as found by
+/// [`RegistryQueryer::why_not`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WhyNot {
+    /// It needs a newer rust than `max_rust_version`.
+    RustVersion(Dependency),
+    /// A filter on summaries dropped it, like `set_license_filter` or
+    /// `set_published_before`.
+    Filtered(Dependency),
+    /// It was a candidate for the dependency, but the version preferences
+    /// ranked the listed versions ahead of it. If there are none, it was
+    /// tried first and backtracked over.
+    LostTiebreak {
+        dependency: Dependency,
+        ranked_ahead: Vec<PackageId>,
+    },
+    /// None of the requirements that were queried matches it.
+    NotRequested,
+}
+
+impl fmt::Display for WhyNot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WhyNot::RustVersion(dep) => write!(
+                f,
+                "it requires a newer rustc than the `rust-version` ceiling (required by `{} = \"{}\"`)",
+                dep.package_name(),
+                dep.version_req()
+            ),
+            WhyNot::Filtered(dep) => write!(
+                f,
+                "it was excluded by a filter (required by `{} = \"{}\"`)",
+                dep.package_name(),
+                dep.version_req()
+            ),
+            WhyNot::LostTiebreak {
+                dependency,
+                ranked_ahead,
+            } => {
+                write!(
+                    f,
+                    "it was a candidate for `{} = \"{}\"`",
+                    dependency.package_name(),
+                    dependency.version_req()
+                )?;
+                match ranked_ahead.first() {
+                    Some(first) => write!(f, ", but `{}` was preferred", first),
+                    None => write!(f, ", but was backtracked over"),
+                }
+            }
+            WhyNot::NotRequested => write!(f, "no dependency requires it"),
+        }
+    }
 }
 
 impl<'a> RegistryQueryer<'a> {
@@ -66,340 +406,2852 @@ impl<'a> RegistryQueryer<'a> {
             replacements,
             version_prefs,
             minimal_versions,
+            oldest_published: false,
+            prefer_smaller_graph: false,
+            dep_counts: HashMap::new(),
             max_rust_version,
+            forbid_ambiguous_sources: false,
+            pinned: HashMap::new(),
+            allowed_replacement_sources: None,
+            assumed_rust_version: None,
+            published_before: None,
+            include_unknown_published: false,
+            query_kind: QueryKind::Exact,
+            prerelease_friendly: false,
             registry_cache: HashMap::new(),
+            msrv_filtered: HashMap::new(),
+            license_filter: None,
+            license_filtered: HashMap::new(),
+            snapshot_filtered: HashMap::new(),
+            cancel: None,
+            minimal_ordering_counts: MinimalOrderingCounts::default(),
+            candidate_counts: HashMap::new(),
+            replacement_cache: HashMap::new(),
             summary_cache: HashMap::new(),
+            finalized: None,
             used_replacements: HashMap::new(),
+            matched_replacements: HashMap::new(),
+            queried_overrides: HashMap::new(),
+            trace: None,
+            conflicts: None,
+            conflict_observer: None,
+            replacement_observer: None,
+            dep_priority: None,
+            substitution: None,
+            build_deps_progress: None,
+            activations: 0,
+            version_limits: HashMap::new(),
+            activated_versions: HashMap::new(),
+            slow_resolve: None,
+            pending_deps: Vec::new(),
+            warnings: BTreeSet::new(),
+            queries: None,
+            registry_time: Duration::ZERO,
+            query_latencies: HashMap::new(),
+            compute_time: Duration::ZERO,
         }
     }
 
-    pub fn reset_pending(&mut self) -> bool {
-        let mut all_ready = true;
-        self.registry_cache.retain(|_, r| {
-            if !r.is_ready() {
-                all_ready = false;
-            }
-            r.is_ready()
-        });
-        self.summary_cache.retain(|_, (_, r)| {
-            if !*r {
-                all_ready = false;
-            }
-            *r
-        });
-        all_ready
+    /// Resolve dependencies with a higher `priority` before the others of the
+    /// same package, falling back to the candidate-count ordering for ties.
+    ///
+    /// Every dependency has a priority of 0 unless this is set.
+    pub fn set_dep_priority(&mut self, priority: impl Fn(&Dependency) -> i32 + 'a) {
+        self.dep_priority = Some(Box::new(priority));
     }
 
-    pub fn used_replacement_for(&self, p: PackageId) -> Option<(PackageId, PackageId)> {
-        self.used_replacements.get(&p).map(|r| (p, r.package_id()))
+    /// Let `substitute` replace candidates as they are queried, e.g. with a
+    /// locally built copy, without a `[replace]` table. It sees each candidate
+    /// left after the `[replace]` entries were looked at, and the summary it
+    /// returns, if any, takes its place in the candidate list.
+    ///
+    /// A substitute must have the same name and version as the candidate, as
+    /// the requirement it was queried for must still hold.
+    pub fn set_substitution(&mut self, substitute: impl Fn(&Summary) -> Option<Summary> + 'a) {
+        self.substitution = Some(Box::new(substitute));
+        self.drop_cached_candidates();
     }
 
-    pub fn replacement_summary(&self, p: PackageId) -> Option<&Summary> {
-        self.used_replacements.get(&p)
+    /// Stop the resolve as soon as `cancel` is set to `true`, for example by an
+    /// editor whose user kept typing.
+    ///
+    /// It is checked before each query and `build_deps`, which then fail with
+    /// `ResolveCancelled` and `ActivateError::Cancelled` without touching the
+    /// caches, so they stay usable for another resolve.
+    pub fn set_cancel(&mut self, cancel: Arc<AtomicBool>) {
+        self.cancel = Some(cancel);
     }
 
-    /// Queries the `registry` to return a list of candidates for `dep`.
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .map_or(false, |c| c.load(Ordering::Relaxed))
+    }
+
+    /// Sort candidates with [`VersionOrdering::OldestPublished`], to resolve
+    /// every dependency to the oldest version the registry has for it.
     ///
-    /// This method is the location where overrides are taken into account. If
-    /// any candidates are returned which match an override then the override is
-    /// applied by performing a second query for what the override should
-    /// return.
-    pub fn query(
-        &mut self,
-        dep: &Dependency,
-        first_minimal_version: bool,
-    ) -> Poll<CargoResult<Rc<Vec<Summary>>>> {
-        let registry_cache_key = (dep.clone(), first_minimal_version);
-        if let Some(out) = self.registry_cache.get(&registry_cache_key).cloned() {
-            return out.map(Result::Ok);
+    /// Results already cached are not sorted again.
+    pub fn set_oldest_published(&mut self, oldest_published: bool) {
+        self.oldest_published = oldest_published;
+    }
+
+    /// Experimental: among candidates the `VersionPreferences` rank the same,
+    /// try first the ones with the fewest dependencies of their own, as a
+    /// way to end up with a smaller dependency graph.
+    ///
+    /// This trades resolve time for a leaner result, as the features of every
+    /// candidate have to be worked out instead of only those of the one
+    /// picked. They are cached, but it can still noticeably slow down a
+    /// resolve. It also means newer versions are not always preferred.
+    pub fn set_prefer_smaller_graph(&mut self, prefer_smaller_graph: bool) {
+        self.prefer_smaller_graph = prefer_smaller_graph;
+    }
+
+    /// Make `query` fail with an [`AmbiguousSourceError`] when the candidates
+    /// for a dependency come from more than one source, as a crate name
+    /// shared between registries can be a way to sneak in the wrong crate.
+    ///
+    /// This is off by default, as mirrors legitimately serve the same crates.
+    pub fn set_forbid_ambiguous_sources(&mut self, forbid: bool) {
+        self.forbid_ambiguous_sources = forbid;
+    }
+
+    /// Make `query` answer with the given summary, without asking the registry,
+    /// for any dependency on the name and source of one of the package ids,
+    /// for example when they come from a trusted lock file.
+    ///
+    /// A dependency whose requirement a pinned version does not satisfy is an
+    /// error rather than a fallback to the registry, as the pin is meant to be
+    /// the only choice.
+    pub fn set_pinned_summaries(&mut self, pinned: HashMap<PackageId, Summary>) {
+        self.pinned.clear();
+        for (pkg_id, summary) in pinned {
+            self.pinned
+                .entry(pkg_id.name())
+                .or_default()
+                .push((pkg_id, summary));
         }
+    }
 
-        let mut ret = Vec::new();
-        let ready = self.registry.query(dep, QueryKind::Exact, &mut |s| {
-            if self.max_rust_version.is_none() || s.rust_version() <= self.max_rust_version {
-                ret.push(s);
+    /// The pinned summary for `dep`, if its name and source are pinned.
+    fn query_pinned(&self, dep: &Dependency) -> Option<CargoResult<Summary>> {
+        let pinned: Vec<_> = self
+            .pinned
+            .get(&dep.package_name())?
+            .iter()
+            .filter(|(pkg_id, _)| pkg_id.source_id() == dep.source_id())
+            .collect();
+        if pinned.is_empty() {
+            return None;
+        }
+        match pinned.iter().find(|(pkg_id, _)| dep.matches_id(*pkg_id)) {
+            Some((_, summary)) => Some(Ok(summary.clone())),
+            None => {
+                let versions: Vec<_> = pinned
+                    .iter()
+                    .map(|(pkg_id, _)| pkg_id.version().to_string())
+                    .collect();
+                Some(Err(anyhow::format_err!(
+                    "`{}` is pinned to version {}, which does not satisfy the requirement `{}`",
+                    dep.package_name(),
+                    versions.join(", "),
+                    dep.version_req()
+                )))
             }
-        })?;
-        if ready.is_pending() {
-            self.registry_cache
-                .insert((dep.clone(), first_minimal_version), Poll::Pending);
-            return Poll::Pending;
         }
-        for summary in ret.iter() {
-            let mut potential_matches = self
-                .replacements
-                .iter()
-                .filter(|&&(ref spec, _)| spec.matches(summary.package_id()));
+    }
 
-            let &(ref spec, ref dep) = match potential_matches.next() {
-                None => continue,
-                Some(replacement) => replacement,
-            };
-            debug!(
-                "found an override for {} {}",
-                dep.package_name(),
-                dep.version_req()
-            );
+    /// Make `query` fail instead of applying a replacement that comes from a
+    /// source other than the given ones, so a misconfigured or compromised
+    /// override cannot redirect a dependency to an untrusted location.
+    ///
+    /// Replacements from the same source as the replaced package are never
+    /// applied, so they are not checked.
+    pub fn set_allowed_replacement_sources(&mut self, sources: impl IntoIterator<Item = SourceId>) {
+        self.allowed_replacement_sources = Some(sources.into_iter().collect());
+    }
 
-            let mut summaries = match self.registry.query_vec(dep, QueryKind::Exact)? {
-                Poll::Ready(s) => s.into_iter(),
-                Poll::Pending => {
-                    self.registry_cache
-                        .insert((dep.clone(), first_minimal_version), Poll::Pending);
-                    return Poll::Pending;
-                }
-            };
-            let s = summaries.next().ok_or_else(|| {
-                anyhow::format_err!(
-                    "no matching package for override `{}` found\n\
-                     location searched: {}\n\
-                     version required: {}",
-                    spec,
-                    dep.source_id(),
-                    dep.version_req()
-                )
-            })?;
-            let summaries = summaries.collect::<Vec<_>>();
-            if !summaries.is_empty() {
-                let bullets = summaries
-                    .iter()
-                    .map(|s| format!("  * {}", s.package_id()))
-                    .collect::<Vec<_>>();
-                return Poll::Ready(Err(anyhow::anyhow!(
-                    "the replacement specification `{}` matched \
-                     multiple packages:\n  * {}\n{}",
-                    spec,
-                    s.package_id(),
-                    bullets.join("\n")
-                )));
-            }
-
-            // The dependency should be hard-coded to have the same name and an
-            // exact version requirement, so both of these assertions should
-            // never fail.
-            assert_eq!(s.version(), summary.version());
-            assert_eq!(s.name(), summary.name());
-
-            let replace = if s.source_id() == summary.source_id() {
-                debug!("Preventing\n{:?}\nfrom replacing\n{:?}", summary, s);
-                None
-            } else {
-                Some(s)
-            };
-            let matched_spec = spec.clone();
-
-            // Make sure no duplicates
-            if let Some(&(ref spec, _)) = potential_matches.next() {
-                return Poll::Ready(Err(anyhow::anyhow!(
-                    "overlapping replacement specifications found:\n\n  \
-                     * {}\n  * {}\n\nboth specifications match: {}",
-                    matched_spec,
-                    spec,
-                    summary.package_id()
-                )));
+    /// Returns an error if one of the `resolved` packages is older than a
+    /// locked, semver compatible version of the same package, for when
+    /// `ResolveOpts::forbid_downgrade` is set.
+    pub fn check_not_downgraded(
+        &self,
+        resolved: impl IntoIterator<Item = PackageId>,
+    ) -> CargoResult<()> {
+        let locked: Vec<_> = self.version_prefs.locked_package_ids().collect();
+        for id in resolved {
+            let key = id.as_activations_key();
+            if let Some(locked) = locked
+                .iter()
+                .find(|l| l.as_activations_key() == key && l.version() > id.version())
+            {
+                anyhow::bail!(
+                    "`{}` would be downgraded from {} to {}, which is not allowed",
+                    id.name(),
+                    locked.version(),
+                    id.version()
+                );
             }
+        }
+        Ok(())
+    }
 
-            for dep in summary.dependencies() {
-                debug!("\t{} => {}", dep.package_name(), dep.version_req());
-            }
-            if let Some(r) = replace {
-                self.used_replacements.insert(summary.package_id(), r);
-            }
+    /// Returns a conflict with `parent` if `candidate` is yanked, for when
+    /// `ResolveOpts::forbid_yanked` is set, so the resolver backtracks to
+    /// find a graph without it.
+    ///
+    /// A root has nothing to conflict with, so it is left to
+    /// `check_resolve_not_yanked`.
+    pub fn check_yanked(
+        &self,
+        candidate: &Summary,
+        parent: Option<(&Summary, &Dependency)>,
+    ) -> ActivateResult<()> {
+        match parent {
+            Some((parent, _)) if self.version_prefs.is_yanked(candidate.package_id()) => Err(
+                ActivateError::Conflict(parent.package_id(), ConflictReason::Yanked),
+            ),
+            _ => Ok(()),
         }
+    }
 
-        // When we attempt versions for a package we'll want to do so in a sorted fashion to pick
-        // the "best candidates" first. VersionPreferences implements this notion.
-        let ordering = if first_minimal_version || self.minimal_versions {
-            VersionOrdering::MinimumVersionsFirst
-        } else {
-            VersionOrdering::MaximumVersionsFirst
+    /// Returns an error if the finished `resolve` uses a yanked version, for
+    /// when `ResolveOpts::forbid_yanked` is set.
+    pub fn check_resolve_not_yanked(&self, resolve: &Resolve) -> CargoResult<()> {
+        let Some(yanked) = resolve.iter().find(|id| self.version_prefs.is_yanked(*id)) else {
+            return Ok(());
         };
-        let first_version = first_minimal_version;
-        self.version_prefs
-            .sort_summaries(&mut ret, ordering, first_version);
+        let required_by = match resolve.path_to_top(&yanked).get(1) {
+            Some((parent, _)) => format!("\nrequired by `{}`", parent),
+            None => String::new(),
+        };
+        anyhow::bail!(
+            "`{}` would be resolved to the yanked version {}, which is not allowed{}",
+            yanked.name(),
+            yanked.version(),
+            required_by
+        )
+    }
 
-        let out = Poll::Ready(Rc::new(ret));
+    /// Treat candidates that do not declare a `rust-version` as needing
+    /// `rust_version` when filtering by `max_rust_version`, instead of always
+    /// keeping them.
+    pub fn set_assumed_rust_version(&mut self, rust_version: Option<PartialVersion>) {
+        self.assumed_rust_version = rust_version;
+        self.drop_cached_candidates();
+    }
 
-        self.registry_cache.insert(registry_cache_key, out.clone());
+    /// Resolve as of `before`, ignoring every version published at or after it.
+    ///
+    /// Versions whose source does not say when they were published are kept
+    /// only if `include_unknown` is set. None of the sources built into cargo
+    /// know it, only summaries loaded with `import_registry_cache` or made by
+    /// a custom `Registry` have a [`Summary::published`] time.
+    pub fn set_published_before(&mut self, before: SystemTime, include_unknown: bool) {
+        self.published_before = Some(before);
+        self.include_unknown_published = include_unknown;
+        self.drop_cached_candidates();
+    }
 
-        out.map(Result::Ok)
+    /// Drop the candidates whose license expression `accept` rejects, for
+    /// example with an SPDX allowlist, so they are never selected.
+    ///
+    /// Versions whose source does not say what their license is are kept only
+    /// if `include_unknown` is set.
+    pub fn set_license_filter(
+        &mut self,
+        accept: impl Fn(&str) -> bool + 'a,
+        include_unknown: bool,
+    ) {
+        self.license_filter = Some((Box::new(accept), include_unknown));
+        self.drop_cached_candidates();
     }
 
-    /// Find out what dependencies will be added by activating `candidate`,
-    /// with features described in `opts`. Then look up in the `registry`
-    /// the candidates that will fulfil each of these dependencies, as it is the
-    /// next obvious question.
-    pub fn build_deps(
+    /// Call `progress` each time `build_deps` works out the dependencies of a
+    /// package, with the ones that have candidates and the ones still pending.
+    ///
+    /// A package with pending dependencies is reported again once the registry
+    /// catches up, until nothing is pending. Results served from the cache are
+    /// not reported.
+    pub fn set_build_deps_progress(
+        &mut self,
+        progress: impl FnMut(PackageId, &[DepInfo], &[Dependency]) + 'a,
+    ) {
+        self.build_deps_progress = Some(Box::new(progress));
+    }
+
+    /// Call `warn` once, without stopping the resolve, when `build_deps` has
+    /// been called `threshold` times, with a guess at which dependency makes
+    /// the resolve take that long.
+    ///
+    /// Unlike a backtracking budget this is only meant to tell the user why a
+    /// resolve that will eventually succeed is slow.
+    pub fn set_slow_resolve_warning(
+        &mut self,
+        threshold: usize,
+        warn: impl FnMut(&SlowResolveWarning) + 'a,
+    ) {
+        self.slow_resolve = Some((threshold, Box::new(warn)));
+    }
+
+    /// Allow at most `max` distinct versions of the package `name` to be
+    /// activated across the whole resolve, e.g. two majors of `syn` but not a
+    /// third one. `build_deps` fails with a conflict for a version past the
+    /// limit, which makes the resolver backtrack until versions are unified.
+    /// With a `max` of 0, no version of `name` can be activated at all.
+    pub fn set_version_limit(&mut self, name: InternedString, max: usize) {
+        self.version_limits.insert(name, max);
+    }
+
+    /// The versions of `name` that were activated the last time `build_deps`
+    /// ran for it. Only the names given to `set_version_limit` are tracked.
+    pub fn activated_versions(&self, name: InternedString) -> Option<&BTreeSet<Version>> {
+        self.activated_versions.get(&name)
+    }
+
+    /// Checks that activating `candidate` in `cx` (where it is already
+    /// flagged as activated) as a dependency of `parent` does not go past
+    /// the limit set for its name.
+    fn check_version_limit(
         &mut self,
         cx: &Context,
         parent: Option<PackageId>,
         candidate: &Summary,
-        opts: &ResolveOpts,
-        first_minimal_version: bool,
-    ) -> ActivateResult<Rc<(HashSet<InternedString>, Rc<Vec<DepInfo>>)>> {
-        // if we have calculated a result before, then we can just return it,
-        // as it is a "pure" query of its arguments.
-        if let Some(out) = self
-            .summary_cache
-            .get(&(parent, candidate.clone(), opts.clone()))
-        {
-            return Ok(out.0.clone());
+    ) -> ActivateResult<()> {
+        let name = candidate.name();
+        let Some(&max) = self.version_limits.get(&name) else {
+            return Ok(());
+        };
+        let mut versions = BTreeSet::new();
+        let mut others = ConflictMap::new();
+        for (s, _) in cx.activations.values() {
+            if s.name() != name {
+                continue;
+            }
+            versions.insert(s.version().clone());
+            if s.version() != candidate.version() {
+                others.insert(s.package_id(), ConflictReason::VersionLimit(max));
+            }
         }
-        // First, figure out our set of dependencies based on the requested set
-        // of features. This also calculates what features we're going to enable
-        // for our own dependencies.
-        let (used_features, deps) = resolve_features(parent, candidate, opts)?;
+        versions.insert(candidate.version().clone());
+        if versions.len() > max {
+            // The limit is only reached while all of the other versions are
+            // activated, so the conflict names every one of them for the
+            // resolver to backtrack over.
+            if !others.is_empty() {
+                return Err(ActivateError::Conflicts(others));
+            }
+            // With a limit of 0 no version fits, whatever else is activated.
+            return Err(match parent {
+                Some(p) => ActivateError::Conflict(p, ConflictReason::VersionLimit(max)),
+                None => ActivateError::Fatal(anyhow::format_err!(
+                    "`{}` cannot be activated, as no version of `{}` may be",
+                    candidate.package_id(),
+                    name
+                )),
+            });
+        }
+        self.activated_versions.insert(name, versions);
+        Ok(())
+    }
 
-        // Next, transform all dependencies into a list of possible candidates
-        // which can satisfy that dependency.
-        let mut all_ready = true;
-        let mut deps = deps
-            .into_iter()
-            .filter_map(
-                |(dep, features)| match self.query(&dep, first_minimal_version) {
-                    Poll::Ready(Ok(candidates)) => Some(Ok((dep, candidates, features))),
-                    Poll::Pending => {
-                        all_ready = false;
-                        // we can ignore Pending deps, resolve will be repeatedly called
-                        // until there are none to ignore
-                        None
-                    }
-                    Poll::Ready(Err(e)) => Some(Err(e).with_context(|| {
-                        format!(
-                            "failed to get `{}` as a dependency of {}",
-                            dep.package_name(),
-                            describe_path_in_context(cx, &candidate.package_id()),
-                        )
-                    })),
-                },
-            )
-            .collect::<CargoResult<Vec<DepInfo>>>()?;
+    /// The dependency with the most candidates, which likely makes the
+    /// resolver try the most combinations.
+    fn widest_dependency(&self) -> Option<(Dependency, usize)> {
+        self.candidate_counts
+            .iter()
+            .max_by(|(a_dep, a), (b_dep, b)| {
+                a.survived
+                    .cmp(&b.survived)
+                    .then_with(|| b_dep.package_name().cmp(&a_dep.package_name()))
+            })
+            .map(|(dep, counts)| (dep.clone(), counts.survived))
+    }
 
-        // Attempt to resolve dependencies with fewer candidates before trying
-        // dependencies with more candidates. This way if the dependency with
-        // only one candidate can't be resolved we don't have to do a bunch of
-        // work before we figure that out.
-        deps.sort_by_key(|&(_, ref a, _)| a.len());
+    /// The names of the dependencies the registry had no answer for yet in
+    /// the last call to `build_deps`, for finding out what a resolve that
+    /// does not finish is waiting on.
+    pub fn pending_deps(&self) -> &[InternedString] {
+        &self.pending_deps
+    }
 
-        let out = Rc::new((used_features, Rc::new(deps)));
+    /// Use `kind` for the registry queries made by `query`, instead of
+    /// `QueryKind::Exact`.
+    ///
+    /// With `QueryKind::Fuzzy` a registry also matches names that only differ
+    /// in `-` versus `_`, so a name typed by hand finds its package. Candidates
+    /// are still required to match the version requirement. The replacements
+    /// of `[replace]` are always looked up with `QueryKind::Exact`, so they
+    /// only ever apply to the package they name.
+    ///
+    /// Candidates cached so far are dropped, as they may differ.
+    pub fn set_query_kind(&mut self, kind: QueryKind) {
+        self.query_kind = kind;
+        self.drop_cached_candidates();
+    }
 
-        // If we succeed we add the result to the cache so we can use it again next time.
-        // We don't cache the failure cases as they don't impl Clone.
-        self.summary_cache.insert(
-            (parent, candidate.clone(), opts.clone()),
-            (out.clone(), all_ready),
-        );
+    /// Consider pre-release versions like any other version, so `^1.0` also
+    /// has `1.1.0-alpha` as a candidate and tries it first when it is the
+    /// newest. See [`OptVersionReq::matches_prerelease`] for what a
+    /// requirement accepts then.
+    ///
+    /// The registry is asked for every version of a package and the
+    /// candidates are filtered here. Candidates are then ordered by semver
+    /// precedence as usual, so `1.1.0-alpha` comes before `1.0.5` but after
+    /// `1.1.0`. Requirements locked to a version still only match it.
+    ///
+    /// Candidates cached so far are dropped, as they may differ.
+    pub fn set_prerelease_friendly(&mut self, prerelease_friendly: bool) {
+        self.prerelease_friendly = prerelease_friendly;
+        self.drop_cached_candidates();
+    }
 
-        Ok(out)
+    /// Forget the candidates found so far, both those of `query` and the
+    /// dependency lists of `build_deps`, after an option that decides them
+    /// changed.
+    fn drop_cached_candidates(&mut self) {
+        self.registry_cache.clear();
+        self.summary_cache.clear();
     }
-}
 
-/// Returns the features we ended up using and
-/// all dependencies and the features we want from each of them.
-pub fn resolve_features<'b>(
-    parent: Option<PackageId>,
-    s: &'b Summary,
-    opts: &'b ResolveOpts,
-) -> ActivateResult<(HashSet<InternedString>, Vec<(Dependency, FeaturesSet)>)> {
-    // First, filter by dev-dependencies.
-    let deps = s.dependencies();
-    let deps = deps.iter().filter(|d| d.is_transitive() || opts.dev_deps);
+    /// Start recording every activation attempt and backtrack of the resolve
+    /// as a [`ResolutionTrace`], keeping at most `max_nodes` of them.
+    ///
+    /// This can take a lot of memory on a large graph, hence the cap.
+    pub fn record_trace(&mut self, max_nodes: usize) {
+        self.trace.get_or_insert_with(|| ResolutionTrace {
+            max_nodes,
+            ..ResolutionTrace::default()
+        });
+    }
 
-    let reqs = build_requirements(parent, s, opts)?;
-    let mut ret = Vec::new();
-    let default_dep = BTreeSet::new();
-    let mut valid_dep_names = HashSet::new();
+    /// The trace recorded since `record_trace` was called.
+    pub fn trace(&self) -> Option<&ResolutionTrace> {
+        self.trace.as_ref()
+    }
 
-    // Next, collect all actually enabled dependencies and their features.
-    for dep in deps {
-        // Skip optional dependencies, but not those enabled through a
-        // feature
-        if dep.is_optional() && !reqs.deps.contains_key(&dep.name_in_toml()) {
-            continue;
+    /// Records in the trace that activating `candidate` for a dependency of
+    /// `parent` failed because of a conflict with `conflict_with`, and that
+    /// the resolver is moving on to the next candidate.
+    pub fn trace_backtrack(
+        &mut self,
+        parent: PackageId,
+        candidate: PackageId,
+        conflict_with: PackageId,
+        reason: &ConflictReason,
+    ) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(
+                Some(parent),
+                candidate,
+                Vec::new(),
+                TraceOutcome::Backtracked {
+                    conflict: reason.to_serialized(conflict_with),
+                },
+            );
+        }
+    }
+
+    /// Start keeping a machine-readable record of every conflict found while
+    /// building the dependencies of a candidate.
+    pub fn record_conflicts(&mut self) {
+        self.conflicts.get_or_insert_with(Vec::new);
+    }
+
+    /// The conflicts recorded since `record_conflicts` was called.
+    pub fn recorded_conflicts(&self) -> &[SerializedConflict] {
+        self.conflicts.as_deref().unwrap_or_default()
+    }
+
+    /// Calls `observer` with each conflict found while building the
+    /// dependencies of a candidate, for example to learn which conflicts
+    /// come up together.
+    ///
+    /// The observer cannot influence the resolution, it only sees what the
+    /// resolver already decided.
+    pub fn set_conflict_observer(&mut self, observer: Option<Rc<dyn Fn(&ConflictReason)>>) {
+        self.conflict_observer = observer;
+    }
+
+    /// Calls `observer` with the original package and its replacement each
+    /// time a `[replace]` entry is applied, for logging the substitutions as
+    /// they happen instead of waiting for `replacement_report`.
+    ///
+    /// It is called once per replaced package, however many queries find
+    /// that package again, and not for the queries of `peek_candidates`.
+    pub fn set_replacement_observer(&mut self, observer: Option<Rc<dyn Fn(&PackageId, &Summary)>>) {
+        self.replacement_observer = observer;
+    }
+
+    /// How many queries so far sorted their candidates minimum versions
+    /// first, telling `first_minimal_version` apart from `minimal_versions`.
+    ///
+    /// Only queries that were worked out are counted, not cached answers.
+    pub fn minimal_ordering_counts(&self) -> MinimalOrderingCounts {
+        self.minimal_ordering_counts
+    }
+
+    /// Start keeping a log of every query made to the `registry` that
+    /// completed, with everything it returned before any filtering.
+    ///
+    /// Queries are logged in the order they are made. As results are cached,
+    /// a dependency is only queried again after it was `Pending`. The log can
+    /// be turned into a [`ReplayRegistry`] to run the same resolve offline.
+    pub fn record_queries(&mut self) {
+        self.queries.get_or_insert_with(Vec::new);
+    }
+
+    /// The queries recorded since `record_queries` was called.
+    pub fn recorded_queries(&self) -> &[(Dependency, Vec<Summary>)] {
+        self.queries.as_deref().unwrap_or_default()
+    }
+
+    /// The ready entries of the query cache, in a form serde can write out so
+    /// `import_registry_cache` can load them into another `RegistryQueryer`.
+    /// This lets a resolve be replayed without the registry it ran against.
+    ///
+    /// Pending entries are left out. Entries are sorted by dependency, so the
+    /// same cache always exports the same way.
+    pub fn export_registry_cache(&self) -> Vec<ExportedQuery> {
+        let mut out: Vec<_> = self
+            .registry_cache
+            .iter()
+            .filter_map(|((dep, first_minimal_version), candidates)| {
+                let Poll::Ready(candidates) = candidates else {
+                    return None;
+                };
+                Some(ExportedQuery {
+                    dep: ExportedDependency::new(dep),
+                    first_minimal_version: *first_minimal_version,
+                    candidates: candidates.iter().map(ExportedSummary::new).collect(),
+                })
+            })
+            .collect();
+        out.sort_by(|a, b| {
+            (&a.dep, a.first_minimal_version).cmp(&(&b.dep, b.first_minimal_version))
+        });
+        out
+    }
+
+    /// Loads the entries of an `export_registry_cache` into the query cache,
+    /// so that queries for them are answered without asking the `registry`.
+    ///
+    /// The candidates are used in the order they were exported in.
+    pub fn import_registry_cache(&mut self, queries: Vec<ExportedQuery>) -> CargoResult<()> {
+        for query in queries {
+            let dep = query.dep.to_dependency()?;
+            let candidates = query
+                .candidates
+                .into_iter()
+                .map(ExportedSummary::into_summary)
+                .collect::<CargoResult<Vec<_>>>()
+                .with_context(|| {
+                    format!(
+                        "failed to import the candidates of `{}`",
+                        dep.package_name()
+                    )
+                })?;
+            self.registry_cache.insert(
+                (dep, query.first_minimal_version),
+                Poll::Ready(Rc::new(candidates)),
+            );
+        }
+        Ok(())
+    }
+
+    /// Fills the query cache with the candidates of dependencies taken from a
+    /// lock file, so the first pass of a resolve does not have to wait on the
+    /// `registry` for them. Returns how many of the `entries` were used.
+    ///
+    /// The candidates of an entry are trusted to be all there is for its
+    /// dependency, as is the case for a dependency locked to an `=` version.
+    /// They go through the same checks and substitution as the answers of
+    /// the `registry` in `query`. An entry that `query` would not answer
+    /// from the `registry`, because the dependency is pinned, or that has a
+    /// candidate that does not pass, that a `[replace]` applies to, or that
+    /// `query` would report an error for, is left for `query` to answer. So
+    /// are the dependencies that are already cached.
+    pub fn warm_from_lock(&mut self, entries: &[(Dependency, Vec<Summary>)]) -> usize {
+        let mut warmed = 0;
+        for (dep, candidates) in entries {
+            if self.query_pinned(dep).is_some() {
+                continue;
+            }
+            let usable = candidates.iter().all(|s| {
+                dep.matches(s)
+                    && self.filtered_out(s).is_none()
+                    && !self
+                        .replacements
+                        .iter()
+                        .any(|(spec, _)| spec.matches(s.package_id()))
+            });
+            if !usable {
+                continue;
+            }
+            if self.forbid_ambiguous_sources {
+                let sources: BTreeSet<_> = candidates.iter().map(|s| s.source_id()).collect();
+                if sources.len() > 1 {
+                    continue;
+                }
+            }
+            let mut candidates = candidates.clone();
+            if self.substitute(&mut candidates).is_err() {
+                continue;
+            }
+            let mut used = false;
+            for first_minimal_version in [false, true] {
+                let key = (dep.clone(), first_minimal_version);
+                if matches!(self.registry_cache.get(&key), Some(Poll::Ready(_))) {
+                    continue;
+                }
+                let mut candidates = candidates.clone();
+                self.version_prefs.sort_summaries(
+                    &mut candidates,
+                    self.version_ordering(first_minimal_version),
+                    first_minimal_version,
+                );
+                self.registry_cache
+                    .insert(key, Poll::Ready(Rc::new(candidates)));
+                used = true;
+            }
+            if used {
+                self.candidate_counts.insert(
+                    dep.clone(),
+                    CandidateCounts {
+                        returned: candidates.len(),
+                        msrv_filtered: 0,
+                        survived: candidates.len(),
+                    },
+                );
+                warmed += 1;
+            }
+        }
+        warmed
+    }
+
+    /// Returns the time spent inside the `registry`, and the time `build_deps`
+    /// spent on everything else.
+    ///
+    /// Calling `query` directly only adds to the registry time.
+    pub fn timing_breakdown(&self) -> (Duration, Duration) {
+        (self.registry_time, self.compute_time)
+    }
+
+    /// Returns the time the `registry` took to answer `query`, summed per
+    /// package name.
+    ///
+    /// Queries answered from the cache are not timed.
+    pub fn query_latencies(&self) -> &HashMap<InternedString, Duration> {
+        &self.query_latencies
+    }
+
+    /// The feature problems found so far that did not reject a candidate.
+    ///
+    /// These may include packages that were tried and then backtracked over.
+    pub fn warnings(&self) -> &BTreeSet<RequirementWarning> {
+        &self.warnings
+    }
+
+    /// The candidates `query` skipped only because they need a newer rust than
+    /// `max_rust_version`, for each dependency that had any.
+    pub fn msrv_filtered(&self) -> &HashMap<Dependency, Vec<PackageId>> {
+        &self.msrv_filtered
+    }
+
+    /// The candidates `query` dropped because of `set_license_filter`, for
+    /// each dependency that had any, to report what was excluded.
+    pub fn license_filtered(&self) -> &HashMap<Dependency, Vec<PackageId>> {
+        &self.license_filtered
+    }
+
+    /// Tells why the `version` of the package `name` was not picked, from what
+    /// the queries made so far recorded. Meant to be asked after a resolve
+    /// about a version it did not select.
+    ///
+    /// The reasons are looked for in order: the `rust-version` ceiling, the
+    /// other filters, then the candidate lists. When several dependencies on
+    /// `name` were queried, the reason is given for one of them.
+    pub fn why_not(&self, name: InternedString, version: &Version) -> WhyNot {
+        let is_it = |pkg_id: &PackageId| pkg_id.name() == name && pkg_id.version() == version;
+        let find = |filtered: &HashMap<Dependency, Vec<PackageId>>| {
+            filtered
+                .iter()
+                .find(|(_, ids)| ids.iter().any(is_it))
+                .map(|(dep, _)| dep.clone())
+        };
+        if let Some(dep) = find(&self.msrv_filtered) {
+            return WhyNot::RustVersion(dep);
+        }
+        if let Some(dep) = find(&self.license_filtered).or_else(|| find(&self.snapshot_filtered)) {
+            return WhyNot::Filtered(dep);
+        }
+        for ((dep, _), candidates) in &self.registry_cache {
+            let Poll::Ready(candidates) = candidates else {
+                continue;
+            };
+            if let Some(pos) = candidates.iter().position(|s| is_it(&s.package_id())) {
+                return WhyNot::LostTiebreak {
+                    dependency: dep.clone(),
+                    ranked_ahead: candidates[..pos].iter().map(|s| s.package_id()).collect(),
+                };
+            }
+        }
+        WhyNot::NotRequested
+    }
+
+    /// How many candidates `query` found for `dep` and how many it kept, so an
+    /// empty list can be told apart from one where everything was filtered out.
+    pub fn candidate_counts(&self, dep: &Dependency) -> Option<CandidateCounts> {
+        self.candidate_counts.get(dep).copied()
+    }
+
+    pub fn reset_pending(&mut self) -> bool {
+        let mut all_ready = true;
+        self.registry_cache.retain(|_, r| {
+            if !r.is_ready() {
+                all_ready = false;
+            }
+            r.is_ready()
+        });
+        self.replacement_cache.retain(|_, r| {
+            if !r.is_ready() {
+                all_ready = false;
+            }
+            r.is_ready()
+        });
+        self.summary_cache.retain(|_, (_, r)| {
+            if !*r {
+                all_ready = false;
+            }
+            *r
+        });
+        all_ready
+    }
+
+    /// Ends the resolve: drops everything that is still pending, moves the
+    /// complete `build_deps` results out of the pending tracking, and shrinks
+    /// the caches to fit, so later lookups (e.g. from `cargo tree`) use as
+    /// little memory as possible.
+    ///
+    /// Only cached answers are expected afterwards. In debug builds, a
+    /// `query` or `build_deps` that would have to ask the registry panics.
+    pub fn finalize(&mut self) {
+        let mut finalized = self.finalized.take().unwrap_or_default();
+        finalized.extend(
+            mem::take(&mut self.summary_cache)
+                .into_iter()
+                .filter_map(|(key, (out, ready))| ready.then_some((key, out))),
+        );
+        finalized.shrink_to_fit();
+        self.finalized = Some(finalized);
+
+        self.registry_cache.retain(|_, r| r.is_ready());
+        self.registry_cache.shrink_to_fit();
+        self.replacement_cache.retain(|_, r| r.is_ready());
+        self.replacement_cache.shrink_to_fit();
+        self.msrv_filtered.shrink_to_fit();
+        self.license_filtered.shrink_to_fit();
+        self.snapshot_filtered.shrink_to_fit();
+        self.candidate_counts.shrink_to_fit();
+        self.used_replacements.shrink_to_fit();
+        self.matched_replacements.shrink_to_fit();
+        self.queried_overrides.shrink_to_fit();
+        self.query_latencies.shrink_to_fit();
+        self.pending_deps = Vec::new();
+    }
+
+    /// Forgets what `build_deps` worked out for `root` and every package
+    /// reachable from it through the cached dependencies, along with the
+    /// candidates queried for those dependencies, so only that part of the
+    /// graph is looked up again. Everything else stays cached.
+    pub fn invalidate_subtree(&mut self, root: PackageId) {
+        let mut by_package: HashMap<PackageId, Vec<&BuildDepsResult>> = HashMap::new();
+        for ((_, summary, _), (out, _)) in &self.summary_cache {
+            by_package
+                .entry(summary.package_id())
+                .or_default()
+                .push(out);
+        }
+
+        let mut packages = HashSet::new();
+        let mut deps = HashSet::new();
+        let mut stack = vec![root];
+        while let Some(pkg_id) = stack.pop() {
+            if !packages.insert(pkg_id) {
+                continue;
+            }
+            for out in by_package.get(&pkg_id).into_iter().flatten() {
+                for (dep, candidates, _, _) in out.1.iter() {
+                    deps.insert(dep.clone());
+                    stack.extend(candidates.iter().map(|s| s.package_id()));
+                }
+            }
+        }
+
+        self.summary_cache
+            .retain(|(_, summary, _), _| !packages.contains(&summary.package_id()));
+        if let Some(finalized) = &mut self.finalized {
+            finalized.retain(|(_, summary, _), _| !packages.contains(&summary.package_id()));
+        }
+        self.registry_cache
+            .retain(|(dep, _), _| !deps.contains(dep));
+    }
+
+    pub fn used_replacement_for(&self, p: PackageId) -> Option<(PackageId, PackageId)> {
+        self.used_replacements.get(&p).map(|r| (p, r.package_id()))
+    }
+
+    pub fn replacement_summary(&self, p: PackageId) -> Option<&Summary> {
+        self.used_replacements.get(&p)
+    }
+
+    /// The packages that matched a `[replace]` entry so far, each with the
+    /// dependency that was queried from the replacement source for it, sorted
+    /// by package.
+    ///
+    /// This is the requirement built from the entry, whether or not the
+    /// replacement then ended up being used.
+    pub fn queried_overrides(&self) -> Vec<(PackageId, &Dependency)> {
+        let mut out: Vec<_> = self
+            .queried_overrides
+            .iter()
+            .map(|(&id, dep)| (id, dep))
+            .collect();
+        out.sort_by_key(|&(id, _)| id);
+        out
+    }
+
+    /// Describes what became of each of the `[replace]` entries so far.
+    pub fn replacement_report(&self) -> ReplacementReport {
+        let mut outcomes: Vec<_> = self
+            .replacements
+            .iter()
+            .map(|(spec, _)| ReplacementOutcome {
+                spec: spec.clone(),
+                applied: Vec::new(),
+                same_source: Vec::new(),
+            })
+            .collect();
+        for (&id, &index) in &self.matched_replacements {
+            let outcome = &mut outcomes[index];
+            match self.used_replacements.get(&id) {
+                Some(replacement) => outcome.applied.push((id, replacement.package_id())),
+                None => outcome.same_source.push(id),
+            }
+        }
+        for outcome in &mut outcomes {
+            outcome.applied.sort();
+            outcome.same_source.sort();
+        }
+        ReplacementReport { outcomes }
+    }
+
+    /// Returns whether a version preference, rather than plain version
+    /// ordering, put `p` ahead of the other candidates for its package.
+    pub fn preference_influence(&self, p: PackageId) -> PreferenceInfluence {
+        self.version_prefs.influence(p)
+    }
+
+    /// Whether `dep` has at least one candidate, with the same pinned
+    /// summaries, filters and substitution as [`Self::query`] but without
+    /// sorting or caching the candidates.
+    ///
+    /// An answer already in the cache is used. Replacements are not looked
+    /// up, as they keep the version of the package they replace. The query is
+    /// not counted in the timings, nor in the query reports.
+    pub fn has_candidate(&mut self, dep: &Dependency) -> Poll<CargoResult<bool>> {
+        for first_minimal_version in [false, true] {
+            if let Some(Poll::Ready(out)) = self
+                .registry_cache
+                .get(&(dep.clone(), first_minimal_version))
+            {
+                return Poll::Ready(Ok(!out.is_empty()));
+            }
+        }
+        if let Some(pinned) = self.query_pinned(dep) {
+            return Poll::Ready(pinned.map(|_| true));
+        }
+
+        let mut matching = Vec::new();
+        let kind = self.query_kind;
+        let (queried, matches_version) = self.registry_query_dep(dep);
+        let ready = self.registry.query(&queried, kind, &mut |s| {
+            if matches_version(s.version()) {
+                matching.push(s);
+            }
+        });
+        if ready?.is_pending() {
+            return Poll::Pending;
+        }
+        matching.retain(|s| self.filtered_out(s).is_none());
+        Poll::Ready(
+            self.substitute(&mut matching)
+                .map(|()| !matching.is_empty()),
+        )
+    }
+
+    /// Queries the `registry` to return a list of candidates for `dep`.
+    ///
+    /// This method is the location where overrides are taken into account. If
+    /// any candidates are returned which match an override then the override is
+    /// applied by performing a second query for what the override should
+    /// return.
+    pub fn query(
+        &mut self,
+        dep: &Dependency,
+        first_minimal_version: bool,
+    ) -> Poll<CargoResult<Rc<Vec<Summary>>>> {
+        if self.is_cancelled() {
+            return Poll::Ready(Err(ResolveCancelled.into()));
+        }
+        let registry_cache_key = (dep.clone(), first_minimal_version);
+        if let Some(out) = self.registry_cache.get(&registry_cache_key).cloned() {
+            return out.map(Result::Ok);
+        }
+        debug_assert!(
+            self.finalized.is_none(),
+            "`query` for `{}` after `finalize`",
+            dep.package_name()
+        );
+        if let Some(pinned) = self.query_pinned(dep) {
+            let out = Rc::new(vec![pinned?]);
+            self.registry_cache
+                .insert(registry_cache_key, Poll::Ready(out.clone()));
+            return Poll::Ready(Ok(out));
+        }
+
+        let mut matching = Vec::new();
+        let mut ret = Vec::new();
+        let mut msrv_filtered = Vec::new();
+        let mut license_filtered = Vec::new();
+        let mut snapshot_filtered = Vec::new();
+        let mut recorded = self.queries.is_some().then(Vec::new);
+        let start = Instant::now();
+        let kind = self.query_kind;
+        let (queried, matches_version) = self.registry_query_dep(dep);
+        let ready = self.registry.query(&queried, kind, &mut |s| {
+            if let Some(recorded) = &mut recorded {
+                recorded.push(s.clone());
+            }
+            if matches_version(s.version()) {
+                matching.push(s);
+            }
+        });
+        let elapsed = start.elapsed();
+        self.registry_time += elapsed;
+        *self.query_latencies.entry(dep.package_name()).or_default() += elapsed;
+        let ready = ready?;
+        if ready.is_pending() {
+            self.registry_cache
+                .insert((dep.clone(), first_minimal_version), Poll::Pending);
+            return Poll::Pending;
+        }
+        let returned = matching.len();
+        for s in matching {
+            match self.filtered_out(&s) {
+                None => ret.push(s),
+                Some(Filtered::Snapshot) => snapshot_filtered.push(s.package_id()),
+                Some(Filtered::License) => license_filtered.push(s.package_id()),
+                Some(Filtered::RustVersion) => msrv_filtered.push(s.package_id()),
+            }
+        }
+        if let (Some(queries), Some(recorded)) = (&mut self.queries, recorded) {
+            queries.push((queried, recorded));
+        }
+        self.candidate_counts.insert(
+            dep.clone(),
+            CandidateCounts {
+                returned,
+                msrv_filtered: msrv_filtered.len(),
+                survived: ret.len(),
+            },
+        );
+        if !msrv_filtered.is_empty() {
+            self.msrv_filtered.insert(dep.clone(), msrv_filtered);
+        }
+        if !license_filtered.is_empty() {
+            self.license_filtered.insert(dep.clone(), license_filtered);
+        }
+        if !snapshot_filtered.is_empty() {
+            self.snapshot_filtered
+                .insert(dep.clone(), snapshot_filtered);
+        }
+        if self.forbid_ambiguous_sources {
+            let sources: BTreeSet<_> = ret.iter().map(|s| s.source_id()).collect();
+            if sources.len() > 1 {
+                return Poll::Ready(Err(AmbiguousSourceError {
+                    name: dep.package_name(),
+                    sources: sources.into_iter().collect(),
+                }
+                .into()));
+            }
+        }
+        // Without any `[replace]` entries there is nothing to look for, so
+        // the candidates are not even walked.
+        let replacements = self.replacements;
+        if !replacements.is_empty() {
+            for summary in ret.iter() {
+                let mut potential_matches =
+                    replacements
+                        .iter()
+                        .enumerate()
+                        .filter(|&(_, &(ref spec, ref dep))| {
+                            spec.matches(summary.package_id())
+                                && dep.version_req().matches(summary.version())
+                        });
+
+                let (index, &(ref spec, ref dep)) = match potential_matches.next() {
+                    None => continue,
+                    Some(replacement) => replacement,
+                };
+                debug!(
+                    "found an override for {} {}",
+                    dep.package_name(),
+                    dep.version_req()
+                );
+                self.queried_overrides
+                    .insert(summary.package_id(), dep.clone());
+
+                // The override may redirect a whole range of versions, in which case
+                // the replacement source provides a counterpart for each of them and
+                // we only want the one with the same version as `summary`.
+                let replaced = match self.query_replacement(dep)? {
+                    Poll::Ready(s) => s,
+                    Poll::Pending => {
+                        self.registry_cache
+                            .insert(registry_cache_key, Poll::Pending);
+                        return Poll::Pending;
+                    }
+                };
+                let mut summaries = replaced
+                    .iter()
+                    .filter(|s| s.version() == summary.version())
+                    .cloned();
+                let Some(s) = summaries.next() else {
+                    let available = self.override_versions_hint(dep, summary);
+                    return Poll::Ready(Err(anyhow::format_err!(
+                        "no matching package for override `{}` found\n\
+                         location searched: {}\n\
+                         version required: {}{}",
+                        spec,
+                        dep.source_id(),
+                        dep.version_req(),
+                        available
+                    )));
+                };
+                let summaries = summaries.collect::<Vec<_>>();
+                if !summaries.is_empty() {
+                    let bullets = summaries
+                        .iter()
+                        .map(|s| format!("  * {}", s.package_id()))
+                        .collect::<Vec<_>>();
+                    return Poll::Ready(Err(anyhow::anyhow!(
+                        "the replacement specification `{}` matched \
+                         multiple packages:\n  * {}\n{}",
+                        spec,
+                        s.package_id(),
+                        bullets.join("\n")
+                    )));
+                }
+
+                // Only summaries with the same version were kept above, and the
+                // dependency is hard-coded to have the same name. A source that
+                // answers with another name is broken, which is not worth a panic.
+                if s.name() != summary.name() {
+                    return Poll::Ready(Err(anyhow::format_err!(
+                        "override `{}` for `{}` was answered with `{}` by {}",
+                        spec,
+                        summary.package_id(),
+                        s.package_id(),
+                        self.registry.describe_source(dep.source_id())
+                    )));
+                }
+
+                if let Some(allowed) = &self.allowed_replacement_sources {
+                    if s.source_id() != summary.source_id() && !allowed.contains(&s.source_id()) {
+                        return Poll::Ready(Err(anyhow::format_err!(
+                            "override `{}` for `{}` comes from {}, \
+                             which is not an allowed source for replacements",
+                            spec,
+                            summary.package_id(),
+                            s.source_id()
+                        )));
+                    }
+                }
+
+                let replace = if s.source_id() == summary.source_id() {
+                    debug!("Preventing\n{:?}\nfrom replacing\n{:?}", summary, s);
+                    None
+                } else {
+                    Some(s)
+                };
+                let matched_spec = spec.clone();
+
+                // Make sure no duplicates
+                if let Some((_, &(ref spec, _))) = potential_matches.next() {
+                    return Poll::Ready(Err(anyhow::anyhow!(
+                        "overlapping replacement specifications found:\n\n  \
+                         * {}\n  * {}\n\nboth specifications match: {}",
+                        matched_spec,
+                        spec,
+                        summary.package_id()
+                    )));
+                }
+
+                for dep in summary.dependencies() {
+                    debug!("\t{} => {}", dep.package_name(), dep.version_req());
+                }
+                self.matched_replacements
+                    .insert(summary.package_id(), index);
+                if let Some(r) = replace {
+                    let id = summary.package_id();
+                    if let Some(observer) = &self.replacement_observer {
+                        if !self.used_replacements.contains_key(&id) {
+                            observer(&id, &r);
+                        }
+                    }
+                    self.used_replacements.insert(id, r);
+                }
+            }
+        }
+
+        if let Err(e) = self.substitute(&mut ret) {
+            return Poll::Ready(Err(e));
+        }
+
+        // When we attempt versions for a package we'll want to do so in a sorted fashion to pick
+        // the "best candidates" first. VersionPreferences implements this notion.
+        let ordering = self.version_ordering(first_minimal_version);
+        if self.minimal_versions {
+            self.minimal_ordering_counts.minimal_versions += 1;
+        } else if first_minimal_version {
+            self.minimal_ordering_counts.first_minimal_version += 1;
+        }
+        let first_version = first_minimal_version;
+        self.version_prefs
+            .sort_summaries(&mut ret, ordering, first_version);
+
+        let out = Poll::Ready(Rc::new(ret));
+
+        self.registry_cache.insert(registry_cache_key, out.clone());
+
+        out.map(Result::Ok)
+    }
+
+    /// Which of the filters of `query`, if any, leaves `s` out of the
+    /// candidates.
+    fn filtered_out(&self, s: &Summary) -> Option<Filtered> {
+        let in_snapshot = match (self.published_before, s.published()) {
+            (None, _) => true,
+            (Some(before), Some(published)) => published < before,
+            (Some(_), None) => self.include_unknown_published,
+        };
+        if !in_snapshot {
+            Some(Filtered::Snapshot)
+        } else if !accepts_license(s, &self.license_filter) {
+            Some(Filtered::License)
+        } else if !fits_rust_version(s, self.max_rust_version, self.assumed_rust_version) {
+            Some(Filtered::RustVersion)
+        } else {
+            None
+        }
+    }
+
+    /// Replaces `candidates` with what the substitution set with
+    /// `set_substitution` returns for them.
+    fn substitute(&self, candidates: &mut [Summary]) -> CargoResult<()> {
+        let Some(substitute) = &self.substitution else {
+            return Ok(());
+        };
+        for summary in candidates.iter_mut() {
+            let Some(s) = substitute(summary) else {
+                continue;
+            };
+            if s.name() != summary.name() || s.version() != summary.version() {
+                anyhow::bail!(
+                    "the substitution for `{}` is `{}`, \
+                     which does not have the same name and version",
+                    summary.package_id(),
+                    s.package_id()
+                );
+            }
+            *summary = s;
+        }
+        Ok(())
+    }
+
+    /// The dependency to ask the registry for the candidates of `dep`, and
+    /// which of the versions it answers with are candidates.
+    ///
+    /// A fuzzy query may return any version, and so does a prerelease-friendly
+    /// one since the registry itself leaves pre-releases out.
+    fn registry_query_dep(&self, dep: &Dependency) -> (Dependency, impl Fn(&Version) -> bool) {
+        let fuzzy = self.query_kind == QueryKind::Fuzzy;
+        let prerelease_friendly = self.prerelease_friendly;
+        let mut queried = dep.clone();
+        if prerelease_friendly && !dep.version_req().is_locked() {
+            queried.set_any_version();
+        }
+        let req = dep.version_req().clone();
+        let matches_version = move |version: &Version| {
+            if prerelease_friendly {
+                req.matches_prerelease(version)
+            } else {
+                !fuzzy || req.matches(version)
+            }
+        };
+        (queried, matches_version)
+    }
+
+    /// Returns the candidates for `dep` in the order `query` would, without
+    /// leaving anything behind in the caches of a resolve in progress.
+    ///
+    /// An answer that is already cached is reused. Otherwise the work is done
+    /// against empty caches and reports, so nothing is shared with or added to
+    /// the real ones, and no observer is told about it.
+    pub fn peek_candidates(
+        &mut self,
+        dep: &Dependency,
+        first_minimal_version: bool,
+    ) -> Poll<CargoResult<Vec<Summary>>> {
+        let key = (dep.clone(), first_minimal_version);
+        if let Some(Poll::Ready(out)) = self.registry_cache.get(&key) {
+            return Poll::Ready(Ok(out.to_vec()));
+        }
+        // Everything `query` writes to, put back once it is done.
+        let saved = (
+            mem::take(&mut self.registry_cache),
+            mem::take(&mut self.replacement_cache),
+            mem::take(&mut self.used_replacements),
+            mem::take(&mut self.matched_replacements),
+            mem::take(&mut self.queried_overrides),
+            self.replacement_observer.take(),
+            mem::take(&mut self.msrv_filtered),
+            mem::take(&mut self.license_filtered),
+            mem::take(&mut self.snapshot_filtered),
+            mem::take(&mut self.candidate_counts),
+            mem::take(&mut self.minimal_ordering_counts),
+            self.queries.take(),
+            mem::take(&mut self.query_latencies),
+            mem::take(&mut self.registry_time),
+        );
+        let out = self.query(dep, first_minimal_version);
+        (
+            self.registry_cache,
+            self.replacement_cache,
+            self.used_replacements,
+            self.matched_replacements,
+            self.queried_overrides,
+            self.replacement_observer,
+            self.msrv_filtered,
+            self.license_filtered,
+            self.snapshot_filtered,
+            self.candidate_counts,
+            self.minimal_ordering_counts,
+            self.queries,
+            self.query_latencies,
+            self.registry_time,
+        ) = saved;
+        out.map_ok(|candidates| candidates.to_vec())
+    }
+
+    /// Looks up what a replacement `dep` points at, sharing the result between
+    /// every candidate that is redirected to the same replacement.
+    fn query_replacement(&mut self, dep: &Dependency) -> Poll<CargoResult<Rc<Vec<Summary>>>> {
+        if let Some(out) = self.replacement_cache.get(dep).cloned() {
+            return out.map(Result::Ok);
+        }
+
+        let out = self.query_registry(dep)?.map(Rc::new);
+        self.replacement_cache.insert(dep.clone(), out.clone());
+        out.map(Result::Ok)
+    }
+
+    /// Lists the versions the source of the override `dep` does have, for the
+    /// error reported when none of them is the version of `summary`.
+    ///
+    /// Returns an empty string if the source has no versions at all, or is
+    /// not ready to answer, as the error is reported without waiting on it.
+    fn override_versions_hint(&mut self, dep: &Dependency, summary: &Summary) -> String {
+        const MAX_VERSIONS: usize = 10;
+        let mut any_version = dep.clone();
+        any_version.set_version_req(semver::VersionReq::STAR);
+        let mut versions = match self.query_unrecorded(&any_version) {
+            Poll::Ready(Ok(summaries)) => summaries
+                .iter()
+                .map(|s| s.version().clone())
+                .collect::<Vec<_>>(),
+            _ => return String::new(),
+        };
+        if versions.is_empty() {
+            return String::new();
+        }
+        versions.sort_unstable_by(|a, b| b.cmp(a));
+        versions.dedup();
+        let mut listed = versions
+            .iter()
+            .take(MAX_VERSIONS)
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        if versions.len() > MAX_VERSIONS {
+            listed.push_str(&format!(" and {} more", versions.len() - MAX_VERSIONS));
+        }
+        format!(
+            "\nversions available: {}\nversion needed: {}",
+            listed,
+            summary.version()
+        )
+    }
+
+    /// Asks the `registry` for everything matching `dep`, recording the
+    /// result if `record_queries` was called.
+    fn query_registry(&mut self, dep: &Dependency) -> Poll<CargoResult<Vec<Summary>>> {
+        let start = Instant::now();
+        let out = self.registry.query_vec(dep, QueryKind::Exact);
+        self.registry_time += start.elapsed();
+        let out = out?;
+        if let (Some(queries), Poll::Ready(summaries)) = (&mut self.queries, &out) {
+            queries.push((dep.clone(), summaries.clone()));
+        }
+        out.map(Result::Ok)
+    }
+
+    /// Asks the `registry` for the summaries `query` starts from for `dep`,
+    /// before any filtering, for diagnostics. Nothing is cached, recorded or
+    /// timed, as this is not a query of the resolve.
+    fn query_unrecorded(&mut self, dep: &Dependency) -> Poll<CargoResult<Vec<Summary>>> {
+        let (queried, matches_version) = self.registry_query_dep(dep);
+        let kind = self.query_kind;
+        let mut out = Vec::new();
+        let ready = self.registry.query(&queried, kind, &mut |s| {
+            if matches_version(s.version()) {
+                out.push(s);
+            }
+        });
+        ready.map_ok(|()| out)
+    }
+
+    /// Returns the candidates `query` finds for `dep`, binned by the source
+    /// they come from.
+    ///
+    /// Sources appear in the order of their first candidate, and each keeps
+    /// the order the resolver would try its candidates in.
+    pub fn query_grouped(
+        &mut self,
+        dep: &Dependency,
+    ) -> Poll<CargoResult<Vec<(SourceId, Vec<Summary>)>>> {
+        let candidates = match self.query(dep, false)? {
+            Poll::Ready(s) => s,
+            Poll::Pending => return Poll::Pending,
+        };
+        let mut groups: Vec<(SourceId, Vec<Summary>)> = Vec::new();
+        for summary in candidates.iter() {
+            let source_id = summary.source_id();
+            match groups.iter_mut().find(|(id, _)| *id == source_id) {
+                Some((_, summaries)) => summaries.push(summary.clone()),
+                None => groups.push((source_id, vec![summary.clone()])),
+            }
+        }
+        Poll::Ready(Ok(groups))
+    }
+
+    /// Returns the candidates `query` finds for `dep` that come from `source`,
+    /// in the order the resolver would try them.
+    ///
+    /// The registry is queried as usual and the other sources are only left
+    /// out afterwards, so the answer is shared with `query`. A candidate with
+    /// a `[replace]` entry is kept or left out by its own source, not by the
+    /// source of its replacement.
+    pub fn query_from_source(
+        &mut self,
+        dep: &Dependency,
+        source: SourceId,
+    ) -> Poll<CargoResult<Vec<Summary>>> {
+        self.query(dep, false).map_ok(|candidates| {
+            candidates
+                .iter()
+                .filter(|s| s.source_id() == source)
+                .cloned()
+                .collect()
+        })
+    }
+
+    /// Traces how `query` arrives at the candidates for `dep`, a dependency of
+    /// `parent`.
+    ///
+    /// The registry is consulted directly to see what it offers before any
+    /// filtering, and the final list is taken from `query` so it is exactly
+    /// what the resolver would try.
+    pub fn explain(
+        &mut self,
+        parent: Option<PackageId>,
+        dep: &Dependency,
+    ) -> Poll<CargoResult<QueryExplanation>> {
+        let first_minimal_version = false;
+        let registry_candidates = match self.query_unrecorded(dep)? {
+            Poll::Ready(s) => s,
+            Poll::Pending => return Poll::Pending,
+        };
+        let candidates = match self.query(dep, first_minimal_version)? {
+            Poll::Ready(s) => s,
+            Poll::Pending => return Poll::Pending,
+        };
+        let msrv_filtered = registry_candidates
+            .iter()
+            .filter(|s| !fits_rust_version(s, self.max_rust_version, self.assumed_rust_version))
+            .map(|s| s.package_id())
+            .collect();
+        Poll::Ready(Ok(QueryExplanation {
+            parent,
+            dep: dep.clone(),
+            registry_candidates,
+            msrv_filtered,
+            ordering: self.version_ordering(first_minimal_version),
+            candidates,
+        }))
+    }
+
+    /// Reorders the `candidates` of `dep` so that the ones that would bring
+    /// in fewer dependencies come first among those ranked the same by the
+    /// `VersionPreferences`. See `set_prefer_smaller_graph`.
+    fn smaller_graph_first(
+        &mut self,
+        dep: &Dependency,
+        candidates: &Rc<Vec<Summary>>,
+        features: &FeaturesSet,
+        opts: &ResolveOpts,
+        first_minimal_version: bool,
+    ) -> Rc<Vec<Summary>> {
+        if candidates.len() < 2 {
+            return Rc::clone(candidates);
+        }
+        let dep_opts = Rc::new(opts.for_dependency(RequestedFeatures::DepFeatures {
+            features: Rc::clone(features),
+            uses_default_features: dep.uses_default_features(),
+        }));
+        let mut counted: Vec<_> = candidates
+            .iter()
+            .map(|s| (self.dep_count(s, &dep_opts), s.clone()))
+            .collect();
+        let ordering = self.version_ordering(first_minimal_version);
+        // The sort is stable, so the version ordering is kept for the rest.
+        counted.sort_by(|(a_count, a), (b_count, b)| {
+            self.version_prefs
+                .compare_preference(a.package_id(), b.package_id(), ordering)
+                .then(a_count.cmp(b_count))
+        });
+        Rc::new(counted.into_iter().map(|(_, s)| s).collect())
+    }
+
+    /// How many dependencies `s` has when activated with `opts`. A candidate
+    /// that cannot be activated that way counts as having the most.
+    fn dep_count(&mut self, s: &Summary, opts: &Rc<ResolveOpts>) -> usize {
+        *self
+            .dep_counts
+            .entry((s.package_id(), Rc::clone(opts)))
+            .or_insert_with(|| {
+                resolve_features(None, s, opts).map_or(usize::MAX, |(_, deps, _)| deps.len())
+            })
+    }
+
+    fn version_ordering(&self, first_minimal_version: bool) -> VersionOrdering {
+        if self.oldest_published {
+            VersionOrdering::OldestPublished
+        } else if first_minimal_version || self.minimal_versions {
+            VersionOrdering::MinimumVersionsFirst
+        } else {
+            VersionOrdering::MaximumVersionsFirst
+        }
+    }
+
+    /// Picks a version for each direct dependency of `root` activated with
+    /// `opts`, without activating any of them, for a quick look at what a
+    /// resolve would start from.
+    ///
+    /// Each dependency gets its first candidate in the order `query` sorts
+    /// them. Nothing is checked past that: the dependencies of the picked
+    /// versions are not looked at, so a full resolve may well pick others.
+    /// As `root` is not activated either, the activation count, trace and
+    /// version limits are left alone.
+    ///
+    /// Returns `Pending` while the registry has no answer for some of the
+    /// dependencies, call it again after `block_until_ready` then.
+    pub fn resolve_direct_deps(
+        &mut self,
+        root: &Summary,
+        opts: &ResolveOpts,
+    ) -> Poll<CargoResult<Vec<(Dependency, Summary)>>> {
+        // Without a parent, every feature problem of `root` is fatal.
+        let (_, deps, _) = match resolve_features(None, root, opts) {
+            Ok(out) => out,
+            Err(ActivateError::Fatal(e)) => return Poll::Ready(Err(e)),
+            Err(e) => panic!("bad error from resolve_features: {e:?}"),
+        };
+        let cx = Context::new(false);
+        let (deps, pending) = match self.query_dep_candidates(&cx, root, deps, false) {
+            Ok(out) => out,
+            Err(e) => return Poll::Ready(Err(e)),
+        };
+        if !pending.is_empty() {
+            return Poll::Pending;
+        }
+        let picked = deps
+            .iter()
+            .map(|(dep, candidates, _, _)| match candidates.first() {
+                Some(s) => Ok((dep.clone(), s.clone())),
+                None => Err(anyhow::format_err!(
+                    "no matching package named `{}` found\n\
+                     location searched: {}\n\
+                     required by package `{}`",
+                    dep.package_name(),
+                    dep.source_id(),
+                    root.package_id()
+                )),
+            })
+            .collect();
+        Poll::Ready(picked)
+    }
+
+    /// Find out what dependencies will be added by activating `candidate`,
+    /// with features described in `opts`. Then look up in the `registry`
+    /// the candidates that will fulfil each of these dependencies, as it is the
+    /// next obvious question.
+    pub fn build_deps(
+        &mut self,
+        cx: &Context,
+        parent: Option<PackageId>,
+        candidate: &Summary,
+        opts: &Rc<ResolveOpts>,
+        first_minimal_version: bool,
+    ) -> ActivateResult<BuildDepsResult> {
+        if self.is_cancelled() {
+            return Err(ActivateError::Cancelled);
+        }
+        self.check_version_limit(cx, parent, candidate)?;
+        self.pending_deps.clear();
+        self.activations += 1;
+        if self
+            .slow_resolve
+            .as_ref()
+            .map_or(false, |&(threshold, _)| self.activations == threshold)
+        {
+            let warning = SlowResolveWarning {
+                activations: self.activations,
+                culprit: self.widest_dependency(),
+            };
+            if let Some((_, warn)) = &mut self.slow_resolve {
+                warn(&warning);
+            }
+        }
+        let start = Instant::now();
+        let registry_time = self.registry_time;
+        let out = self.build_deps_inner(cx, parent, candidate, opts, first_minimal_version);
+        if let (Some(trace), Ok(result)) = (&mut self.trace, &out) {
+            let features = match &opts.features {
+                RequestedFeatures::CliFeatures(cli) => {
+                    cli.features.iter().map(|f| f.to_string()).collect()
+                }
+                RequestedFeatures::DepFeatures { features, .. } => {
+                    features.iter().map(|f| f.to_string()).collect()
+                }
+            };
+            trace.push(
+                parent,
+                candidate.package_id(),
+                features,
+                TraceOutcome::Activated {
+                    dependencies: result.1.len(),
+                },
+            );
+        }
+        self.compute_time += start
+            .elapsed()
+            .saturating_sub(self.registry_time - registry_time);
+        out
+    }
+
+    /// Asks the registry for the dependencies of the most preferred candidate
+    /// of each of `deps`, as the resolver is likely to query them next. This
+    /// lets a registry that batches queries fetch them along with the current
+    /// ones.
+    ///
+    /// The registry is asked directly, so nothing is cached and the query
+    /// reports, timings and observers only see the queries the resolver
+    /// makes. Errors are ignored too, the query the resolver makes later
+    /// reports them if they still happen.
+    fn prefetch(&mut self, deps: &[DepInfo], first_minimal_version: bool) {
+        let next: Vec<Dependency> = deps
+            .iter()
+            .filter_map(|(_, candidates, _, _)| candidates.first())
+            .flat_map(|s| s.dependencies())
+            .filter(|dep| dep.is_transitive() && !dep.is_optional())
+            .filter(|dep| {
+                !self
+                    .registry_cache
+                    .contains_key(&((*dep).clone(), first_minimal_version))
+            })
+            .cloned()
+            .collect();
+        let kind = self.query_kind;
+        for dep in next {
+            let (queried, _) = self.registry_query_dep(&dep);
+            let _ = self.registry.query(&queried, kind, &mut |_| {});
+        }
+    }
+
+    /// Queries the candidates of each of `deps`, the dependencies of
+    /// `candidate`, in the order they are given. The dependencies the
+    /// `registry` has no answer for yet are returned separately.
+    ///
+    /// This only calls `query`, so unlike `build_deps` it does not count as
+    /// an activation nor show up in the trace, progress or version limits.
+    fn query_dep_candidates(
+        &mut self,
+        cx: &Context,
+        candidate: &Summary,
+        deps: Vec<(Dependency, FeaturesSet)>,
+        first_minimal_version: bool,
+    ) -> CargoResult<(Vec<DepInfo>, Vec<Dependency>)> {
+        let mut pending = Vec::new();
+        let deps = deps
+            .into_iter()
+            .filter_map(
+                |(dep, features)| match self.query(&dep, first_minimal_version) {
+                    Poll::Ready(Ok(candidates)) => {
+                        let kind = dep.kind();
+                        Some(Ok((dep, candidates, features, kind)))
+                    }
+                    Poll::Pending => {
+                        debug!(
+                            "dependency `{}` of {} is pending",
+                            dep.package_name(),
+                            candidate.package_id()
+                        );
+                        pending.push(dep);
+                        // we can ignore Pending deps, resolve will be repeatedly called
+                        // until there are none to ignore
+                        None
+                    }
+                    Poll::Ready(Err(e)) => Some(Err(e).with_context(|| {
+                        format!(
+                            "failed to get `{}` as a dependency of {}",
+                            dep.package_name(),
+                            describe_path_in_context(cx, &candidate.package_id()),
+                        )
+                    })),
+                },
+            )
+            .collect::<CargoResult<Vec<DepInfo>>>()?;
+        Ok((deps, pending))
+    }
+
+    fn build_deps_inner(
+        &mut self,
+        cx: &Context,
+        parent: Option<PackageId>,
+        candidate: &Summary,
+        opts: &Rc<ResolveOpts>,
+        first_minimal_version: bool,
+    ) -> ActivateResult<BuildDepsResult> {
+        // if we have calculated a result before, then we can just return it,
+        // as it is a "pure" query of its arguments. That is unless some of the
+        // queries were still pending, in which case the result is incomplete.
+        let key = (parent, candidate.clone(), Rc::clone(opts));
+        if let Some(out) = self.finalized.as_ref().and_then(|f| f.get(&key)) {
+            return Ok(out.clone());
+        }
+        if let Some((out, true)) = self.summary_cache.get(&key) {
+            return Ok(out.clone());
+        }
+        debug_assert!(
+            self.finalized.is_none(),
+            "`build_deps` for `{}` after `finalize`",
+            candidate.package_id()
+        );
+        // First, figure out our set of dependencies based on the requested set
+        // of features. This also calculates what features we're going to enable
+        // for our own dependencies.
+        let (used_features, deps, warnings) = match resolve_features(parent, candidate, opts) {
+            Ok(out) => out,
+            Err(e) => {
+                if let ActivateError::Conflict(p, reason) = &e {
+                    if let Some(conflicts) = &mut self.conflicts {
+                        conflicts.push(reason.to_serialized(*p));
+                    }
+                    if let Some(observer) = &self.conflict_observer {
+                        observer(reason);
+                    }
+                }
+                return Err(e);
+            }
+        };
+        self.warnings.extend(warnings);
+
+        // Next, transform all dependencies into a list of possible candidates
+        // which can satisfy that dependency.
+        let (mut deps, pending) =
+            self.query_dep_candidates(cx, candidate, deps, first_minimal_version)?;
+
+        if self.prefer_smaller_graph {
+            for (dep, candidates, features, _) in deps.iter_mut() {
+                *candidates = self.smaller_graph_first(
+                    dep,
+                    candidates,
+                    features,
+                    opts,
+                    first_minimal_version,
+                );
+            }
+        }
+
+        order_deps_by_constrainedness(&mut deps);
+        // A user supplied priority takes precedence over that, for when they
+        // know which dependencies prune the search the most. The sort is
+        // stable, so ties keep the candidate-count ordering.
+        if let Some(priority) = &self.dep_priority {
+            deps.sort_by_key(|(dep, _, _, _)| std::cmp::Reverse(priority(dep)));
+        }
+
+        if let Some(progress) = &mut self.build_deps_progress {
+            progress(candidate.package_id(), &deps, &pending);
+        }
+        self.pending_deps = pending.iter().map(|dep| dep.package_name()).collect();
+        if self.registry.capabilities().batch_queries {
+            self.prefetch(&deps, first_minimal_version);
+        }
+        let all_ready = pending.is_empty();
+
+        let out = Rc::new((used_features, Rc::new(deps)));
+
+        // If we succeed we add the result to the cache so we can use it again next time.
+        // We don't cache the failure cases as they don't impl Clone.
+        self.summary_cache.insert(key, (out.clone(), all_ready));
+
+        Ok(out)
+    }
+}
+
+/// The filter of [`RegistryQueryer::query`] that left a version out.
+enum Filtered {
+    Snapshot,
+    License,
+    RustVersion,
+}
+
+/// Whether `s` can be used with a rust no newer than `max`, taking `assumed`
+/// as the `rust-version` of a summary that does not declare one.
+fn fits_rust_version(
+    s: &Summary,
+    max: Option<PartialVersion>,
+    assumed: Option<PartialVersion>,
+) -> bool {
+    max.is_none() || s.rust_version().or(assumed) <= max
+}
+
+/// Decides which license expressions are allowed, and whether versions without
+/// a license are kept.
+type LicenseFilter<'a> = (Box<dyn Fn(&str) -> bool + 'a>, bool);
+
+fn accepts_license(s: &Summary, filter: &Option<LicenseFilter<'_>>) -> bool {
+    match (filter, s.license()) {
+        (None, _) => true,
+        (Some((accept, _)), Some(license)) => accept(&license),
+        (Some((_, include_unknown)), None) => *include_unknown,
+    }
+}
+
+/// Every activation attempt and backtrack of a resolve, as a tree, recorded
+/// with [`RegistryQueryer::record_trace`].
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ResolutionTrace {
+    /// The nodes in the order they were recorded, each pointing at the node
+    /// of the package that depends on it.
+    pub nodes: Vec<TraceNode>,
+    /// Whether nodes were left out for going over the cap.
+    pub truncated: bool,
+    #[serde(skip)]
+    max_nodes: usize,
+    /// The index of the latest node of each package, to link its dependencies to.
+    #[serde(skip)]
+    latest: HashMap<PackageId, usize>,
+}
+
+/// A single step of a [`ResolutionTrace`].
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceNode {
+    /// The index in [`ResolutionTrace::nodes`] of the node of the package
+    /// this is a dependency of, or `None` for a root or when that package
+    /// was not recorded.
+    pub parent: Option<usize>,
+    pub package_id: PackageId,
+    /// The features the package was asked for.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<String>,
+    pub outcome: TraceOutcome,
+}
+
+/// How a [`TraceNode`] ended.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum TraceOutcome {
+    /// The dependencies of the package were worked out.
+    Activated { dependencies: usize },
+    /// The package could not be used, so the next candidate was tried.
+    Backtracked { conflict: SerializedConflict },
+}
+
+impl ResolutionTrace {
+    fn push(
+        &mut self,
+        parent: Option<PackageId>,
+        package_id: PackageId,
+        features: Vec<String>,
+        outcome: TraceOutcome,
+    ) {
+        if self.nodes.len() >= self.max_nodes {
+            self.truncated = true;
+            return;
+        }
+        let parent = parent.and_then(|p| self.latest.get(&p).copied());
+        if let TraceOutcome::Activated { .. } = outcome {
+            self.latest.insert(package_id, self.nodes.len());
+        }
+        self.nodes.push(TraceNode {
+            parent,
+            package_id,
+            features,
+            outcome,
+        });
+    }
+}
+
+/// What became of each `[replace]` entry, as returned by
+/// [`RegistryQueryer::replacement_report`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplacementReport {
+    /// One outcome per entry, in the order the entries were given.
+    pub outcomes: Vec<ReplacementOutcome>,
+}
+
+/// What became of a single `[replace]` entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplacementOutcome {
+    pub spec: PackageIdSpec,
+    /// The packages the entry replaced, each with what replaced it.
+    pub applied: Vec<(PackageId, PackageId)>,
+    /// The packages the entry matched, but that the replacement source
+    /// answered with the very same package, so nothing was replaced.
+    pub same_source: Vec<PackageId>,
+}
+
+/// A summary of a [`ReplacementOutcome`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplacementStatus {
+    /// The entry matched no candidate.
+    Unused,
+    /// The entry replaced at least one package.
+    Applied,
+    /// The entry matched candidates, but only ones already from its source.
+    SameSource,
+}
+
+impl ReplacementOutcome {
+    pub fn status(&self) -> ReplacementStatus {
+        if !self.applied.is_empty() {
+            ReplacementStatus::Applied
+        } else if !self.same_source.is_empty() {
+            ReplacementStatus::SameSource
+        } else {
+            ReplacementStatus::Unused
+        }
+    }
+}
+
+/// A ready entry of the query cache, as made by
+/// [`RegistryQueryer::export_registry_cache`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedQuery {
+    pub dep: ExportedDependency,
+    pub first_minimal_version: bool,
+    /// The candidates for `dep`, in the order the resolver tries them.
+    pub candidates: Vec<ExportedSummary>,
+}
+
+/// A `Dependency` in a form serde can read back.
+///
+/// Artifact dependencies are exported as plain dependencies.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ExportedDependency {
+    pub name: InternedString,
+    pub source: SourceId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registry: Option<SourceId>,
+    /// `None` when no version requirement was given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub req: Option<String>,
+    /// The version the dependency is locked to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked: Option<Version>,
+    pub kind: DepKind,
+    #[serde(default)]
+    pub optional: bool,
+    #[serde(default)]
+    pub public: bool,
+    pub uses_default_features: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<InternedString>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<Platform>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rename: Option<InternedString>,
+}
+
+impl ExportedDependency {
+    pub fn new(dep: &Dependency) -> ExportedDependency {
+        let (req, locked) = match dep.version_req() {
+            OptVersionReq::Any => (None, None),
+            OptVersionReq::Req(req) => (Some(req.to_string()), None),
+            OptVersionReq::Locked(version, req) => (Some(req.to_string()), Some(version.clone())),
+        };
+        ExportedDependency {
+            name: dep.package_name(),
+            source: dep.source_id(),
+            registry: dep.registry_id(),
+            req,
+            locked,
+            kind: dep.kind(),
+            optional: dep.is_optional(),
+            public: dep.is_public(),
+            uses_default_features: dep.uses_default_features(),
+            features: dep.features().to_vec(),
+            target: dep.platform().cloned(),
+            rename: dep.explicit_name_in_toml(),
+        }
+    }
+
+    pub fn to_dependency(&self) -> CargoResult<Dependency> {
+        let mut dep = Dependency::parse(self.name, self.req.as_deref(), self.source)?;
+        if let Some(registry) = self.registry {
+            dep.set_registry_id(registry);
+        }
+        if let Some(version) = &self.locked {
+            let req = dep.version_req();
+            if !req.matches(version) {
+                anyhow::bail!(
+                    "dependency `{}` is locked to {}, which does not match `{}`",
+                    self.name,
+                    version,
+                    req
+                );
+            }
+            dep.lock_version(version);
+        }
+        dep.set_kind(self.kind)
+            .set_optional(self.optional)
+            .set_default_features(self.uses_default_features)
+            .set_features(self.features.iter().copied())
+            .set_platform(self.target.clone());
+        if self.public {
+            dep.set_public(true);
+        }
+        if let Some(rename) = self.rename {
+            dep.set_explicit_name_in_toml(rename);
+        }
+        Ok(dep)
+    }
+}
+
+/// A `Summary` in a form serde can read back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedSummary {
+    pub package_id: PackageId,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<ExportedDependency>,
+    /// The declared features, without the implicit ones of optional
+    /// dependencies.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub features: BTreeMap<InternedString, Vec<InternedString>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub links: Option<InternedString>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rust_version: Option<PartialVersion>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    /// When the version was published, in seconds since the unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<InternedString>,
+    /// The edition, like `"2021"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edition: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub deprecated_features: BTreeSet<InternedString>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclusive_feature_groups: Vec<BTreeSet<InternedString>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub feature_aliases: BTreeMap<InternedString, InternedString>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub feature_preconditions: BTreeMap<InternedString, BTreeSet<InternedString>>,
+}
+
+impl ExportedSummary {
+    pub fn new(s: &Summary) -> ExportedSummary {
+        let features = s
+            .features()
+            .iter()
+            .filter(|(name, _)| !s.implicit_features().contains(*name))
+            .map(|(name, values)| {
+                let values = values
+                    .iter()
+                    .map(|v| InternedString::new(&v.to_string()))
+                    .collect();
+                (*name, values)
+            })
+            .collect();
+        ExportedSummary {
+            package_id: s.package_id(),
+            dependencies: s
+                .dependencies()
+                .iter()
+                .map(ExportedDependency::new)
+                .collect(),
+            features,
+            links: s.links(),
+            rust_version: s.rust_version(),
+            checksum: s.checksum().map(str::to_owned),
+            published: s
+                .published()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+            license: s.license(),
+            edition: s.edition().map(|e| e.to_string()),
+            deprecated_features: s.deprecated_features().iter().copied().collect(),
+            exclusive_feature_groups: s.exclusive_feature_groups().to_vec(),
+            feature_aliases: s.feature_aliases().clone(),
+            feature_preconditions: s.feature_preconditions().clone(),
+        }
+    }
+
+    pub fn into_summary(self) -> CargoResult<Summary> {
+        let dependencies = self
+            .dependencies
+            .iter()
+            .map(ExportedDependency::to_dependency)
+            .collect::<CargoResult<Vec<_>>>()?;
+        let mut s = Summary::new(
+            self.package_id,
+            dependencies,
+            &self.features,
+            self.links,
+            self.rust_version,
+        )?;
+        if let Some(checksum) = self.checksum {
+            s.set_checksum(checksum);
+        }
+        if let Some(published) = self.published {
+            s.set_published(UNIX_EPOCH + Duration::from_secs(published));
+        }
+        if let Some(license) = self.license {
+            s.set_license(license);
+        }
+        if let Some(edition) = self.edition {
+            s.set_edition(edition.parse()?);
+        }
+        s.set_deprecated_features(self.deprecated_features);
+        s.set_exclusive_feature_groups(self.exclusive_feature_groups);
+        s.set_feature_aliases(self.feature_aliases);
+        s.set_feature_preconditions(self.feature_preconditions);
+        Ok(s)
+    }
+}
+
+/// A `Registry` that answers queries from a log made with
+/// [`RegistryQueryer::record_queries`], without touching any source.
+///
+/// A query that was not recorded is an error, so a replayed resolve fails
+/// loudly if it asks for anything the recorded one did not.
+pub struct ReplayRegistry {
+    queries: HashMap<Dependency, Vec<Summary>>,
+}
+
+impl ReplayRegistry {
+    pub fn new(queries: impl IntoIterator<Item = (Dependency, Vec<Summary>)>) -> ReplayRegistry {
+        ReplayRegistry {
+            queries: queries.into_iter().collect(),
+        }
+    }
+}
+
+impl Registry for ReplayRegistry {
+    fn query(
+        &mut self,
+        dep: &Dependency,
+        _kind: QueryKind,
+        f: &mut dyn FnMut(Summary),
+    ) -> Poll<CargoResult<()>> {
+        let Some(summaries) = self.queries.get(dep) else {
+            return Poll::Ready(Err(anyhow::format_err!(
+                "no recorded query for `{}` ({}) from {}",
+                dep.package_name(),
+                dep.version_req(),
+                dep.source_id()
+            )));
+        };
+        summaries.iter().cloned().for_each(f);
+        Poll::Ready(Ok(()))
+    }
+
+    fn describe_source(&self, source: SourceId) -> String {
+        source.to_string()
+    }
+
+    fn is_replaced(&self, _source: SourceId) -> bool {
+        false
+    }
+
+    fn block_until_ready(&mut self) -> CargoResult<()> {
+        Ok(())
+    }
+}
+
+/// What `build_deps` returns for a package: its enabled features and its
+/// dependencies with their candidates.
+pub type BuildDepsResult = Rc<(HashSet<InternedString>, Rc<Vec<DepInfo>>)>;
+
+/// How the result of `build_deps` for one package differs between two
+/// resolves, as returned by [`diff_build_deps_results`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct BuildDepsDiff {
+    /// Features enabled only in the second resolve.
+    pub added_features: BTreeSet<InternedString>,
+    /// Features enabled only in the first resolve.
+    pub removed_features: BTreeSet<InternedString>,
+    /// Dependencies, by the name used in the manifest, used only in the second resolve.
+    pub added_deps: BTreeSet<InternedString>,
+    /// Dependencies, by the name used in the manifest, used only in the first resolve.
+    pub removed_deps: BTreeSet<InternedString>,
+}
+
+/// Compares the `build_deps` results of two resolves, for example with
+/// different `ResolveOpts`, to show what a change of features does.
+///
+/// A package only found on one side has all of its features and dependencies
+/// added or removed. Packages without any difference are left out.
+pub fn diff_build_deps_results(
+    before: &HashMap<PackageId, BuildDepsResult>,
+    after: &HashMap<PackageId, BuildDepsResult>,
+) -> BTreeMap<PackageId, BuildDepsDiff> {
+    let empty = BuildDepsResult::default();
+    let dep_names = |result: &BuildDepsResult| -> BTreeSet<InternedString> {
+        result
+            .1
+            .iter()
+            .map(|(dep, _, _, _)| dep.name_in_toml())
+            .collect()
+    };
+    let mut diffs = BTreeMap::new();
+    for pkg_id in before.keys().chain(after.keys()) {
+        if diffs.contains_key(pkg_id) {
+            continue;
+        }
+        let before = before.get(pkg_id).unwrap_or(&empty);
+        let after = after.get(pkg_id).unwrap_or(&empty);
+        let (deps_before, deps_after) = (dep_names(before), dep_names(after));
+        let diff = BuildDepsDiff {
+            added_features: after.0.difference(&before.0).copied().collect(),
+            removed_features: before.0.difference(&after.0).copied().collect(),
+            added_deps: deps_after.difference(&deps_before).copied().collect(),
+            removed_deps: deps_before.difference(&deps_after).copied().collect(),
+        };
+        diffs.insert(*pkg_id, diff);
+    }
+    diffs.retain(|_, diff| *diff != BuildDepsDiff::default());
+    diffs
+}
+
+/// Orders the dependencies of a package the way the resolver tries them:
+/// those with fewer candidates first. This way if the dependency with only
+/// one candidate can't be resolved we don't have to do a bunch of work before
+/// we figure that out.
+///
+/// The sort is stable, so dependencies with as many candidates keep their
+/// order.
+pub fn order_deps_by_constrainedness(deps: &mut Vec<DepInfo>) {
+    deps.sort_by_key(|(_, candidates, _, _)| candidates.len());
+}
+
+/// The features of a package merged over all the edges leading to it, as
+/// returned by [`union_edge_features`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FeatureUnion {
+    /// Every feature requested by at least one dependent.
+    pub features: BTreeSet<InternedString>,
+    /// For each feature, the dependents that requested it, sorted.
+    pub requested_by: BTreeMap<InternedString, Vec<PackageId>>,
+}
+
+/// Merges the features requested on each edge to a package, given as its
+/// dependent and the `FeaturesSet` of that edge, into what the package is
+/// built with once features are unified.
+///
+/// This is what explains a unified feature set, like `cargo tree -e features`
+/// does, by telling which dependents asked for each feature.
+pub fn union_edge_features<'f>(
+    edges: impl IntoIterator<Item = (PackageId, &'f FeaturesSet)>,
+) -> FeatureUnion {
+    let mut union = FeatureUnion::default();
+    for (dependent, features) in edges {
+        for &feature in features.iter() {
+            union.features.insert(feature);
+            union
+                .requested_by
+                .entry(feature)
+                .or_default()
+                .push(dependent);
+        }
+    }
+    for dependents in union.requested_by.values_mut() {
+        dependents.sort();
+        dependents.dedup();
+    }
+    union
+}
+
+/// Returns the features we ended up using,
+/// all dependencies and the features we want from each of them,
+/// and the problems that were only warned about.
+pub fn resolve_features<'b>(
+    parent: Option<PackageId>,
+    s: &'b Summary,
+    opts: &'b ResolveOpts,
+) -> ActivateResult<(
+    HashSet<InternedString>,
+    Vec<(Dependency, FeaturesSet)>,
+    Vec<RequirementWarning>,
+)> {
+    resolve_features_reporting_skipped(parent, s, opts, None)
+}
+
+/// Like [`resolve_features`], also adding to `skipped`, when given, the names
+/// of the optional dependencies that were left out because nothing enabled
+/// them, to tell users what they could turn on.
+pub fn resolve_features_reporting_skipped<'b>(
+    parent: Option<PackageId>,
+    s: &'b Summary,
+    opts: &'b ResolveOpts,
+    mut skipped: Option<&mut BTreeSet<InternedString>>,
+) -> ActivateResult<(
+    HashSet<InternedString>,
+    Vec<(Dependency, FeaturesSet)>,
+    Vec<RequirementWarning>,
+)> {
+    // First, filter by dev-dependencies.
+    let deps = s.dependencies();
+    let deps = deps.iter().filter(|d| d.is_transitive() || opts.dev_deps);
+
+    if opts.all_deps_superset {
+        let mut dep_features: HashMap<InternedString, BTreeSet<InternedString>> = HashMap::new();
+        for fv in s.features().values().flatten() {
+            if let FeatureValue::DepFeature {
+                dep_name,
+                dep_feature,
+                ..
+            } = fv.without_cfg()
+            {
+                dep_features
+                    .entry(*dep_name)
+                    .or_default()
+                    .insert(*dep_feature);
+            }
+        }
+        let ret = deps
+            .map(|dep| {
+                let mut features = dep_features
+                    .get(&dep.name_in_toml())
+                    .cloned()
+                    .unwrap_or_default();
+                features.extend(dep.features().iter().copied());
+                (dep.clone(), Rc::new(features))
+            })
+            .collect();
+        return Ok((s.features().keys().copied().collect(), ret, Vec::new()));
+    }
+
+    // Most leaf crates have no features and no optional dependencies. With
+    // nothing requested there is nothing for `build_requirements` to enable,
+    // so every dependency is used with exactly the features it declares.
+    if s.features().is_empty()
+        && !opts.features.has_requested_features()
+        && !s.dependencies().iter().any(|d| d.is_optional())
+        && opts.extra_features(s.name()).is_none()
+    {
+        let ret = deps
+            .map(|dep| {
+                let features: BTreeSet<_> = dep.features().iter().copied().collect();
+                (dep.clone(), Rc::new(features))
+            })
+            .collect();
+        return Ok((HashSet::new(), ret, Vec::new()));
+    }
+
+    let reqs = build_requirements(parent, s, opts)?;
+    let mut ret = Vec::new();
+    let default_dep = DepRequirements::default();
+    let mut valid_dep_names = HashSet::new();
+
+    // Next, collect all actually enabled dependencies and their features.
+    for dep in deps {
+        // Skip optional dependencies, but not those enabled through a
+        // feature, unless they were all asked for
+        if dep.is_optional()
+            && !opts.all_optional_deps
+            && !reqs.deps.contains_key(&dep.name_in_toml())
+        {
+            if let Some(skipped) = &mut skipped {
+                skipped.insert(dep.name_in_toml());
+            }
+            continue;
+        }
+        valid_dep_names.insert(dep.name_in_toml());
+        // So we want this dependency. Move the features we want from
+        // `feature_deps` to `ret` and register ourselves as using this
+        // name.
+        let dep_reqs = reqs.deps.get(&dep.name_in_toml()).unwrap_or(&default_dep);
+        let mut base = dep_reqs.features.clone();
+        base.extend(dep.features().iter());
+        // An optional dependency only enabled by `cfg(...):` values is only
+        // used on the targets they match.
+        let dep = match &dep_reqs.platform {
+            Some(cfg) if dep.is_optional() && !opts.all_optional_deps => {
+                restrict_platform(dep, cfg)
+            }
+            _ => dep.clone(),
+        };
+        ret.push((dep, Rc::new(base)));
+    }
+
+    // This is a special case for command-line `--features
+    // dep_name/feat_name` where `dep_name` does not exist. All other
+    // validation is done either in `build_requirements` or
+    // `build_feature_map`.
+    if parent.is_none() {
+        for dep_name in reqs.deps.keys() {
+            if !valid_dep_names.contains(dep_name) {
+                let e = RequirementError::MissingDependency(*dep_name);
+                return Err(e.into_activate_error(parent, s));
+            }
+        }
+    }
+
+    Ok((reqs.features, ret, reqs.warnings))
+}
+
+/// Returns `dep` limited to the targets matching `cfg`, on top of the
+/// platform it is already declared for.
+fn restrict_platform(dep: &Dependency, cfg: &CfgExpr) -> Dependency {
+    let platform = match dep.platform() {
+        None => Platform::Cfg(cfg.clone()),
+        Some(Platform::Cfg(declared)) => {
+            Platform::Cfg(CfgExpr::All(vec![declared.clone(), cfg.clone()]))
+        }
+        // A target name is already narrower than anything `cfg` could add
+        // without knowing the target.
+        Some(Platform::Name(name)) => Platform::Name(name.clone()),
+    };
+    let mut dep = dep.clone();
+    dep.set_platform(Some(platform));
+    dep
+}
+
+/// Takes requested features for a single package from the input `ResolveOpts` and
+/// recurses to find all requested features, dependencies and requested
+/// dependency features in a `Requirements` object, returning it to the resolver.
+fn build_requirements<'a, 'b: 'a>(
+    parent: Option<PackageId>,
+    s: &'a Summary,
+    opts: &'b ResolveOpts,
+) -> ActivateResult<Requirements<'a>> {
+    let mut reqs = Requirements::new(s, opts);
+
+    let handle_default = |uses_default_features, reqs: &mut Requirements<'_>| {
+        if uses_default_features
+            && !opts.no_default_features
+            && s.features().contains_key("default")
+        {
+            reqs.from_default_features = true;
+            let result = reqs.require_feature(InternedString::new("default"));
+            reqs.from_default_features = false;
+            if let Err(e) = result {
+                return Err(e.into_activate_error(parent, s));
+            }
+        }
+        Ok(())
+    };
+
+    match &opts.features {
+        RequestedFeatures::CliFeatures(CliFeatures {
+            features,
+            all_features,
+            uses_default_features,
+        }) => {
+            if *all_features {
+                for key in s.features().keys() {
+                    if let Err(e) = reqs.require_feature(*key) {
+                        return Err(e.into_activate_error(parent, s));
+                    }
+                }
+            }
+
+            for fv in features.iter() {
+                if let FeatureValue::Feature(feature) = fv {
+                    reqs.check_deprecated(*feature, parent);
+                }
+                if let Err(e) = reqs.require_value(fv) {
+                    return Err(e.into_activate_error(parent, s));
+                }
+            }
+            handle_default(*uses_default_features, &mut reqs)?;
+        }
+        RequestedFeatures::DepFeatures {
+            features,
+            uses_default_features,
+        } => {
+            for feature in features.iter() {
+                reqs.check_deprecated(*feature, parent);
+                if let Err(e) = reqs.require_feature(*feature) {
+                    return Err(e.into_activate_error(parent, s));
+                }
+            }
+            handle_default(*uses_default_features, &mut reqs)?;
+        }
+    }
+
+    if let Some(extra) = opts.extra_features(s.name()) {
+        for feature in extra.features.iter() {
+            if let Err(e) = reqs.require_feature(*feature) {
+                return Err(e.into_activate_error(parent, s));
+            }
+        }
+    }
+
+    if let Err(e) = reqs.check_preconditions() {
+        return Err(e.into_activate_error(parent, s));
+    }
+
+    Ok(reqs)
+}
+
+/// Returns the command-line features in `opts` that have no effect on `s`,
+/// because the other requested features (or `default`) already imply them.
+///
+/// This only observes `build_requirements`, rebuilding the requirements
+/// without each feature in turn and comparing the result.
+pub fn redundant_cli_features(
+    parent: Option<PackageId>,
+    s: &Summary,
+    opts: &ResolveOpts,
+) -> ActivateResult<Vec<FeatureValue>> {
+    let RequestedFeatures::CliFeatures(cli_features) = &opts.features else {
+        return Ok(Vec::new());
+    };
+    let full = build_requirements(parent, s, opts)?;
+    let mut redundant = Vec::new();
+    for fv in cli_features.features.iter() {
+        let mut features = (*cli_features.features).clone();
+        features.remove(fv);
+        let without = ResolveOpts {
+            features: RequestedFeatures::CliFeatures(CliFeatures {
+                features: Rc::new(features),
+                ..cli_features.clone()
+            }),
+            ..opts.clone()
+        };
+        let reqs = build_requirements(parent, s, &without)?;
+        if reqs.features == full.features && reqs.deps == full.deps {
+            redundant.push(fv.clone());
+        }
+    }
+    Ok(redundant)
+}
+
+/// Why a feature of a package is enabled, see [`feature_origins`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeatureOrigin {
+    /// Requested, or enabled by a requested feature.
+    Explicit,
+    /// Only enabled because the default features were used, so turning off
+    /// `default-features` would drop it.
+    DefaultFeatures,
+}
+
+/// Tells, for each feature enabled on `s` with `opts`, whether it was asked
+/// for or only comes in through the default features.
+pub fn feature_origins(
+    parent: Option<PackageId>,
+    s: &Summary,
+    opts: &ResolveOpts,
+) -> ActivateResult<BTreeMap<InternedString, FeatureOrigin>> {
+    let reqs = build_requirements(parent, s, opts)?;
+    Ok(reqs
+        .features
+        .iter()
+        .map(|&feature| {
+            let origin = if reqs.default_features_only.contains(&feature) {
+                FeatureOrigin::DefaultFeatures
+            } else {
+                FeatureOrigin::Explicit
+            };
+            (feature, origin)
+        })
+        .collect())
+}
+
+/// What a command-line feature did for a package, see [`cli_feature_uses`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CliFeatureUse {
+    /// The package has no such feature or dependency.
+    Missing,
+    /// It exists, but the other requested features already imply it.
+    Redundant,
+    /// It enabled something that would not be enabled without it.
+    Exercised,
+}
+
+/// Classifies each command-line feature in `opts` by what it does for `s`,
+/// telling apart features that do not exist from those that have no effect.
+///
+/// Features that do not exist are left out when looking for redundant ones,
+/// so a typo does not hide what the other features do.
+pub fn cli_feature_uses(
+    parent: Option<PackageId>,
+    s: &Summary,
+    opts: &ResolveOpts,
+) -> ActivateResult<Vec<(FeatureValue, CliFeatureUse)>> {
+    let RequestedFeatures::CliFeatures(cli_features) = &opts.features else {
+        return Ok(Vec::new());
+    };
+    let has_dep = |name: InternedString| s.dependencies().iter().any(|d| d.name_in_toml() == name);
+    let exists = |fv: &FeatureValue| match fv.without_cfg() {
+        FeatureValue::Feature(feat) => {
+            s.features().contains_key(feat) || s.feature_aliases().contains_key(feat)
+        }
+        FeatureValue::Dep { dep_name } | FeatureValue::DepFeature { dep_name, .. } => {
+            has_dep(*dep_name)
+        }
+        FeatureValue::TargetFeature { .. } => false,
+    };
+    let existing: BTreeSet<_> = cli_features
+        .features
+        .iter()
+        .filter(|fv| exists(fv))
+        .cloned()
+        .collect();
+    let existing_opts = ResolveOpts {
+        features: RequestedFeatures::CliFeatures(CliFeatures {
+            features: Rc::new(existing),
+            ..cli_features.clone()
+        }),
+        ..opts.clone()
+    };
+    let redundant = redundant_cli_features(parent, s, &existing_opts)?;
+    Ok(cli_features
+        .features
+        .iter()
+        .map(|fv| {
+            let kind = if !exists(fv) {
+                CliFeatureUse::Missing
+            } else if redundant.contains(fv) {
+                CliFeatureUse::Redundant
+            } else {
+                CliFeatureUse::Exercised
+            };
+            (fv.clone(), kind)
+        })
+        .collect())
+}
+
+/// Returns every feature of `s` that the command-line `features` would
+/// enable, along with the inputs that could not be used and why.
+///
+/// Unlike a resolve, this does not stop at the first bad input, so it can
+/// back shell completion and interactive feature pickers.
+pub fn features_from_cli(
+    s: &Summary,
+    features: &[String],
+    uses_default_features: bool,
+) -> (HashSet<InternedString>, Vec<(String, RequirementError)>) {
+    let opts = ResolveOpts::new(
+        false,
+        RequestedFeatures::CliFeatures(CliFeatures::new_all(false)),
+    );
+    let mut reqs = Requirements::new(s, &opts);
+    let mut errors = Vec::new();
+    for feature in features {
+        let fv = FeatureValue::new(InternedString::new(feature));
+        if let FeatureValue::Dep { dep_name } | FeatureValue::DepFeature { dep_name, .. } = fv {
+            if !s
+                .dependencies()
+                .iter()
+                .any(|d| d.name_in_toml() == dep_name)
+            {
+                errors.push((
+                    feature.clone(),
+                    RequirementError::MissingDependency(dep_name),
+                ));
+                continue;
+            }
+        }
+        // A bad input must not leave behind what it enabled before failing.
+        let before = (reqs.features.clone(), reqs.deps.clone());
+        if let Err(e) = reqs.require_value(&fv) {
+            (reqs.features, reqs.deps) = before;
+            errors.push((feature.clone(), e));
+        }
+    }
+    if uses_default_features && s.features().contains_key("default") {
+        if let Err(e) = reqs.require_feature(InternedString::new("default")) {
+            errors.push(("default".to_string(), e));
+        }
+    }
+    (reqs.features, errors)
+}
+
+/// Returns every problem in the feature table of `s`, each with the feature
+/// that lists it, rather than stopping at the first one like a resolve does.
+///
+/// Every value of every feature is checked, one feature at a time, so this
+/// is as if `--all-features` was resolved without one problem hiding the
+/// next. A problem reached through several features is reported once.
+pub fn requirement_errors(s: &Summary) -> Vec<(InternedString, RequirementError)> {
+    let opts = ResolveOpts::new(
+        false,
+        RequestedFeatures::CliFeatures(CliFeatures::new_all(false)),
+    );
+    let mut errors: Vec<(InternedString, RequirementError)> = Vec::new();
+    for (feature, values) in s.features() {
+        let mut reqs = Requirements::new(s, &opts);
+        reqs.features.insert(*feature);
+        for fv in values {
+            let result = match fv {
+                FeatureValue::Feature(f) if f == feature => Err(RequirementError::Cycle(*f)),
+                _ => {
+                    // Keep going from where things were before the bad value.
+                    let before = (reqs.features.clone(), reqs.deps.clone());
+                    let result = reqs.require_value(fv);
+                    if result.is_err() {
+                        (reqs.features, reqs.deps) = before;
+                    }
+                    result
+                }
+            };
+            if let Err(e) = result {
+                if !errors.iter().any(|(_, seen)| *seen == e) {
+                    errors.push((*feature, e));
+                }
+            }
         }
-        valid_dep_names.insert(dep.name_in_toml());
-        // So we want this dependency. Move the features we want from
-        // `feature_deps` to `ret` and register ourselves as using this
-        // name.
-        let mut base = reqs
-            .deps
-            .get(&dep.name_in_toml())
-            .unwrap_or(&default_dep)
-            .clone();
-        base.extend(dep.features().iter());
-        ret.push((dep.clone(), Rc::new(base)));
     }
+    errors
+}
 
-    // This is a special case for command-line `--features
-    // dep_name/feat_name` where `dep_name` does not exist. All other
-    // validation is done either in `build_requirements` or
-    // `build_feature_map`.
-    if parent.is_none() {
-        for dep_name in reqs.deps.keys() {
-            if !valid_dep_names.contains(dep_name) {
-                let e = RequirementError::MissingDependency(*dep_name);
-                return Err(e.into_activate_error(parent, s));
+/// Returns the smallest set of features of `s` that activates its dependency
+/// `dep_name`, or `None` if no combination of features does.
+///
+/// Enabling more features never deactivates a dependency, so if any set of
+/// features activates it, a single feature does, along with the preconditions
+/// it needs. Of those, the one enabling the fewest other features is picked.
+/// A required dependency needs none.
+pub fn minimal_features_enabling_dep(
+    s: &Summary,
+    dep_name: InternedString,
+) -> Option<Vec<InternedString>> {
+    // The features enabled by `features`, if they activate the dependency.
+    let enabled_with = |features: &[InternedString]| {
+        let opts = ResolveOpts::new(
+            false,
+            RequestedFeatures::CliFeatures(CliFeatures {
+                features: Rc::new(features.iter().map(|f| FeatureValue::Feature(*f)).collect()),
+                all_features: false,
+                uses_default_features: false,
+            }),
+        );
+        let (enabled, deps, _) = resolve_features(None, s, &opts).ok()?;
+        deps.iter()
+            .any(|(dep, _)| dep.name_in_toml() == dep_name)
+            .then_some(enabled.len())
+    };
+
+    // `feature` along with the preconditions it ends up needing, if those
+    // can be enabled together.
+    let with_preconditions = |feature: InternedString| {
+        let opts = ResolveOpts::new(
+            false,
+            RequestedFeatures::CliFeatures(CliFeatures::new_all(false)),
+        );
+        let mut features = BTreeSet::from([feature]);
+        loop {
+            let mut reqs = Requirements::new(s, &opts);
+            for feature in &features {
+                reqs.require_feature(*feature).ok()?;
+            }
+            match reqs.check_preconditions() {
+                Ok(()) => return Some(features.into_iter().collect::<Vec<_>>()),
+                Err(RequirementError::UnmetPrecondition(_, required)) => {
+                    features.insert(required);
+                }
+                Err(_) => return None,
             }
         }
+    };
+
+    if enabled_with(&[]).is_some() {
+        return Some(Vec::new());
     }
+    s.features()
+        .keys()
+        .filter_map(|feature| {
+            let features = with_preconditions(*feature)?;
+            Some((enabled_with(&features)?, features))
+        })
+        .min()
+        .map(|(_, features)| features)
+}
 
-    Ok((reqs.into_features(), ret))
+/// The features and dependencies of a package enabled by a selection of its
+/// features, kept up to date as features are toggled one at a time.
+///
+/// Each selected feature keeps what it enables on its own, so adding a feature
+/// only walks the features it enables, and removing one only merges what the
+/// remaining ones enable, without walking the feature table again. A feature
+/// enabled by several selected features stays enabled until all of them are
+/// removed.
+#[derive(Debug)]
+pub struct RequirementsBuilder<'a> {
+    summary: &'a Summary,
+    opts: ResolveOpts,
+    /// What each selected feature enables on its own.
+    selected: BTreeMap<InternedString, EnabledByFeature>,
+    features: HashSet<InternedString>,
+    deps: HashMap<InternedString, BTreeSet<InternedString>>,
 }
 
-/// Takes requested features for a single package from the input `ResolveOpts` and
-/// recurses to find all requested features, dependencies and requested
-/// dependency features in a `Requirements` object, returning it to the resolver.
-fn build_requirements<'a, 'b: 'a>(
-    parent: Option<PackageId>,
-    s: &'a Summary,
-    opts: &'b ResolveOpts,
-) -> ActivateResult<Requirements<'a>> {
-    let mut reqs = Requirements::new(s);
+#[derive(Debug)]
+struct EnabledByFeature {
+    features: HashSet<InternedString>,
+    deps: HashMap<InternedString, BTreeSet<InternedString>>,
+}
 
-    let handle_default = |uses_default_features, reqs: &mut Requirements<'_>| {
-        if uses_default_features && s.features().contains_key("default") {
-            if let Err(e) = reqs.require_feature(InternedString::new("default")) {
-                return Err(e.into_activate_error(parent, s));
-            }
+impl<'a> RequirementsBuilder<'a> {
+    /// Starts with no feature selected, not even `default`.
+    pub fn new(summary: &'a Summary) -> RequirementsBuilder<'a> {
+        RequirementsBuilder {
+            summary,
+            opts: ResolveOpts::new(
+                false,
+                RequestedFeatures::CliFeatures(CliFeatures::new_all(false)),
+            ),
+            selected: BTreeMap::new(),
+            features: HashSet::new(),
+            deps: HashMap::new(),
         }
-        Ok(())
-    };
+    }
 
-    match &opts.features {
-        RequestedFeatures::CliFeatures(CliFeatures {
-            features,
-            all_features,
-            uses_default_features,
-        }) => {
-            if *all_features {
-                for key in s.features().keys() {
-                    if let Err(e) = reqs.require_feature(*key) {
-                        return Err(e.into_activate_error(parent, s));
-                    }
-                }
+    /// Selects `feature`, enabling everything it enables.
+    ///
+    /// Nothing changes if that fails, including when it enables a feature
+    /// that is mutually exclusive with one already enabled, or one whose
+    /// precondition neither it nor the selected features enable, so
+    /// preconditions have to be selected first.
+    pub fn add_feature(&mut self, feature: InternedString) -> Result<(), RequirementError> {
+        if self.selected.contains_key(&feature) {
+            return Ok(());
+        }
+        let mut reqs = Requirements::new(self.summary, &self.opts);
+        reqs.require_feature(feature)?;
+        if let Some((feat, required)) = unmet_precondition(self.summary, |f| {
+            self.features.contains(f) || reqs.features.contains(f)
+        }) {
+            return Err(RequirementError::UnmetPrecondition(feat, required));
+        }
+        for group in self.summary.exclusive_feature_groups() {
+            let new = group.iter().find(|f| reqs.features.contains(*f));
+            let old = group
+                .iter()
+                .find(|f| self.features.contains(*f) && !reqs.features.contains(*f));
+            if let (Some(new), Some(old)) = (new, old) {
+                return Err(RequirementError::MutuallyExclusive(*old, *new));
             }
+        }
+        let enabled = EnabledByFeature {
+            features: reqs.features,
+            deps: reqs
+                .deps
+                .into_iter()
+                .map(|(dep, dep_reqs)| (dep, dep_reqs.features))
+                .collect(),
+        };
+        merge_enabled(&mut self.features, &mut self.deps, &enabled);
+        self.selected.insert(feature, enabled);
+        Ok(())
+    }
 
-            for fv in features.iter() {
-                if let Err(e) = reqs.require_value(fv) {
-                    return Err(e.into_activate_error(parent, s));
-                }
-            }
-            handle_default(*uses_default_features, &mut reqs)?;
+    /// Unselects `feature`, returning whether it was selected.
+    pub fn remove_feature(&mut self, feature: InternedString) -> bool {
+        if self.selected.remove(&feature).is_none() {
+            return false;
         }
-        RequestedFeatures::DepFeatures {
-            features,
-            uses_default_features,
-        } => {
-            for feature in features.iter() {
-                if let Err(e) = reqs.require_feature(*feature) {
-                    return Err(e.into_activate_error(parent, s));
-                }
-            }
-            handle_default(*uses_default_features, &mut reqs)?;
+        self.features.clear();
+        self.deps.clear();
+        for enabled in self.selected.values() {
+            merge_enabled(&mut self.features, &mut self.deps, enabled);
         }
+        true
     }
 
-    Ok(reqs)
+    /// The features selected with `add_feature`.
+    pub fn selected(&self) -> impl Iterator<Item = InternedString> + '_ {
+        self.selected.keys().copied()
+    }
+
+    /// Every feature enabled by the selected ones, including themselves.
+    pub fn features(&self) -> &HashSet<InternedString> {
+        &self.features
+    }
+
+    /// The dependencies enabled by the selected features, with the features
+    /// enabled on each of them, including the ones only enabled on some
+    /// targets by `cfg(...):` values.
+    pub fn deps(&self) -> &HashMap<InternedString, BTreeSet<InternedString>> {
+        &self.deps
+    }
+}
+
+fn merge_enabled(
+    features: &mut HashSet<InternedString>,
+    deps: &mut HashMap<InternedString, BTreeSet<InternedString>>,
+    enabled: &EnabledByFeature,
+) {
+    features.extend(enabled.features.iter().copied());
+    for (dep, dep_features) in &enabled.deps {
+        deps.entry(*dep)
+            .or_default()
+            .extend(dep_features.iter().copied());
+    }
+}
+
+/// Returns a feature of `s` for which `enabled` holds along with one of its
+/// preconditions for which it does not, in the order of the feature table.
+fn unmet_precondition(
+    s: &Summary,
+    enabled: impl Fn(&InternedString) -> bool,
+) -> Option<(InternedString, InternedString)> {
+    s.feature_preconditions()
+        .iter()
+        .filter(|(feat, _)| enabled(feat))
+        .find_map(|(feat, required)| Some((*feat, *required.iter().find(|r| !enabled(r))?)))
 }
 
 /// Set of feature and dependency requirements for a package.
@@ -410,17 +3262,52 @@ struct Requirements<'a> {
     ///
     /// The resolver will activate all of these dependencies, with the given
     /// features enabled.
-    deps: HashMap<InternedString, BTreeSet<InternedString>>,
+    deps: HashMap<InternedString, DepRequirements>,
     /// The set of features enabled on this package which is later used when
     /// compiling to instruct the code what features were enabled.
     features: HashSet<InternedString>,
+    /// The options this package is resolved with.
+    opts: &'a ResolveOpts,
+    /// Problems that are only warned about.
+    warnings: Vec<RequirementWarning>,
+    /// The `dep/feature` values seen so far, and whether they were weak.
+    dep_feature_refs: HashSet<(InternedString, InternedString, bool)>,
+    /// Whether the features being required come from the `default` feature
+    /// of a dependency edge rather than from an explicit request.
+    from_default_features: bool,
+    /// The enabled features that only the `default` feature led to.
+    default_features_only: HashSet<InternedString>,
+}
+
+/// What is required of a single dependency, see `Requirements::deps`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct DepRequirements {
+    /// The features enabled on the dependency.
+    features: BTreeSet<InternedString>,
+    /// The targets the dependency is required on, when it is only required
+    /// by `cfg(...):` values and the target is not known, or `None` for every
+    /// target.
+    platform: Option<CfgExpr>,
+}
+
+impl DepRequirements {
+    /// Widens the targets the dependency is required on to also cover
+    /// `platform`, `None` meaning every target.
+    fn require_on(&mut self, platform: Option<&CfgExpr>) {
+        self.platform = match (self.platform.take(), platform) {
+            (Some(old), Some(new)) if old == *new => Some(old),
+            (Some(old), Some(new)) => Some(CfgExpr::Any(vec![old, new.clone()])),
+            _ => None,
+        };
+    }
 }
 
 /// An error for a requirement.
 ///
 /// This will later be converted to an `ActivateError` depending on whether or
 /// not this is a dependency or a root package.
-enum RequirementError {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequirementError {
     /// The package does not have the requested feature.
     MissingFeature(InternedString),
     /// The package does not have the requested dependency.
@@ -430,31 +3317,67 @@ enum RequirementError {
     /// Note that cycles through multiple features are allowed (but perhaps
     /// they shouldn't be?).
     Cycle(InternedString),
+    /// The second feature is in an exclusive group with the first one, which
+    /// was already enabled.
+    MutuallyExclusive(InternedString, InternedString),
+    /// The implicit feature of an optional dependency was enabled while
+    /// `ResolveOpts::no_implicit_features` is set.
+    ImplicitFeature(InternedString),
+    /// The first feature was enabled, but not the second one, which it
+    /// requires to be enabled along with it without enabling it itself.
+    UnmetPrecondition(InternedString, InternedString),
 }
 
 impl Requirements<'_> {
-    fn new(summary: &Summary) -> Requirements<'_> {
+    fn new<'a>(summary: &'a Summary, opts: &'a ResolveOpts) -> Requirements<'a> {
         Requirements {
             summary,
             deps: HashMap::new(),
             features: HashSet::new(),
+            opts,
+            warnings: Vec::new(),
+            dep_feature_refs: HashSet::new(),
+            from_default_features: false,
+            default_features_only: HashSet::new(),
         }
     }
 
-    fn into_features(self) -> HashSet<InternedString> {
-        self.features
-    }
-
+    /// Requires `feat` on the dependency `package`, on the targets matching
+    /// `platform` or on every target if `None`, returning the implicit
+    /// feature of `package` that also needs to be enabled, if any.
+    ///
+    /// When `package` is declared under `[target.'cfg(...)'.dependencies]`,
+    /// that platform stays on the `Dependency` and `resolve_features` returns
+    /// it alongside `feat`, so the feature only takes effect where the
+    /// dependency itself does, and where `platform` matches too.
+    ///
+    /// No implicit feature is returned for a `platform`, as enabling it
+    /// would enable `package` on every target.
     fn require_dep_feature(
         &mut self,
         package: InternedString,
         feat: InternedString,
         weak: bool,
-    ) -> Result<(), RequirementError> {
+        platform: Option<&CfgExpr>,
+    ) -> Option<InternedString> {
+        self.require_dependency(package, platform)
+            .features
+            .insert(feat);
+        if self.dep_feature_refs.insert((package, feat, weak))
+            && self.dep_feature_refs.contains(&(package, feat, !weak))
+        {
+            self.warnings
+                .push(RequirementWarning::RedundantWeakDepFeature {
+                    package: self.summary.package_id(),
+                    dep: package,
+                    feature: feat,
+                });
+        }
         // If `package` is indeed an optional dependency then we activate the
         // feature named `package`, but otherwise if `package` is a required
         // dependency then there's no feature associated with it.
         if !weak
+            && platform.is_none()
             && self
                 .summary
                 .dependencies()
@@ -465,57 +3388,307 @@ impl Requirements<'_> {
             // the same name if the `dep:` syntax is used to avoid creating
             // that implicit feature.
             if self.summary.features().contains_key(&package) {
-                self.require_feature(package)?;
+                return Some(package);
             }
         }
-        self.deps.entry(package).or_default().insert(feat);
-        Ok(())
+        None
     }
 
-    fn require_dependency(&mut self, pkg: InternedString) {
-        self.deps.entry(pkg).or_default();
+    /// Returns an enabled feature that may not be enabled along with `feat`.
+    fn enabled_exclusive_with(&self, feat: InternedString) -> Option<InternedString> {
+        self.summary
+            .exclusive_feature_groups()
+            .iter()
+            .filter(|group| group.contains(&feat))
+            .flatten()
+            .find(|other| **other != feat && self.features.contains(*other))
+            .copied()
     }
 
-    fn require_feature(&mut self, feat: InternedString) -> Result<(), RequirementError> {
-        if !self.features.insert(feat) {
-            // Already seen this feature.
-            return Ok(());
+    /// Checks that the preconditions of the enabled features are enabled too.
+    ///
+    /// This is done once everything is required, so it does not matter in
+    /// which order the features were enabled, or whether a precondition was
+    /// asked for or enabled by another feature.
+    fn check_preconditions(&self) -> Result<(), RequirementError> {
+        match unmet_precondition(self.summary, |f| self.features.contains(f)) {
+            Some((feat, required)) => Err(RequirementError::UnmetPrecondition(feat, required)),
+            None => Ok(()),
         }
+    }
 
-        let fvs = match self.summary.features().get(&feat) {
-            Some(fvs) => fvs,
-            None => return Err(RequirementError::MissingFeature(feat)),
-        };
+    /// Records a warning if `feat`, asked for by `enabled_by`, is deprecated.
+    ///
+    /// Only features asked for from outside the package are checked, a
+    /// deprecated feature may still be enabled by the package's own features.
+    fn check_deprecated(&mut self, feat: InternedString, enabled_by: Option<PackageId>) {
+        if self.summary.deprecated_features().contains(&feat) {
+            self.warnings.push(RequirementWarning::DeprecatedFeature {
+                package: self.summary.package_id(),
+                feature: feat,
+                enabled_by,
+            });
+        }
+    }
+
+    /// Requires the dependency `pkg` on the targets matching `platform`, or
+    /// on every target if `None`.
+    fn require_dependency(
+        &mut self,
+        pkg: InternedString,
+        platform: Option<&CfgExpr>,
+    ) -> &mut DepRequirements {
+        match self.deps.entry(pkg) {
+            Entry::Occupied(e) => {
+                let dep = e.into_mut();
+                dep.require_on(platform);
+                dep
+            }
+            Entry::Vacant(e) => e.insert(DepRequirements {
+                features: BTreeSet::new(),
+                platform: platform.cloned(),
+            }),
+        }
+    }
+
+    fn require_feature(&mut self, feat: InternedString) -> Result<(), RequirementError> {
+        self.require_value(&FeatureValue::Feature(feat))
+    }
 
-        for fv in fvs {
-            if let FeatureValue::Feature(dep_feat) = fv {
-                if *dep_feat == feat {
-                    return Err(RequirementError::Cycle(feat));
+    /// Requires `fv` and everything it transitively enables.
+    ///
+    /// Feature tables can be nested arbitrarily deep, so this walks them with
+    /// an explicit stack rather than recursing, in the same depth-first order
+    /// a recursive walk would use.
+    fn require_value(&mut self, fv: &FeatureValue) -> Result<(), RequirementError> {
+        // Each entry remembers the feature that listed it, if any, so a
+        // feature enabling itself can be reported.
+        let mut stack = vec![(None, fv.clone())];
+        while let Some((listed_by, fv)) = stack.pop() {
+            match fv {
+                FeatureValue::Feature(feat) => {
+                    if listed_by == Some(feat) {
+                        return Err(RequirementError::Cycle(feat));
+                    }
+                    if self.features.insert(feat) {
+                        if self.from_default_features {
+                            self.default_features_only.insert(feat);
+                        }
+                    } else if self.from_default_features
+                        || !self.default_features_only.remove(&feat)
+                    {
+                        // Already seen this feature.
+                        continue;
+                    }
+                    // Otherwise it was only seen through `default`, and is
+                    // walked again so what it enables is explicit too.
+                    if let Some(other) = self.enabled_exclusive_with(feat) {
+                        // Every feature is asked for, so one of the group
+                        // cannot be picked, only reported.
+                        let all_features = matches!(
+                            &self.opts.features,
+                            RequestedFeatures::CliFeatures(cli) if cli.all_features
+                        );
+                        if !all_features {
+                            return Err(RequirementError::MutuallyExclusive(other, feat));
+                        }
+                        self.warnings
+                            .push(RequirementWarning::ExclusiveFeaturesEnabled {
+                                package: self.summary.package_id(),
+                                first: other,
+                                second: feat,
+                            });
+                    }
+                    if self.opts.no_implicit_features
+                        && self.summary.implicit_features().contains(&feat)
+                    {
+                        return Err(RequirementError::ImplicitFeature(feat));
+                    }
+                    let fvs = match self.summary.features().get(&feat) {
+                        Some(fvs) => fvs,
+                        None => {
+                            // An alias names the optional dependency like a
+                            // `dep:` value does, so the dependency's own
+                            // implicit feature is not enabled along with it.
+                            if let Some(&dep_name) = self.summary.feature_aliases().get(&feat) {
+                                stack.push((Some(feat), FeatureValue::Dep { dep_name }));
+                                continue;
+                            }
+                            if self.accept_dependency_as_feature(feat) {
+                                continue;
+                            }
+                            return Err(RequirementError::MissingFeature(feat));
+                        }
+                    };
+                    // Pushed in reverse so they are visited in declaration order.
+                    stack.extend(fvs.iter().rev().map(|fv| (Some(feat), fv.clone())));
+                }
+                FeatureValue::Dep { dep_name } => {
+                    self.require_dependency(dep_name, None);
+                }
+                FeatureValue::DepFeature {
+                    dep_name,
+                    dep_feature,
+                    // Weak features are always activated in the dependency
+                    // resolver. They will be narrowed inside the new feature
+                    // resolver.
+                    weak,
+                } => {
+                    if let Some(implicit) =
+                        self.require_dep_feature(dep_name, dep_feature, weak, None)
+                    {
+                        stack.push((None, FeatureValue::Feature(implicit)));
+                    }
+                }
+                FeatureValue::TargetFeature { cfg, value } => {
+                    let opts = self.opts;
+                    match opts.target_cfg() {
+                        Some(target_cfg) => {
+                            if cfg.matches(target_cfg) {
+                                stack.push((listed_by, *value));
+                            }
+                        }
+                        // Without a target, any target may be built. A
+                        // dependency is required on the matching targets
+                        // only, while a feature is conservatively required
+                        // everywhere, as the enabled features are the same on
+                        // every target.
+                        None => match *value {
+                            FeatureValue::Dep { dep_name } => {
+                                self.require_dependency(dep_name, Some(&cfg));
+                            }
+                            FeatureValue::DepFeature {
+                                dep_name,
+                                dep_feature,
+                                weak,
+                            } => {
+                                self.require_dep_feature(dep_name, dep_feature, weak, Some(&cfg));
+                            }
+                            value => stack.push((listed_by, value)),
+                        },
+                    }
                 }
             }
-            self.require_value(fv)?;
         }
         Ok(())
     }
 
-    fn require_value(&mut self, fv: &FeatureValue) -> Result<(), RequirementError> {
-        match fv {
-            FeatureValue::Feature(feat) => self.require_feature(*feat)?,
-            FeatureValue::Dep { dep_name } => self.require_dependency(*dep_name),
-            FeatureValue::DepFeature {
-                dep_name,
-                dep_feature,
-                // Weak features are always activated in the dependency
-                // resolver. They will be narrowed inside the new feature
-                // resolver.
-                weak,
-            } => self.require_dep_feature(*dep_name, *dep_feature, *weak)?,
+    /// Handles a missing feature `feat` that names a dependency, according to
+    /// the conflict policy. Returns `false` if it is still an error.
+    fn accept_dependency_as_feature(&mut self, feat: InternedString) -> bool {
+        let mut deps = self
+            .summary
+            .dependencies()
+            .iter()
+            .filter(|dep| dep.name_in_toml() == feat)
+            .peekable();
+        if deps.peek().is_none() {
+            return false;
+        }
+        // Matches the conflict `RequirementError::into_activate_error` reports.
+        let kind = if deps.any(|dep| dep.is_optional()) {
+            ConflictKind::NonImplicitDependencyAsFeature
+        } else {
+            ConflictKind::RequiredDependencyAsFeature
         };
-        Ok(())
+        match self.opts.conflict_policy(kind) {
+            ConflictPolicy::Error => return false,
+            ConflictPolicy::Warn => self.warnings.push(RequirementWarning::DependencyAsFeature {
+                package: self.summary.package_id(),
+                feature: feat,
+                kind,
+            }),
+            ConflictPolicy::Ignore => {}
+        }
+        // It is not a feature of this package, only a way to name the dependency.
+        self.features.remove(&feat);
+        self.require_dependency(feat, None);
+        true
     }
 }
 
+impl fmt::Display for RequirementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequirementError::MissingFeature(feat) => write!(f, "no feature named `{}`", feat),
+            RequirementError::MissingDependency(dep_name) => {
+                write!(f, "no dependency named `{}`", dep_name)
+            }
+            RequirementError::Cycle(feat) => {
+                write!(f, "feature `{}` depends on itself", feat)
+            }
+            RequirementError::MutuallyExclusive(a, b) => {
+                write!(f, "features `{}` and `{}` cannot be enabled together", a, b)
+            }
+            RequirementError::ImplicitFeature(dep_name) => write!(
+                f,
+                "`{}` is the implicit feature of an optional dependency",
+                dep_name
+            ),
+            RequirementError::UnmetPrecondition(feat, required) => write!(
+                f,
+                "feature `{}` requires the feature `{}` to be enabled too",
+                feat, required
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RequirementError {}
+
+/// A machine-readable [`RequirementError`], for editors that want to point at
+/// the problem in the feature table without parsing the error message.
+#[derive(Debug, Clone, Serialize)]
+pub struct SerializedRequirementError {
+    /// Always [`SerializedRequirementError::SCHEMA_VERSION`] for the layout
+    /// described here.
+    pub schema_version: u32,
+    /// The kind of error, such as `"missing-feature"` or `"cycle"`.
+    pub kind: &'static str,
+    /// The package whose feature table is at fault.
+    pub package_id: PackageId,
+    /// The features involved, in the order the message names them.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<InternedString>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dependency: Option<InternedString>,
+    /// The message the error displays as.
+    pub message: String,
+}
+
+impl SerializedRequirementError {
+    /// Bumped whenever a field changes meaning or is removed.
+    pub const SCHEMA_VERSION: u32 = 1;
+}
+
 impl RequirementError {
+    /// Describes this error with the features of `package_id` in a form tools
+    /// can consume.
+    pub fn to_serialized(&self, package_id: PackageId) -> SerializedRequirementError {
+        let (kind, features, dependency) = match self {
+            RequirementError::MissingFeature(feat) => ("missing-feature", vec![*feat], None),
+            RequirementError::MissingDependency(dep_name) => {
+                ("missing-dependency", Vec::new(), Some(*dep_name))
+            }
+            RequirementError::Cycle(feat) => ("cycle", vec![*feat], None),
+            RequirementError::MutuallyExclusive(a, b) => ("mutually-exclusive", vec![*a, *b], None),
+            RequirementError::ImplicitFeature(dep_name) => {
+                ("implicit-feature", vec![*dep_name], Some(*dep_name))
+            }
+            RequirementError::UnmetPrecondition(feat, required) => {
+                ("unmet-precondition", vec![*feat, *required], None)
+            }
+        };
+        SerializedRequirementError {
+            schema_version: SerializedRequirementError::SCHEMA_VERSION,
+            kind,
+            package_id,
+            features,
+            dependency,
+            message: self.to_string(),
+        }
+    }
+
     fn into_activate_error(self, parent: Option<PackageId>, summary: &Summary) -> ActivateError {
         match self {
             RequirementError::MissingFeature(feat) => {
@@ -585,6 +3758,422 @@ impl RequirementError {
                 "cyclic feature dependency: feature `{}` depends on itself",
                 feat
             )),
+            RequirementError::MutuallyExclusive(a, b) => match parent {
+                None => ActivateError::Fatal(anyhow::format_err!(
+                    "features `{}` and `{}` of package `{}` cannot be enabled together",
+                    a,
+                    b,
+                    summary.package_id()
+                )),
+                Some(p) => {
+                    ActivateError::Conflict(p, ConflictReason::MutuallyExclusiveFeatures(a, b))
+                }
+            },
+            // Implicit features are only rejected to find where they are
+            // used, so this stops the resolve rather than trying other versions.
+            RequirementError::ImplicitFeature(dep_name) => {
+                ActivateError::Fatal(anyhow::format_err!(
+                    "package `{}` enables its optional dependency `{}` through the \
+                     implicit feature of the same name, which is not allowed\n\
+                     help: enable it with `dep:{}` in a feature instead",
+                    summary.package_id(),
+                    dep_name,
+                    dep_name
+                ))
+            }
+            RequirementError::UnmetPrecondition(feat, required) => match parent {
+                None => ActivateError::Fatal(anyhow::format_err!(
+                    "feature `{}` of package `{}` requires the feature `{}` to be enabled \
+                     along with it",
+                    feat,
+                    summary.package_id(),
+                    required
+                )),
+                Some(p) => ActivateError::Conflict(
+                    p,
+                    ConflictReason::UnmetFeaturePrecondition(feat, required),
+                ),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::{RegistryCapabilities, SourceId};
+    use std::collections::BTreeMap;
+
+    fn src_id() -> SourceId {
+        SourceId::from_url("registry+https://github.com/rust-lang/crates.io-index").unwrap()
+    }
+
+    fn summ(features: &[(&str, &[&str])]) -> Summary {
+        summ_with_deps(Vec::new(), features)
+    }
+
+    fn summ_with_deps(deps: Vec<Dependency>, features: &[(&str, &[&str])]) -> Summary {
+        let pkg_id = PackageId::new("foo", "1.0.0", src_id()).unwrap();
+        let features: BTreeMap<_, _> = features
+            .iter()
+            .map(|(name, values)| {
+                (
+                    InternedString::new(name),
+                    values.iter().map(|v| InternedString::new(v)).collect(),
+                )
+            })
+            .collect();
+        Summary::new(
+            pkg_id,
+            deps,
+            &features,
+            None::<&String>,
+            None::<PartialVersion>,
+        )
+        .unwrap()
+    }
+
+    fn summ_named(name: &str, version: &str) -> Summary {
+        let pkg_id = PackageId::new(name, version, src_id()).unwrap();
+        Summary::new(
+            pkg_id,
+            Vec::new(),
+            &BTreeMap::new(),
+            None::<&String>,
+            None::<PartialVersion>,
+        )
+        .unwrap()
+    }
+
+    fn opts(features: &[&str]) -> ResolveOpts {
+        let features: Vec<String> = features.iter().map(|f| f.to_string()).collect();
+        let cli_features = CliFeatures::from_command_line(&features, false, false).unwrap();
+        ResolveOpts::new(false, RequestedFeatures::CliFeatures(cli_features))
+    }
+
+    #[test]
+    fn test_feature_errors() {
+        let s = summ(&[("a", &["b"]), ("b", &["b"])]);
+
+        let err = build_requirements(None, &s, &opts(&["a"])).unwrap_err();
+        let ActivateError::Fatal(err) = err else {
+            panic!("expected a fatal error")
+        };
+        assert_eq!(
+            err.to_string(),
+            "cyclic feature dependency: feature `b` depends on itself"
+        );
+
+        let err = build_requirements(None, &s, &opts(&["missing"])).unwrap_err();
+        let ActivateError::Fatal(err) = err else {
+            panic!("expected a fatal error")
+        };
+        assert_eq!(
+            err.to_string(),
+            "Package `foo v1.0.0` does not have the feature `missing`"
+        );
+    }
+
+    #[test]
+    fn test_dependency_as_feature_policy() {
+        let bar = Dependency::parse("bar", Some("1.0"), src_id()).unwrap();
+        let s = summ_with_deps(vec![bar], &[]);
+
+        let err = build_requirements(None, &s, &opts(&["bar"])).unwrap_err();
+        assert!(matches!(err, ActivateError::Fatal(_)));
+
+        let mut warn = opts(&["bar"]);
+        warn.set_conflict_policy(
+            ConflictKind::RequiredDependencyAsFeature,
+            ConflictPolicy::Warn,
+        );
+        let (features, deps, warnings) = resolve_features(None, &s, &warn).unwrap();
+        assert!(features.is_empty());
+        assert_eq!(deps.len(), 1);
+        assert_eq!(
+            warnings,
+            [RequirementWarning::DependencyAsFeature {
+                package: s.package_id(),
+                feature: InternedString::new("bar"),
+                kind: ConflictKind::RequiredDependencyAsFeature,
+            }]
+        );
+
+        let mut ignore = warn.clone();
+        ignore.set_conflict_policy(
+            ConflictKind::RequiredDependencyAsFeature,
+            ConflictPolicy::Ignore,
+        );
+        let (_, _, warnings) = resolve_features(None, &s, &ignore).unwrap();
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_dep_feature_keeps_platform() {
+        let windows: cargo_platform::Platform = "cfg(windows)".parse().unwrap();
+        let mut bar = Dependency::parse("bar", Some("1.0"), src_id()).unwrap();
+        bar.set_optional(true).set_platform(Some(windows.clone()));
+        let s = summ_with_deps(vec![bar], &[("x", &["bar/std"])]);
+
+        let (_, deps, _) = resolve_features(None, &s, &opts(&["x"])).unwrap();
+        assert_eq!(deps.len(), 1);
+        let (dep, features) = &deps[0];
+        assert_eq!(dep.platform(), Some(&windows));
+        assert!(features.contains(&InternedString::new("std")));
+    }
+
+    #[test]
+    fn test_target_dep_feature() {
+        let mut bar = Dependency::parse("bar", Some("1.0"), src_id()).unwrap();
+        bar.set_optional(true);
+        let s = summ_with_deps(vec![bar], &[("x", &["cfg(windows):bar/std"])]);
+        let target = |cfg: &str| Some(vec![cfg.parse().unwrap()]);
+        let mut opts = opts(&["x"]);
+
+        // Any target may be built, so `bar` is kept for Windows only.
+        let (features, deps, _) = resolve_features(None, &s, &opts).unwrap();
+        assert!(!features.contains(&InternedString::new("bar")));
+        assert_eq!(deps.len(), 1);
+        let (dep, dep_features) = &deps[0];
+        assert_eq!(dep.platform(), Some(&"cfg(windows)".parse().unwrap()));
+        assert!(dep_features.contains(&InternedString::new("std")));
+
+        opts.set_target_cfg(target("windows"));
+        let (features, deps, _) = resolve_features(None, &s, &opts).unwrap();
+        assert!(features.contains(&InternedString::new("bar")));
+        assert_eq!(deps.len(), 1);
+        assert_eq!(deps[0].0.platform(), None);
+
+        opts.set_target_cfg(target("unix"));
+        let (_, deps, _) = resolve_features(None, &s, &opts).unwrap();
+        assert!(deps.is_empty());
+    }
+
+    #[test]
+    fn test_mutually_exclusive_features() {
+        let mut s = summ(&[("a", &[]), ("b", &[]), ("c", &["b"]), ("d", &[])]);
+        s.set_exclusive_feature_groups([["a", "b"].into_iter().map(InternedString::new).collect()]);
+
+        assert!(build_requirements(None, &s, &opts(&["a", "d"])).is_ok());
+
+        let err = build_requirements(None, &s, &opts(&["a", "c"])).unwrap_err();
+        let ActivateError::Fatal(err) = err else {
+            panic!("expected a fatal error")
+        };
+        assert_eq!(
+            err.to_string(),
+            "features `a` and `b` of package `foo v1.0.0` cannot be enabled together"
+        );
+
+        // Asking for every feature is reported rather than failing.
+        let all = ResolveOpts::new(
+            false,
+            RequestedFeatures::CliFeatures(CliFeatures::new_all(true)),
+        );
+        let reqs = build_requirements(None, &s, &all).unwrap();
+        assert_eq!(
+            reqs.warnings,
+            [RequirementWarning::ExclusiveFeaturesEnabled {
+                package: s.package_id(),
+                first: InternedString::new("a"),
+                second: InternedString::new("b"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_serialized_feature_conflicts() {
+        let package_id = summ_named("bar", "1.0.0").package_id();
+        let reason = ConflictReason::MutuallyExclusiveFeatures("a".into(), "b".into());
+        let serialized = serde_json::to_value(reason.to_serialized(package_id)).unwrap();
+        assert_eq!(serialized["kind"], "mutually-exclusive-features");
+        assert_eq!(serialized["features"], serde_json::json!(["a", "b"]));
+        assert!(serialized.get("feature").is_none());
+
+        let reason = ConflictReason::UnmetFeaturePrecondition("a".into(), "b".into());
+        let serialized = serde_json::to_value(reason.to_serialized(package_id)).unwrap();
+        assert_eq!(serialized["kind"], "unmet-feature-precondition");
+        assert_eq!(serialized["features"], serde_json::json!(["a", "b"]));
+    }
+
+    #[test]
+    fn test_conflict_observer() {
+        let mut registry = ReplayRegistry::new([]);
+        let version_prefs = VersionPreferences::default();
+        let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+        let seen = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let observer_seen = seen.clone();
+        queryer.set_conflict_observer(Some(Rc::new(move |reason: &ConflictReason| {
+            observer_seen.borrow_mut().push(reason.clone())
+        })));
+        let cx = Context::new(false);
+        let parent = summ_named("parent", "1.0.0").package_id();
+
+        let s = summ(&[("a", &[])]);
+        assert!(queryer
+            .build_deps(&cx, Some(parent), &s, &Rc::new(opts(&["nope"])), false)
+            .is_err());
+        assert!(queryer
+            .build_deps(&cx, Some(parent), &s, &Rc::new(opts(&["a"])), false)
+            .is_ok());
+        assert_eq!(
+            *seen.borrow(),
+            [ConflictReason::MissingFeatures("nope".to_string())]
+        );
+    }
+
+    /// A `ReplayRegistry` that claims to batch queries, and records the
+    /// packages it is asked for.
+    struct BatchRegistry(ReplayRegistry, Vec<InternedString>);
+
+    impl Registry for BatchRegistry {
+        fn query(
+            &mut self,
+            dep: &Dependency,
+            kind: QueryKind,
+            f: &mut dyn FnMut(Summary),
+        ) -> Poll<CargoResult<()>> {
+            self.1.push(dep.package_name());
+            self.0.query(dep, kind, f)
+        }
+
+        fn describe_source(&self, source: SourceId) -> String {
+            self.0.describe_source(source)
+        }
+
+        fn is_replaced(&self, source: SourceId) -> bool {
+            self.0.is_replaced(source)
+        }
+
+        fn block_until_ready(&mut self) -> CargoResult<()> {
+            Ok(())
+        }
+
+        fn capabilities(&self) -> RegistryCapabilities {
+            RegistryCapabilities {
+                batch_queries: true,
+            }
         }
     }
+
+    #[test]
+    fn test_prefetch_with_batching_registry() {
+        let bar = Dependency::parse("bar", Some("1.0"), src_id()).unwrap();
+        let baz = Dependency::parse("baz", Some("1.0"), src_id()).unwrap();
+        let foo = summ_with_deps(vec![bar.clone()], &[]);
+        let bar_summary = Summary::new(
+            PackageId::new("bar", "1.0.0", src_id()).unwrap(),
+            vec![baz.clone()],
+            &BTreeMap::new(),
+            None::<&String>,
+            None::<PartialVersion>,
+        )
+        .unwrap();
+        let queries = || {
+            [
+                (bar.clone(), vec![bar_summary.clone()]),
+                (baz.clone(), vec![summ_named("baz", "1.0.0")]),
+            ]
+        };
+        let version_prefs = VersionPreferences::default();
+        let cx = Context::new(false);
+
+        let mut registry = BatchRegistry(ReplayRegistry::new(queries()), Vec::new());
+        let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+        queryer
+            .build_deps(&cx, None, &foo, &Rc::new(opts(&[])), true)
+            .unwrap();
+        // `baz` was asked for, but only `bar` is a query of the resolve.
+        assert!(!queryer.registry_cache.contains_key(&(baz.clone(), true)));
+        assert!(!queryer.query_latencies.contains_key(&baz.package_name()));
+        assert!(!queryer.candidate_counts.contains_key(&baz));
+        drop(queryer);
+        assert_eq!(registry.1, ["bar", "baz"]);
+
+        // A query that is cached already is not repeated.
+        let mut registry = BatchRegistry(ReplayRegistry::new(queries()), Vec::new());
+        let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+        assert!(queryer.query(&baz, true).is_ready());
+        queryer
+            .build_deps(&cx, None, &foo, &Rc::new(opts(&[])), true)
+            .unwrap();
+        drop(queryer);
+        assert_eq!(registry.1, ["baz", "bar"]);
+
+        // The registry failing to answer the prefetch does not fail
+        // `build_deps`, nor count as time spent in the registry.
+        let mut registry = BatchRegistry(
+            ReplayRegistry::new([(bar.clone(), vec![bar_summary.clone()])]),
+            Vec::new(),
+        );
+        let mut queryer = RegistryQueryer::new(&mut registry, &[], &version_prefs, false, None);
+        let deps = queryer
+            .build_deps(&cx, None, &foo, &Rc::new(opts(&[])), true)
+            .unwrap();
+        let registry_time = queryer.registry_time;
+        queryer.prefetch(&deps.1, true);
+        assert_eq!(queryer.registry_time, registry_time);
+        drop(queryer);
+        assert_eq!(registry.1, ["bar", "baz", "baz"]);
+    }
+
+    #[test]
+    fn test_target_feature() {
+        let s = summ(&[
+            ("embedded", &["cfg(target_pointer_width = \"32\"):small"]),
+            ("small", &[]),
+        ]);
+        let target = |width: &str| {
+            let cfg: cargo_platform::Cfg = format!("target_pointer_width = \"{width}\"")
+                .parse()
+                .unwrap();
+            Some(vec![cfg])
+        };
+        let small = InternedString::new("small");
+
+        let mut opts = opts(&["embedded"]);
+        opts.set_target_cfg(target("32"));
+        let (features, _, _) = resolve_features(None, &s, &opts).unwrap();
+        assert!(features.contains(&small));
+
+        opts.set_target_cfg(target("64"));
+        let (features, _, _) = resolve_features(None, &s, &opts).unwrap();
+        assert!(!features.contains(&small));
+
+        opts.set_target_cfg(None);
+        let (features, _, _) = resolve_features(None, &s, &opts).unwrap();
+        assert!(features.contains(&small));
+    }
+
+    #[test]
+    fn test_unmet_precondition() {
+        let mut s = summ(&[("a", &[]), ("b", &[]), ("c", &["a", "b"])]);
+        s.set_feature_preconditions([(
+            InternedString::new("a"),
+            [InternedString::new("b")].into(),
+        )]);
+
+        // The order the features are enabled in does not matter.
+        assert!(build_requirements(None, &s, &opts(&["a", "b"])).is_ok());
+        assert!(build_requirements(None, &s, &opts(&["b", "a"])).is_ok());
+        // Neither does how `b` gets enabled.
+        assert!(build_requirements(None, &s, &opts(&["c"])).is_ok());
+
+        let err = build_requirements(None, &s, &opts(&["a"])).unwrap_err();
+        let ActivateError::Fatal(err) = err else {
+            panic!("expected a fatal error")
+        };
+        assert_eq!(
+            err.to_string(),
+            "feature `a` of package `foo v1.0.0` requires the feature `b` to be enabled along with it"
+        );
+
+        let parent = summ_named("bar", "1.0.0").package_id();
+        let err = build_requirements(Some(parent), &s, &opts(&["a"])).unwrap_err();
+        assert!(matches!(
+            err,
+            ActivateError::Conflict(_, ConflictReason::UnmetFeaturePrecondition(a, b))
+                if a == "a" && b == "b"
+        ));
+    }
 }
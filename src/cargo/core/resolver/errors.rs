@@ -1,12 +1,14 @@
 use std::fmt;
 use std::task::Poll;
 
-use crate::core::{Dependency, PackageId, QueryKind, Registry, Summary};
+use crate::core::{Dependency, PackageId, QueryKind, Registry, SourceId, Summary};
 use crate::util::edit_distance::edit_distance;
+use crate::util::interning::InternedString;
 use crate::util::{Config, VersionExt};
 use anyhow::Error;
 
 use super::context::Context;
+use super::dep_cache::CandidateCounts;
 use super::types::{ConflictMap, ConflictReason};
 
 /// Error during resolution providing a path of `PackageId`s.
@@ -54,14 +56,60 @@ pub type ActivateResult<T> = Result<T, ActivateError>;
 pub enum ActivateError {
     Fatal(anyhow::Error),
     Conflict(PackageId, ConflictReason),
+    /// Like `Conflict`, for a candidate that conflicts with all of these
+    /// packages being activated together rather than with any one of them.
+    Conflicts(ConflictMap),
+    /// The resolve was cancelled through `RegistryQueryer::set_cancel`.
+    Cancelled,
 }
 
 impl From<::anyhow::Error> for ActivateError {
     fn from(t: ::anyhow::Error) -> Self {
+        if t.is::<ResolveCancelled>() {
+            return ActivateError::Cancelled;
+        }
         ActivateError::Fatal(t)
     }
 }
 
+/// The error a cancelled resolve fails with, so callers can tell it apart
+/// from a real failure with `anyhow::Error::is`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolveCancelled;
+
+impl fmt::Display for ResolveCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("dependency resolution was cancelled")
+    }
+}
+
+impl std::error::Error for ResolveCancelled {}
+
+/// The candidates for a dependency came from more than one source, which
+/// `RegistryQueryer::set_forbid_ambiguous_sources` turns into an error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AmbiguousSourceError {
+    pub name: InternedString,
+    /// Every source with a candidate, sorted.
+    pub sources: Vec<SourceId>,
+}
+
+impl fmt::Display for AmbiguousSourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the package `{}` is available from more than one source:",
+            self.name
+        )?;
+        for source in &self.sources {
+            write!(f, "\n  {}", source)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AmbiguousSourceError {}
+
 impl From<(PackageId, ConflictReason)> for ActivateError {
     fn from(t: (PackageId, ConflictReason)) -> Self {
         ActivateError::Conflict(t.0, t.1)
@@ -75,6 +123,7 @@ pub(super) fn activation_error(
     dep: &Dependency,
     conflicting_activations: &ConflictMap,
     candidates: &[Summary],
+    candidate_counts: Option<CandidateCounts>,
     config: Option<&Config>,
 ) -> ResolveError {
     let to_resolve_err = |err| {
@@ -188,6 +237,52 @@ pub(super) fn activation_error(
                     );
                     // p == parent so the full path is redundant.
                 }
+                ConflictReason::MutuallyExclusiveFeatures(a, b) => {
+                    msg.push_str("\n\nthe package `");
+                    msg.push_str(&*p.name());
+                    msg.push_str("` depends on `");
+                    msg.push_str(&*dep.package_name());
+                    msg.push_str("`, with features: `");
+                    msg.push_str(a);
+                    msg.push_str("` and `");
+                    msg.push_str(b);
+                    msg.push_str("` but `");
+                    msg.push_str(&*dep.package_name());
+                    msg.push_str("` does not allow these features to be enabled together.\n");
+                    // p == parent so the full path is redundant.
+                }
+                ConflictReason::UnmetFeaturePrecondition(feat, required) => {
+                    msg.push_str("\n\nthe package `");
+                    msg.push_str(&*p.name());
+                    msg.push_str("` depends on `");
+                    msg.push_str(&*dep.package_name());
+                    msg.push_str("`, with feature: `");
+                    msg.push_str(feat);
+                    msg.push_str("` but `");
+                    msg.push_str(&*dep.package_name());
+                    msg.push_str("` requires the feature `");
+                    msg.push_str(required);
+                    msg.push_str("` to be enabled along with it.\n");
+                    // p == parent so the full path is redundant.
+                }
+                ConflictReason::VersionLimit(max) => {
+                    msg.push_str("\n\nat most ");
+                    msg.push_str(&max.to_string());
+                    msg.push_str(" versions of `");
+                    msg.push_str(&*dep.package_name());
+                    msg.push_str("` may be activated, which is reached with:\n");
+                    msg.push_str(&describe_path_in_context(cx, p));
+                }
+                ConflictReason::Yanked => {
+                    msg.push_str("\n\nthe package `");
+                    msg.push_str(&*p.name());
+                    msg.push_str("` depends on `");
+                    msg.push_str(&*dep.package_name());
+                    msg.push_str("`, but the matching versions of `");
+                    msg.push_str(&*dep.package_name());
+                    msg.push_str("` are yanked, which is not allowed.\n");
+                    // p == parent so the full path is redundant.
+                }
                 ConflictReason::PublicDependency(pkg_id) => {
                     // TODO: This needs to be implemented.
                     unimplemented!("pub dep {:?}", pkg_id);
@@ -217,6 +312,35 @@ pub(super) fn activation_error(
         return to_resolve_err(anyhow::format_err!("{}", msg));
     }
 
+    // The registry did have versions matching the requirement, but none of
+    // them made it through the resolver's own filters.
+    if let Some(counts) = candidate_counts.filter(|c| c.returned > 0 && c.survived == 0) {
+        let mut reasons = Vec::new();
+        if counts.msrv_filtered > 0 {
+            reasons.push(format!(
+                "{} require a newer rustc than the `rust-version` ceiling",
+                counts.msrv_filtered
+            ));
+        }
+        let other = counts.returned - counts.msrv_filtered;
+        if other > 0 {
+            reasons.push(format!("{} were excluded by other filters", other));
+        }
+        let mut msg = format!(
+            "failed to select a version for the requirement `{} = \"{}\"`\n\
+             {} versions exist, but none of them can be used: {}\n\
+             location searched: {}\n",
+            dep.package_name(),
+            dep.version_req(),
+            counts.returned,
+            reasons.join(", "),
+            registry.describe_source(dep.source_id()),
+        );
+        msg.push_str("required by ");
+        msg.push_str(&describe_path_in_context(cx, &parent.package_id()));
+        return to_resolve_err(anyhow::format_err!("{}", msg));
+    }
+
     // We didn't actually find any candidates, so we need to
     // give an error message that nothing was found.
     //
@@ -262,14 +386,43 @@ pub(super) fn activation_error(
                 .map(|v| format!(" (locked to {})", v))
                 .unwrap_or_default();
 
+            // The versions just below and just above the requirement, in
+            // case it only narrowly missed. When some version does match, it
+            // was left out for another reason, like its `rust-version`, and
+            // the versions around it are no help.
+            let req = dep.version_req();
+            let (below, above): (Vec<_>, Vec<_>) =
+                if candidates.iter().any(|cand| req.matches(cand.version())) {
+                    Default::default()
+                } else {
+                    candidates
+                        .iter()
+                        .map(|cand| cand.version())
+                        .partition(|v| req.is_below(v))
+                };
+            let closest = below
+                .into_iter()
+                .max()
+                .map(|v| format!("{} (too low)", v))
+                .into_iter()
+                .chain(above.into_iter().min().map(|v| format!("{} (too high)", v)))
+                .collect::<Vec<_>>();
+            let closest = if closest.is_empty() {
+                String::new()
+            } else {
+                format!("closest versions: {}\n", closest.join(", "))
+            };
+
             let mut msg = format!(
                 "failed to select a version for the requirement `{} = \"{}\"`{}\n\
                  candidate versions found which didn't match: {}\n\
+                 {}\
                  location searched: {}\n",
                 dep.package_name(),
                 dep.version_req(),
                 locked_version,
                 versions,
+                closest,
                 registry.describe_source(dep.source_id()),
             );
             msg.push_str("required by ");
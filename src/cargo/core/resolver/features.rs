@@ -44,7 +44,7 @@ use crate::core::resolver::types::FeaturesSet;
 use crate::core::resolver::{Resolve, ResolveBehavior};
 use crate::core::{FeatureValue, PackageId, PackageIdSpec, PackageSet, Workspace};
 use crate::util::interning::InternedString;
-use crate::util::CargoResult;
+use crate::util::{CargoResult, Config};
 use anyhow::{bail, Context};
 use itertools::Itertools;
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
@@ -247,6 +247,21 @@ pub enum RequestedFeatures {
     },
 }
 
+impl RequestedFeatures {
+    /// Returns `true` if any feature is explicitly requested, not counting
+    /// the `default` feature enabled through `uses_default_features`.
+    pub fn has_requested_features(&self) -> bool {
+        match self {
+            RequestedFeatures::CliFeatures(CliFeatures {
+                features,
+                all_features,
+                ..
+            }) => *all_features || !features.is_empty(),
+            RequestedFeatures::DepFeatures { features, .. } => !features.is_empty(),
+        }
+    }
+}
+
 /// Features specified on the command-line.
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct CliFeatures {
@@ -265,29 +280,107 @@ impl CliFeatures {
         all_features: bool,
         uses_default_features: bool,
     ) -> CargoResult<CliFeatures> {
-        let features = Rc::new(CliFeatures::split_features(features));
-        // Some early validation to ensure correct syntax.
-        for feature in features.iter() {
-            match feature {
-                // Maybe call validate_feature_name here once it is an error?
+        Ok(CliFeatures {
+            all_features,
+            uses_default_features,
+            ..CliFeatures::from_feature_specs(features)?
+        })
+    }
+
+    /// Creates a new CliFeatures requesting the given features, in the same
+    /// syntax as `--features`, with the default features enabled.
+    ///
+    /// Each spec may hold several features separated by commas or spaces.
+    /// Malformed features are reported as errors instead of being left for
+    /// the resolver to trip over.
+    pub fn from_feature_specs(specs: &[String]) -> CargoResult<CliFeatures> {
+        let mut features = BTreeSet::new();
+        for spec in CliFeatures::split_specs(specs) {
+            let feature = FeatureValue::new(InternedString::new(spec));
+            // Maybe call validate_feature_name here once it is an error?
+            match &feature {
                 FeatureValue::Feature(_) => {}
                 FeatureValue::Dep { .. } => {
                     bail!(
                         "feature `{}` is not allowed to use explicit `dep:` syntax",
-                        feature
+                        spec
                     );
                 }
-                FeatureValue::DepFeature { dep_feature, .. } => {
+                FeatureValue::DepFeature {
+                    dep_name,
+                    dep_feature,
+                    ..
+                } => {
                     if dep_feature.contains('/') {
-                        bail!("multiple slashes in feature `{}` is not allowed", feature);
+                        bail!("multiple slashes in feature `{}` is not allowed", spec);
+                    }
+                    if dep_name.starts_with("dep:") {
+                        bail!(
+                            "feature `{}` is not allowed to use explicit `dep:` syntax",
+                            spec
+                        );
                     }
+                    if dep_name.is_empty() {
+                        bail!("feature `{}` is missing a dependency name before `/`", spec);
+                    }
+                    if dep_feature.is_empty() {
+                        bail!("feature `{}` is missing a feature name after `/`", spec);
+                    }
+                }
+                FeatureValue::TargetFeature { .. } => {
+                    bail!(
+                        "feature `{}` is not allowed to use `cfg(...):` syntax",
+                        spec
+                    );
                 }
             }
+            features.insert(feature);
         }
         Ok(CliFeatures {
-            features,
-            all_features,
-            uses_default_features,
+            features: Rc::new(features),
+            all_features: false,
+            uses_default_features: true,
+        })
+    }
+
+    /// Creates a new CliFeatures from the environment variables
+    /// `<prefix>_FEATURES`, `<prefix>_ALL_FEATURES` and
+    /// `<prefix>_NO_DEFAULT_FEATURES`, which stand for `--features`,
+    /// `--all-features` and `--no-default-features`, as seen by `config`.
+    /// The last two take `true` or `false`, and an unset variable means
+    /// `false`.
+    ///
+    /// The environment is only a fallback for the command line: when features
+    /// are also given explicitly, use those and ignore the environment
+    /// entirely, rather than merging both.
+    pub fn from_env(config: &Config, prefix: &str) -> CargoResult<CliFeatures> {
+        let var = |suffix: &str| -> CargoResult<(String, Option<String>)> {
+            let key = format!("{}_{}", prefix, suffix);
+            if config.get_env_os(&key).is_none() {
+                return Ok((key, None));
+            }
+            let value = config
+                .get_env(&key)
+                .with_context(|| format!("failed to read `{}`", key))?;
+            Ok((key, Some(value)))
+        };
+        let flag = |suffix: &str| -> CargoResult<bool> {
+            match var(suffix)? {
+                (_, None) => Ok(false),
+                (_, Some(value)) if value == "true" => Ok(true),
+                (_, Some(value)) if value == "false" => Ok(false),
+                (key, Some(value)) => {
+                    bail!("`{}` must be `true` or `false`, but is `{}`", key, value)
+                }
+            }
+        };
+        let (key, features) = var("FEATURES")?;
+        let features: Vec<_> = features.into_iter().collect();
+        Ok(CliFeatures {
+            all_features: flag("ALL_FEATURES")?,
+            uses_default_features: !flag("NO_DEFAULT_FEATURES")?,
+            ..CliFeatures::from_feature_specs(&features)
+                .with_context(|| format!("failed to parse `{}`", key))?
         })
     }
 
@@ -300,15 +393,12 @@ impl CliFeatures {
         }
     }
 
-    fn split_features(features: &[String]) -> BTreeSet<FeatureValue> {
-        features
+    fn split_specs(specs: &[String]) -> impl Iterator<Item = &str> {
+        specs
             .iter()
             .flat_map(|s| s.split_whitespace())
             .flat_map(|s| s.split(','))
             .filter(|s| !s.is_empty())
-            .map(InternedString::new)
-            .map(FeatureValue::new)
-            .collect()
     }
 }
 
@@ -582,6 +672,21 @@ impl<'a, 'cfg> FeatureResolver<'a, 'cfg> {
             } => {
                 self.activate_dep_feature(pkg_id, fk, *dep_name, *dep_feature, *weak)?;
             }
+            FeatureValue::TargetFeature { cfg, value } => {
+                let applies = match fk {
+                    FeaturesFor::HostDep => cfg.matches(self.target_data.cfg(CompileKind::Host)),
+                    FeaturesFor::NormalOrDev => self
+                        .requested_targets
+                        .iter()
+                        .any(|kind| cfg.matches(self.target_data.cfg(*kind))),
+                    FeaturesFor::ArtifactDep(target) => {
+                        cfg.matches(self.target_data.cfg(CompileKind::Target(target)))
+                    }
+                };
+                if applies {
+                    self.activate_fv(pkg_id, fk, value)?;
+                }
+            }
         }
         Ok(())
     }
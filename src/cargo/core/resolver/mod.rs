@@ -61,6 +61,8 @@
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::mem;
 use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use tracing::{debug, trace};
@@ -74,18 +76,34 @@ use crate::util::profile;
 use crate::util::PartialVersion;
 
 use self::context::Context;
-use self::dep_cache::RegistryQueryer;
 use self::features::RequestedFeatures;
 use self::types::{ConflictMap, ConflictReason, DepsFrame};
 use self::types::{FeaturesSet, RcVecIter, RemainingDeps, ResolverProgress};
 
+pub use self::dep_cache::{
+    cli_feature_uses, diff_build_deps_results, feature_origins, features_from_cli,
+    minimal_features_enabling_dep, order_deps_by_constrainedness, redundant_cli_features,
+    requirement_errors, resolve_features_reporting_skipped, union_edge_features, BuildDepsDiff,
+    BuildDepsResult, CandidateCounts, CliFeatureUse, ExportedDependency, ExportedQuery,
+    ExportedSummary, FeatureOrigin, FeatureUnion, MinimalOrderingCounts, QueryExplanation,
+    RegistryQueryer, ReplacementOutcome, ReplacementReport, ReplacementStatus, ReplayRegistry,
+    RequirementError, RequirementWarning, RequirementsBuilder, ResolutionTrace,
+    SerializedRequirementError, SlowResolveWarning, TraceNode, TraceOutcome, WhyNot,
+};
 pub use self::encode::Metadata;
 pub use self::encode::{EncodableDependency, EncodablePackageId, EncodableResolve};
-pub use self::errors::{ActivateError, ActivateResult, ResolveError};
+pub use self::errors::{
+    ActivateError, ActivateResult, AmbiguousSourceError, ResolveCancelled, ResolveError,
+};
 pub use self::features::{CliFeatures, ForceAllTargets, HasDevUnits};
 pub use self::resolve::{Resolve, ResolveVersion};
-pub use self::types::{ResolveBehavior, ResolveOpts};
-pub use self::version_prefs::{VersionOrdering, VersionPreferences};
+pub use self::types::{
+    ConflictKind, ConflictPolicy, DepInfo, ExtraFeatures, ResolveBehavior, ResolveOpts,
+    SerializedConflict,
+};
+pub use self::version_prefs::{
+    BuildMetadataPreference, PreferenceInfluence, VersionOrdering, VersionPreferences,
+};
 
 mod conflict_cache;
 mod context;
@@ -132,6 +150,12 @@ mod version_prefs;
 ///
 ///     When we have a decision for how to implement is without breaking existing functionality
 ///     this flag can be removed.
+///
+/// * `cancel` - a flag another thread can set to stop the resolve, which then
+///   fails with [`ResolveCancelled`], for example in an editor whose user kept
+///   typing.
+///
+/// See [`resolve_with_queryer`] to configure the [`RegistryQueryer`] further.
 pub fn resolve(
     summaries: &[(Summary, ResolveOpts)],
     replacements: &[(PackageIdSpec, Dependency)],
@@ -140,16 +164,12 @@ pub fn resolve(
     config: Option<&Config>,
     check_public_visible_dependencies: bool,
     mut max_rust_version: Option<PartialVersion>,
+    cancel: Option<Arc<AtomicBool>>,
 ) -> CargoResult<Resolve> {
-    let _p = profile::start("resolving");
     let minimal_versions = match config {
         Some(config) => config.cli_unstable().minimal_versions,
         None => false,
     };
-    let direct_minimal_versions = match config {
-        Some(config) => config.cli_unstable().direct_minimal_versions,
-        None => false,
-    };
     if !config
         .map(|c| c.cli_unstable().msrv_policy)
         .unwrap_or(false)
@@ -163,29 +183,68 @@ pub fn resolve(
         minimal_versions,
         max_rust_version,
     );
+    if let Some(cancel) = cancel {
+        registry.set_cancel(cancel);
+    }
+    resolve_with_queryer(
+        summaries,
+        &mut registry,
+        config,
+        check_public_visible_dependencies,
+    )
+}
+
+/// Like [`resolve`], but with a `registry` the caller made and configured.
+///
+/// This is for tools that need the options of [`RegistryQueryer`] that cargo
+/// itself does not use, such as a dependency priority or a version limit, or
+/// want to look at what it recorded once the resolve is done, such as the
+/// conflicts or the queries. `-Z minimal-versions` and `--ignore-rust-version`
+/// are left to the `RegistryQueryer`, `config` is only used for
+/// `-Z direct-minimal-versions` and to print progress and warnings.
+pub fn resolve_with_queryer(
+    summaries: &[(Summary, ResolveOpts)],
+    registry: &mut RegistryQueryer<'_>,
+    config: Option<&Config>,
+    check_public_visible_dependencies: bool,
+) -> CargoResult<Resolve> {
+    let _p = profile::start("resolving");
+    let direct_minimal_versions = match config {
+        Some(config) => config.cli_unstable().direct_minimal_versions,
+        None => false,
+    };
+    let forbid_yanked = summaries.iter().any(|(_, opts)| opts.forbid_yanked);
+    let forbid_downgrade = summaries.iter().any(|(_, opts)| opts.forbid_downgrade);
     let cx = loop {
         let cx = Context::new(check_public_visible_dependencies);
-        let cx = activate_deps_loop(
-            cx,
-            &mut registry,
-            summaries,
-            direct_minimal_versions,
-            config,
-        )?;
+        let cx = activate_deps_loop(cx, registry, summaries, direct_minimal_versions, config)?;
         if registry.reset_pending() {
             break cx;
         } else {
             registry.registry.block_until_ready()?;
         }
     };
+    let (registry_time, compute_time) = registry.timing_breakdown();
+    debug!(
+        "resolver spent {:?} in the registry and {:?} computing",
+        registry_time, compute_time
+    );
 
     let mut cksums = HashMap::new();
     for (summary, _) in cx.activations.values() {
         let cksum = summary.checksum().map(|s| s.to_string());
         cksums.insert(summary.package_id(), cksum);
     }
+    if let Some(config) = config {
+        // Only report the packages that made it into the final graph.
+        for warning in registry.warnings() {
+            if cksums.contains_key(&warning.package_id()) {
+                config.shell().warn(warning)?;
+            }
+        }
+    }
     let graph = cx.graph();
-    let replacements = cx.resolve_replacements(&registry);
+    let replacements = cx.resolve_replacements(registry);
     let features = cx
         .resolve_features
         .iter()
@@ -209,6 +268,12 @@ pub fn resolve(
 
     check_cycles(&resolve)?;
     check_duplicate_pkgs_in_lockfile(&resolve)?;
+    if forbid_downgrade {
+        registry.check_not_downgraded(resolve.iter())?;
+    }
+    if forbid_yanked {
+        registry.check_resolve_not_yanked(&resolve)?;
+    }
     trace!("resolved: {:?}", resolve);
 
     Ok(resolve)
@@ -242,13 +307,16 @@ fn activate_deps_loop(
             None,
             summary.clone(),
             direct_minimal_versions,
-            opts,
+            &Rc::new(opts.clone()),
         );
         match res {
             Ok(Some((frame, _))) => remaining_deps.push(frame),
             Ok(None) => (),
             Err(ActivateError::Fatal(e)) => return Err(e),
-            Err(ActivateError::Conflict(_, _)) => panic!("bad error from activate"),
+            Err(ActivateError::Cancelled) => return Err(ResolveCancelled.into()),
+            Err(ActivateError::Conflict(_, _) | ActivateError::Conflicts(_)) => {
+                panic!("bad error from activate")
+            }
         }
     }
 
@@ -270,7 +338,7 @@ fn activate_deps_loop(
     while let Some((just_here_for_the_error_messages, frame)) =
         remaining_deps.pop_most_constrained()
     {
-        let (mut parent, (mut dep, candidates, mut features)) = frame;
+        let (mut parent, mut parent_opts, (mut dep, candidates, mut features, _)) = frame;
 
         // If we spend a lot of time here (we shouldn't in most cases) then give
         // a bit of a visual indicator as to what we're doing.
@@ -374,6 +442,7 @@ fn activate_deps_loop(
                         remaining_deps = frame.remaining_deps;
                         remaining_candidates = frame.remaining_candidates;
                         parent = frame.parent;
+                        parent_opts = frame.parent_opts;
                         dep = frame.dep;
                         features = frame.features;
                         conflicting_activations = frame.conflicting_activations;
@@ -382,6 +451,7 @@ fn activate_deps_loop(
                     }
                     None => {
                         debug!("no candidates found");
+                        let candidate_counts = registry.candidate_counts(&dep);
                         Err(errors::activation_error(
                             &cx,
                             registry.registry,
@@ -389,6 +459,7 @@ fn activate_deps_loop(
                             &dep,
                             &conflicting_activations,
                             &candidates,
+                            candidate_counts,
                             config,
                         ))
                     }
@@ -418,6 +489,7 @@ fn activate_deps_loop(
                     remaining_deps: remaining_deps.clone(),
                     remaining_candidates: remaining_candidates.clone(),
                     parent: Summary::clone(&parent),
+                    parent_opts: Rc::clone(&parent_opts),
                     dep: Dependency::clone(&dep),
                     features: Rc::clone(&features),
                     conflicting_activations: conflicting_activations.clone(),
@@ -427,13 +499,10 @@ fn activate_deps_loop(
             };
 
             let pid = candidate.package_id();
-            let opts = ResolveOpts {
-                dev_deps: false,
-                features: RequestedFeatures::DepFeatures {
-                    features: Rc::clone(&features),
-                    uses_default_features: dep.uses_default_features(),
-                },
-            };
+            let opts = Rc::new(parent_opts.for_dependency(RequestedFeatures::DepFeatures {
+                features: Rc::clone(&features),
+                uses_default_features: dep.uses_default_features(),
+            }));
             trace!(
                 "{}[{}]>{} trying {}",
                 parent.name(),
@@ -478,7 +547,7 @@ fn activate_deps_loop(
                         if let Some(conflicting) = frame
                             .remaining_siblings
                             .clone()
-                            .filter_map(|(ref new_dep, _, _)| {
+                            .filter_map(|(ref new_dep, _, _, _)| {
                                 past_conflicting_activations.conflicting(&cx, new_dep)
                             })
                             .next()
@@ -612,13 +681,24 @@ fn activate_deps_loop(
                 // backtrack from errors like these
                 Err(ActivateError::Fatal(e)) => return Err(e),
 
+                // Whoever started the resolve no longer wants it.
+                Err(ActivateError::Cancelled) => return Err(ResolveCancelled.into()),
+
                 // We failed due to a bland conflict, bah! Record this in our
                 // frame's list of conflicting activations as to why this
                 // candidate failed, and then move on.
                 Err(ActivateError::Conflict(id, reason)) => {
+                    registry.trace_backtrack(parent.package_id(), pid, id, &reason);
                     conflicting_activations.insert(id, reason);
                     false
                 }
+                Err(ActivateError::Conflicts(conflicts)) => {
+                    if let Some((&id, reason)) = conflicts.iter().next() {
+                        registry.trace_backtrack(parent.package_id(), pid, id, reason);
+                    }
+                    conflicting_activations.extend(conflicts);
+                    false
+                }
             };
 
             // If we've successfully activated then save off the backtrack frame
@@ -662,9 +742,12 @@ fn activate(
     parent: Option<(&Summary, &Dependency)>,
     candidate: Summary,
     first_minimal_version: bool,
-    opts: &ResolveOpts,
+    opts: &Rc<ResolveOpts>,
 ) -> ActivateResult<Option<(DepsFrame, Duration)>> {
     let candidate_pid = candidate.package_id();
+    if opts.forbid_yanked {
+        registry.check_yanked(&candidate, parent)?;
+    }
     cx.age += 1;
     if let Some((parent, dep)) = parent {
         let parent_pid = parent.package_id();
@@ -733,6 +816,7 @@ fn activate(
 
     let frame = DepsFrame {
         parent: candidate,
+        opts: Rc::clone(opts),
         just_for_error_messages: false,
         remaining_siblings: RcVecIter::new(Rc::clone(deps)),
     };
@@ -745,6 +829,7 @@ struct BacktrackFrame {
     remaining_deps: RemainingDeps,
     remaining_candidates: RemainingCandidates,
     parent: Summary,
+    parent_opts: Rc<ResolveOpts>,
     dep: Dependency,
     features: FeaturesSet,
     conflicting_activations: ConflictMap,
@@ -1,8 +1,11 @@
 use super::features::{CliFeatures, RequestedFeatures};
+use crate::core::dependency::DepKind;
 use crate::core::{Dependency, PackageId, Summary};
 use crate::util::errors::CargoResult;
 use crate::util::interning::InternedString;
 use crate::util::Config;
+use cargo_platform::Cfg;
+use serde::Serialize;
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, BTreeSet};
 use std::ops::Range;
@@ -135,6 +138,13 @@ impl ResolveBehavior {
 }
 
 /// Options for how the resolve should work.
+///
+/// Apart from `dev_deps` and `features`, the options apply to the whole
+/// resolve: they are passed on to every dependency activated from a package.
+///
+/// The options are the key of the resolver caches, so the settings that can
+/// be written more than one way are kept private and normalized by their
+/// setters: options that activate the same way always compare equal.
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct ResolveOpts {
     /// Whether or not dev-dependencies should be included.
@@ -144,25 +154,165 @@ pub struct ResolveOpts {
     pub dev_deps: bool,
     /// Set of features requested on the command-line.
     pub features: RequestedFeatures,
+    /// How feature conflicts are handled, for tools that have to cope with
+    /// legacy manifests. Kinds not listed here are errors, and so the
+    /// default policy is never listed.
+    conflict_policy: Rc<BTreeMap<ConflictKind, ConflictPolicy>>,
+    /// Activate every optional dependency, whether or not a feature enables it.
+    ///
+    /// This goes further than `--all-features`, as it also reaches optional
+    /// dependencies that are only enabled through `dep:` values.
+    pub all_optional_deps: bool,
+    /// Activate every dependency with every feature the package could enable
+    /// on it, without going through the features of the package at all.
+    ///
+    /// This is the superset of everything the package could depend on, for
+    /// tools like vulnerability scanners. Requested features are ignored.
+    pub all_deps_superset: bool,
+    /// Never enable the `default` feature implicitly, as if every dependency
+    /// and root package had `default-features = false`.
+    ///
+    /// Asking for `default` by name still enables it.
+    pub no_default_features: bool,
+    /// Features to enable on top of the requested ones for every package
+    /// with the given name, wherever it appears in the graph.
+    ///
+    /// This is part of the options a package is cached with, so the extra
+    /// features of one package never end up on another. Packages without
+    /// extra features are never listed.
+    per_package_overrides: Rc<BTreeMap<InternedString, ExtraFeatures>>,
+    /// Fail whenever the implicit feature of an optional dependency is
+    /// enabled, to find what still needs to move to `dep:` values.
+    pub no_implicit_features: bool,
+    /// Backtrack away from versions marked yanked in the `VersionPreferences`,
+    /// failing the resolve if they cannot be avoided, instead of only trying
+    /// such versions last.
+    pub forbid_yanked: bool,
+    /// Fail the resolve if it ends up with a version older than the one
+    /// locked in the `VersionPreferences`, as a safety rail for updates.
+    pub forbid_downgrade: bool,
+    /// The `cfg` values of the target being resolved for, which decide
+    /// whether `cfg(...):feature` values apply.
+    ///
+    /// With `None` the resolve covers every target, so those values always
+    /// apply. The values are sorted and deduplicated.
+    target_cfg: Option<Rc<Vec<Cfg>>>,
+}
+
+/// Features [`ResolveOpts::per_package_overrides`] adds to a package.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct ExtraFeatures {
+    pub features: BTreeSet<InternedString>,
 }
 
 impl ResolveOpts {
     /// Creates a ResolveOpts that resolves everything.
     pub fn everything() -> ResolveOpts {
+        ResolveOpts::new(
+            true,
+            RequestedFeatures::CliFeatures(CliFeatures::new_all(true)),
+        )
+    }
+
+    pub fn new(dev_deps: bool, features: RequestedFeatures) -> ResolveOpts {
         ResolveOpts {
-            dev_deps: true,
-            features: RequestedFeatures::CliFeatures(CliFeatures::new_all(true)),
+            dev_deps,
+            features,
+            conflict_policy: Rc::default(),
+            all_optional_deps: false,
+            all_deps_superset: false,
+            no_default_features: false,
+            per_package_overrides: Rc::default(),
+            no_implicit_features: false,
+            forbid_yanked: false,
+            forbid_downgrade: false,
+            target_cfg: None,
         }
     }
 
-    pub fn new(dev_deps: bool, features: RequestedFeatures) -> ResolveOpts {
-        ResolveOpts { dev_deps, features }
+    /// Creates the options for activating a dependency of a package resolved
+    /// with `self`, keeping the settings that apply to the whole resolve.
+    pub fn for_dependency(&self, features: RequestedFeatures) -> ResolveOpts {
+        ResolveOpts {
+            dev_deps: false,
+            features,
+            ..self.clone()
+        }
+    }
+
+    /// Returns how conflicts of the given `kind` are handled.
+    pub fn conflict_policy(&self, kind: ConflictKind) -> ConflictPolicy {
+        self.conflict_policy.get(&kind).copied().unwrap_or_default()
+    }
+
+    /// Sets how conflicts of the given `kind` are handled.
+    pub fn set_conflict_policy(&mut self, kind: ConflictKind, policy: ConflictPolicy) {
+        let conflict_policy = Rc::make_mut(&mut self.conflict_policy);
+        if policy == ConflictPolicy::default() {
+            conflict_policy.remove(&kind);
+        } else {
+            conflict_policy.insert(kind, policy);
+        }
+    }
+
+    /// Returns the features added to every package named `package`.
+    pub fn extra_features(&self, package: InternedString) -> Option<&ExtraFeatures> {
+        self.per_package_overrides.get(&package)
+    }
+
+    /// Sets the features added to every package named `package`, replacing
+    /// the ones set before.
+    pub fn set_extra_features(&mut self, package: InternedString, extra: ExtraFeatures) {
+        let overrides = Rc::make_mut(&mut self.per_package_overrides);
+        if extra.features.is_empty() {
+            overrides.remove(&package);
+        } else {
+            overrides.insert(package, extra);
+        }
+    }
+
+    /// Returns the `cfg` values of the target being resolved for, if any.
+    pub fn target_cfg(&self) -> Option<&[Cfg]> {
+        self.target_cfg.as_deref().map(Vec::as_slice)
     }
+
+    /// Sets the `cfg` values of the target being resolved for, or `None` to
+    /// resolve for every target.
+    pub fn set_target_cfg(&mut self, target_cfg: Option<Vec<Cfg>>) {
+        self.target_cfg = target_cfg.map(|mut cfgs| {
+            cfgs.sort();
+            cfgs.dedup();
+            Rc::new(cfgs)
+        });
+    }
+}
+
+/// The feature-related [`ConflictReason`]s that a [`ConflictPolicy`] can relax.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub enum ConflictKind {
+    /// See [`ConflictReason::RequiredDependencyAsFeature`].
+    RequiredDependencyAsFeature,
+    /// See [`ConflictReason::NonImplicitDependencyAsFeature`].
+    NonImplicitDependencyAsFeature,
+}
+
+/// How the resolver reacts to a [`ConflictKind`].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub enum ConflictPolicy {
+    /// Reject the candidate, or fail if it is a root package.
+    #[default]
+    Error,
+    /// Enable the dependency the feature is named after and record a warning.
+    Warn,
+    /// Enable the dependency the feature is named after without a warning.
+    Ignore,
 }
 
 #[derive(Clone)]
 pub struct DepsFrame {
     pub parent: Summary,
+    /// The options `parent` was activated with.
+    pub opts: Rc<ResolveOpts>,
     pub just_for_error_messages: bool,
     pub remaining_siblings: RcVecIter<DepInfo>,
 }
@@ -177,14 +327,14 @@ impl DepsFrame {
     fn min_candidates(&self) -> usize {
         self.remaining_siblings
             .peek()
-            .map(|(_, (_, candidates, _))| candidates.len())
+            .map(|(_, (_, candidates, _, _))| candidates.len())
             .unwrap_or(0)
     }
 
     pub fn flatten(&self) -> impl Iterator<Item = (PackageId, Dependency)> + '_ {
         self.remaining_siblings
             .clone()
-            .map(move |(d, _, _)| (self.parent.package_id(), d))
+            .map(move |(d, _, _, _)| (self.parent.package_id(), d))
     }
 }
 
@@ -240,7 +390,7 @@ impl RemainingDeps {
         self.data.insert((x, insertion_time));
         self.time += 1;
     }
-    pub fn pop_most_constrained(&mut self) -> Option<(bool, (Summary, DepInfo))> {
+    pub fn pop_most_constrained(&mut self) -> Option<(bool, (Summary, Rc<ResolveOpts>, DepInfo))> {
         while let Some((mut deps_frame, insertion_time)) = self.data.remove_min() {
             let just_here_for_the_error_messages = deps_frame.just_for_error_messages;
 
@@ -249,8 +399,9 @@ impl RemainingDeps {
             // move on to the next frame.
             if let Some(sibling) = deps_frame.remaining_siblings.next() {
                 let parent = Summary::clone(&deps_frame.parent);
+                let opts = Rc::clone(&deps_frame.opts);
                 self.data.insert((deps_frame, insertion_time));
-                return Some((just_here_for_the_error_messages, (parent, sibling)));
+                return Some((just_here_for_the_error_messages, (parent, opts, sibling)));
             }
         }
         None
@@ -262,8 +413,11 @@ impl RemainingDeps {
 
 /// Information about the dependencies for a crate, a tuple of:
 ///
-/// (dependency info, candidates, features activated)
-pub type DepInfo = (Dependency, Rc<Vec<Summary>>, FeaturesSet);
+/// (dependency info, candidates, features activated, kind of the dependency)
+///
+/// The kind is the same as `Dependency::kind`, kept alongside so consumers
+/// do not need to go back to the `Dependency` for it.
+pub type DepInfo = (Dependency, Rc<Vec<Summary>>, FeaturesSet, DepKind);
 
 /// All possible reasons that a package might fail to activate.
 ///
@@ -297,6 +451,23 @@ pub enum ConflictReason {
     /// optional dependency is "hidden" using namespaced `dep:` syntax.
     NonImplicitDependencyAsFeature(InternedString),
 
+    /// A dependency asked for a feature that cannot be enabled together with
+    /// another feature it also enabled, as both are in one of the candidate's
+    /// exclusive feature groups.
+    MutuallyExclusiveFeatures(InternedString, InternedString),
+
+    /// A dependency enabled the first feature of a candidate without also
+    /// enabling the second one, which the first requires.
+    UnmetFeaturePrecondition(InternedString, InternedString),
+
+    /// The candidate would go past the most versions of its name that may be
+    /// activated at once, see `RegistryQueryer::set_version_limit`.
+    VersionLimit(usize),
+
+    /// The candidate is yanked, and yanked versions are not allowed by
+    /// `ResolveOpts::forbid_yanked`.
+    Yanked,
+
     // TODO: needs more info for `activation_error`
     // TODO: needs more info for `find_candidate`
     /// pub dep error
@@ -323,6 +494,86 @@ impl ConflictReason {
             ConflictReason::PublicDependency(_) | ConflictReason::PubliclyExports(_)
         )
     }
+
+    /// Describes this reason for a conflict with `pkg_id` in a form tools can consume.
+    pub fn to_serialized(&self, pkg_id: PackageId) -> SerializedConflict {
+        let mut out = SerializedConflict {
+            schema_version: SerializedConflict::SCHEMA_VERSION,
+            kind: "",
+            feature: None,
+            features: Vec::new(),
+            dependency: None,
+            links: None,
+            package_ids: vec![pkg_id],
+        };
+        match self {
+            ConflictReason::Semver => out.kind = "semver",
+            ConflictReason::Links(links) => {
+                out.kind = "links";
+                out.links = Some(links.to_string());
+            }
+            ConflictReason::MissingFeatures(feature) => {
+                out.kind = "missing-features";
+                out.feature = Some(feature.clone());
+            }
+            ConflictReason::RequiredDependencyAsFeature(name) => {
+                out.kind = "required-dependency-as-feature";
+                out.feature = Some(name.to_string());
+                out.dependency = Some(name.to_string());
+            }
+            ConflictReason::NonImplicitDependencyAsFeature(name) => {
+                out.kind = "non-implicit-dependency-as-feature";
+                out.feature = Some(name.to_string());
+                out.dependency = Some(name.to_string());
+            }
+            ConflictReason::MutuallyExclusiveFeatures(a, b) => {
+                out.kind = "mutually-exclusive-features";
+                out.features = vec![a.to_string(), b.to_string()];
+            }
+            ConflictReason::UnmetFeaturePrecondition(feat, required) => {
+                out.kind = "unmet-feature-precondition";
+                out.features = vec![feat.to_string(), required.to_string()];
+            }
+            ConflictReason::VersionLimit(_) => out.kind = "version-limit",
+            ConflictReason::Yanked => out.kind = "yanked",
+            ConflictReason::PublicDependency(other) => {
+                out.kind = "public-dependency";
+                out.package_ids.push(*other);
+            }
+            ConflictReason::PubliclyExports(other) => {
+                out.kind = "publicly-exports";
+                out.package_ids.push(*other);
+            }
+        }
+        out
+    }
+}
+
+/// A machine-readable [`ConflictReason`], for IDEs and other tools that
+/// want to explain a failed resolve without parsing the error message.
+#[derive(Debug, Clone, Serialize)]
+pub struct SerializedConflict {
+    /// Always [`SerializedConflict::SCHEMA_VERSION`] for the layout described here.
+    pub schema_version: u32,
+    /// The kind of conflict, such as `"semver"` or `"missing-features"`.
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feature: Option<String>,
+    /// The features involved, for the kinds of conflict about more than one,
+    /// in the order the [`ConflictReason`] lists them.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dependency: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub links: Option<String>,
+    /// The packages involved, starting with the one the conflict is with.
+    pub package_ids: Vec<PackageId>,
+}
+
+impl SerializedConflict {
+    /// Bumped whenever a field changes meaning or is removed.
+    pub const SCHEMA_VERSION: u32 = 1;
 }
 
 /// A list of packages that have gotten in the way of resolving a dependency.
@@ -4,7 +4,9 @@
 use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 
-use crate::core::{Dependency, PackageId, Summary};
+use semver::Version;
+
+use crate::core::{Dependency, Edition, PackageId, SourceId, Summary};
 use crate::util::interning::InternedString;
 
 /// A collection of preferences for particular package versions.
@@ -18,11 +20,72 @@ use crate::util::interning::InternedString;
 pub struct VersionPreferences {
     try_to_use: HashSet<PackageId>,
     prefer_patch_deps: HashMap<InternedString, HashSet<Dependency>>,
+    /// Names of the root's direct dependencies, which ignore `try_to_use` so
+    /// they can float to new versions while everything else stays locked.
+    float_direct_deps: Option<HashSet<InternedString>>,
+    /// Versions that are usable but only tried after the other candidates.
+    discouraged: HashSet<PackageId>,
+    /// Yanked versions that are still candidates, tried after discouraged ones.
+    yanked: HashSet<PackageId>,
+    /// Major versions of a package that are only tried after the other ones,
+    /// treated like discouraged versions.
+    avoided_majors: HashMap<InternedString, HashSet<u64>>,
+    build_metadata: BuildMetadataPreference,
+    /// The `major.minor` of the versions given to [`Self::patch_only_updates`].
+    locked_minors: HashMap<(InternedString, SourceId), HashSet<(u64, u64)>>,
+    /// The source that wins over the others for the same version, see
+    /// [`Self::set_canonical_source`].
+    canonical_source: Option<SourceId>,
+    /// The edition whose versions are tried first, see
+    /// [`Self::set_preferred_edition`].
+    preferred_edition: Option<Edition>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum VersionOrdering {
     MaximumVersionsFirst,
+    /// Lowest versions first, still after the preferred ones and before the
+    /// discouraged ones. This is what `-Z minimal-versions` uses, so the
+    /// lower bounds authors declare get exercised.
     MinimumVersionsFirst,
+    /// Lowest versions first, ignoring every preference: locked and `[patch]`
+    /// versions are not tried first and discouraged ones are not tried last.
+    /// Only yanked versions still come last.
+    ///
+    /// Unlike [`VersionOrdering::MinimumVersionsFirst`] this lands on the
+    /// oldest version the registry has for each requirement, whatever the lock
+    /// file says, to test against the real floor of the dependency graph.
+    OldestPublished,
+}
+
+/// Which of two versions differing only in build metadata, like `1.0.0` and
+/// `1.0.0+patched`, [`VersionPreferences::sort_summaries`] tries first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BuildMetadataPreference {
+    /// Semver gives build metadata no precedence, so fall back on comparing
+    /// it as text, just to keep the order deterministic.
+    #[default]
+    Ignore,
+    /// Try versions with build metadata first.
+    PreferPresent,
+    /// Try versions without build metadata first.
+    PreferAbsent,
+}
+
+/// What made [`VersionPreferences::sort_summaries`] place a version where it did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PreferenceInfluence {
+    /// The version was preferred through [`VersionPreferences::prefer_package_id`],
+    /// typically because it is in the lock file.
+    Locked,
+    /// The version was preferred through [`VersionPreferences::prefer_dependency`],
+    /// typically because it matches a `[patch]` entry.
+    Pinned,
+    /// The version has the `major.minor` of a version given to
+    /// [`VersionPreferences::patch_only_updates`].
+    LockedMinor,
+    /// No preference applied, the version was placed by [`VersionOrdering`] alone.
+    VersionOrder,
 }
 
 impl VersionPreferences {
@@ -39,38 +102,235 @@ impl VersionPreferences {
             .insert(dep);
     }
 
+    /// Indicate that the given package should only be used if no other version
+    /// fits, short of a yanked one.
+    pub fn discourage_package_id(&mut self, pkg_id: PackageId) {
+        self.discouraged.insert(pkg_id);
+    }
+
+    /// Indicate that the given package is yanked, so it is tried after every
+    /// other version, including discouraged ones.
+    pub fn mark_yanked(&mut self, pkg_id: PackageId) {
+        self.yanked.insert(pkg_id);
+    }
+
+    /// Whether `pkg_id` was marked yanked with [`Self::mark_yanked`].
+    pub fn is_yanked(&self, pkg_id: PackageId) -> bool {
+        self.yanked.contains(&pkg_id)
+    }
+
+    /// Indicate that versions of `name` with the given `major` version should
+    /// only be used if no other version fits, like discouraged versions.
+    ///
+    /// Unlike a version ceiling, this can avoid a major version in the middle
+    /// while older and newer ones are still tried first.
+    pub fn avoid_major(&mut self, name: InternedString, major: u64) {
+        self.avoided_majors.entry(name).or_default().insert(major);
+    }
+
+    /// Sets how versions that only differ in build metadata are ordered, as a
+    /// last tiebreak after every other preference.
+    pub fn set_build_metadata_preference(&mut self, preference: BuildMetadataPreference) {
+        self.build_metadata = preference;
+    }
+
+    /// Indicate that when the same version is available from several sources,
+    /// the one from `source_id` is tried first.
+    ///
+    /// This is meant for crates.io and the mirrors serving copies of it: the
+    /// versions are identical, but resolving to the canonical source keeps the
+    /// lock file reproducible for those who don't use the mirror. Unlike a
+    /// general source priority, a newer version from a mirror still wins.
+    pub fn set_canonical_source(&mut self, source_id: SourceId) {
+        self.canonical_source = Some(source_id);
+    }
+
+    /// Indicate that versions written in `edition` are tried before those
+    /// written in another one, e.g. the edition of the workspace.
+    ///
+    /// This only reorders candidates, it never removes any. Versions whose
+    /// edition is not known go between the two, and the preferred versions
+    /// (locked, `[patch]`, ...) still come first.
+    pub fn set_preferred_edition(&mut self, edition: Edition) {
+        self.preferred_edition = Some(edition);
+    }
+
+    /// Prefer, for each of the `locked` packages, the versions with the same
+    /// `major.minor` from the same source, so an update picks the newest
+    /// patch release without moving to a new minor version.
+    ///
+    /// Other versions are still tried after those, for when a requirement
+    /// forces another minor version or none of them fit.
+    pub fn patch_only_updates(&mut self, locked: &[PackageId]) {
+        for pkg_id in locked {
+            let version = pkg_id.version();
+            self.locked_minors
+                .entry((pkg_id.name(), pkg_id.source_id()))
+                .or_default()
+                .insert((version.major, version.minor));
+        }
+    }
+
+    /// Only keep the locked versions of transitive dependencies, letting the given
+    /// direct dependencies of the root use the normal version ordering.
+    ///
+    /// A package that is both a direct and a transitive dependency is treated as direct.
+    pub fn float_direct_dependencies(&mut self, names: impl IntoIterator<Item = InternedString>) {
+        self.float_direct_deps = Some(names.into_iter().collect());
+    }
+
+    /// Returns which preference, if any, moves `pkg_id` ahead of the plain version ordering.
+    pub fn influence(&self, pkg_id: PackageId) -> PreferenceInfluence {
+        let floats = self
+            .float_direct_deps
+            .as_ref()
+            .map(|names| names.contains(&pkg_id.name()))
+            .unwrap_or(false);
+        if !floats && self.try_to_use.contains(&pkg_id) {
+            PreferenceInfluence::Locked
+        } else if self
+            .prefer_patch_deps
+            .get(&pkg_id.name())
+            .map(|deps| deps.iter().any(|d| d.matches_id(pkg_id)))
+            .unwrap_or(false)
+        {
+            PreferenceInfluence::Pinned
+        } else if !floats
+            && self
+                .locked_minors
+                .get(&(pkg_id.name(), pkg_id.source_id()))
+                .map(|minors| {
+                    let version = pkg_id.version();
+                    minors.contains(&(version.major, version.minor))
+                })
+                .unwrap_or(false)
+        {
+            PreferenceInfluence::LockedMinor
+        } else {
+            PreferenceInfluence::VersionOrder
+        }
+    }
+
+    /// Returns the versions that are preferred because they are locked, see
+    /// [`PreferenceInfluence::Locked`].
+    pub fn locked_package_ids(&self) -> impl Iterator<Item = PackageId> + '_ {
+        self.try_to_use
+            .iter()
+            .copied()
+            .filter(|pkg_id| self.influence(*pkg_id) == PreferenceInfluence::Locked)
+    }
+
+    /// The rank of `pkg_id` among the versions with the same preference:
+    /// normal versions, then discouraged ones, then yanked ones.
+    fn tier(&self, pkg_id: PackageId) -> u8 {
+        if self.yanked.contains(&pkg_id) {
+            2
+        } else if self.discouraged.contains(&pkg_id)
+            || self
+                .avoided_majors
+                .get(&pkg_id.name())
+                .map(|majors| majors.contains(&pkg_id.version().major))
+                .unwrap_or(false)
+        {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// Compares two summaries of the same package the way [`Self::sort_summaries`]
+    /// orders them, `Less` meaning that `a` is tried first.
+    ///
+    /// Preferred versions come before the others, then discouraged versions
+    /// come after the others and yanked versions last. Then versions in the
+    /// edition set with [`Self::set_preferred_edition`] come first, and finally
+    /// versions are compared according to `version_ordering`, with build metadata only
+    /// breaking ties as set with [`Self::set_build_metadata_preference`].
+    /// The same version from several sources is tried from the source set
+    /// with [`Self::set_canonical_source`] first.
+    ///
+    /// This is a total order: only summaries with the same [`PackageId`]
+    /// compare equal.
+    pub fn compare(&self, a: &Summary, b: &Summary, version_ordering: VersionOrdering) -> Ordering {
+        let precedence = |v: &Version| (v.major, v.minor, v.patch, v.pre.clone());
+        let no_build = |v: &Version| v.build.is_empty();
+        let (a_id, b_id) = (a.package_id(), b.package_id());
+        let edition_rank = |s: &Summary| match (self.preferred_edition, s.edition()) {
+            (Some(preferred), Some(edition)) if edition == preferred => 0,
+            (Some(_), Some(_)) => 2,
+            _ => 1,
+        };
+        let (a_edition, b_edition) = (edition_rank(a), edition_rank(b));
+        let (a, b) = (a.version(), b.version());
+        self.compare_preference(a_id, b_id, version_ordering)
+            .then_with(|| {
+                if version_ordering == VersionOrdering::OldestPublished {
+                    return Ordering::Equal;
+                }
+                a_edition.cmp(&b_edition)
+            })
+            .then_with(|| {
+                let cmp = precedence(a).cmp(&precedence(b));
+                match version_ordering {
+                    VersionOrdering::MaximumVersionsFirst => cmp.reverse(),
+                    VersionOrdering::MinimumVersionsFirst | VersionOrdering::OldestPublished => cmp,
+                }
+            })
+            .then_with(|| match self.build_metadata {
+                BuildMetadataPreference::Ignore => Ordering::Equal,
+                BuildMetadataPreference::PreferPresent => no_build(a).cmp(&no_build(b)),
+                BuildMetadataPreference::PreferAbsent => no_build(a).cmp(&no_build(b)).reverse(),
+            })
+            .then_with(|| a.build.cmp(&b.build))
+            // Only the same version from different sources is left.
+            .then_with(|| {
+                let not_canonical = |pkg_id: PackageId| {
+                    self.canonical_source
+                        .map_or(false, |canonical| pkg_id.source_id() != canonical)
+                };
+                not_canonical(a_id).cmp(&not_canonical(b_id))
+            })
+            // Ordered by source, so that no two distinct summaries compare equal.
+            .then_with(|| a_id.cmp(&b_id))
+    }
+
+    /// Compares two versions of the same package like [`Self::compare`], but
+    /// leaving out the version numbers themselves. `Equal` means that only
+    /// `version_ordering` tells them apart.
+    pub fn compare_preference(
+        &self,
+        a: PackageId,
+        b: PackageId,
+        version_ordering: VersionOrdering,
+    ) -> Ordering {
+        if version_ordering == VersionOrdering::OldestPublished {
+            let yanked = |pkg_id: PackageId| self.yanked.contains(&pkg_id);
+            return yanked(a).cmp(&yanked(b));
+        }
+        let should_prefer =
+            |pkg_id: PackageId| self.influence(pkg_id) != PreferenceInfluence::VersionOrder;
+        should_prefer(a)
+            .cmp(&should_prefer(b))
+            .reverse()
+            .then_with(|| self.tier(a).cmp(&self.tier(b)))
+    }
+
     /// Sort the given vector of summaries in-place, with all summaries presumed to be for
     /// the same package.  Preferred versions appear first in the result, sorted by
     /// `version_ordering`, followed by non-preferred versions sorted the same way.
+    ///
+    /// Within each of those groups, discouraged versions come after the others
+    /// and yanked versions come last. See [`Self::compare`].
+    ///
+    /// As [`Self::compare`] is a total order, the result does not depend on
+    /// the order the summaries came in, which allows an unstable sort.
     pub fn sort_summaries(
         &self,
         summaries: &mut Vec<Summary>,
         version_ordering: VersionOrdering,
         first_version: bool,
     ) {
-        let should_prefer = |pkg_id: &PackageId| {
-            self.try_to_use.contains(pkg_id)
-                || self
-                    .prefer_patch_deps
-                    .get(&pkg_id.name())
-                    .map(|deps| deps.iter().any(|d| d.matches_id(*pkg_id)))
-                    .unwrap_or(false)
-        };
-        summaries.sort_unstable_by(|a, b| {
-            let prefer_a = should_prefer(&a.package_id());
-            let prefer_b = should_prefer(&b.package_id());
-            let previous_cmp = prefer_a.cmp(&prefer_b).reverse();
-            match previous_cmp {
-                Ordering::Equal => {
-                    let cmp = a.version().cmp(b.version());
-                    match version_ordering {
-                        VersionOrdering::MaximumVersionsFirst => cmp.reverse(),
-                        VersionOrdering::MinimumVersionsFirst => cmp,
-                    }
-                }
-                _ => previous_cmp,
-            }
-        });
+        summaries.sort_unstable_by(|a, b| self.compare(a, b, version_ordering));
         if first_version {
             let _ = summaries.split_off(1);
         }
@@ -80,7 +340,6 @@ impl VersionPreferences {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::core::SourceId;
     use crate::util::PartialVersion;
     use std::collections::BTreeMap;
 
@@ -167,6 +426,134 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_discouraged_and_yanked() {
+        let mut vp = VersionPreferences::default();
+        vp.discourage_package_id(pkgid("foo", "1.2.4"));
+        vp.discourage_package_id(pkgid("foo", "1.0.9"));
+        vp.mark_yanked(pkgid("foo", "1.2.5"));
+
+        let mut summaries = vec![
+            summ("foo", "1.2.5"),
+            summ("foo", "1.2.4"),
+            summ("foo", "1.2.3"),
+            summ("foo", "1.1.0"),
+            summ("foo", "1.0.9"),
+        ];
+
+        vp.sort_summaries(&mut summaries, VersionOrdering::MaximumVersionsFirst, false);
+        assert_eq!(
+            describe(&summaries),
+            "foo/1.2.3, foo/1.1.0, foo/1.2.4, foo/1.0.9, foo/1.2.5".to_string()
+        );
+
+        vp.sort_summaries(&mut summaries, VersionOrdering::MinimumVersionsFirst, false);
+        assert_eq!(
+            describe(&summaries),
+            "foo/1.1.0, foo/1.2.3, foo/1.0.9, foo/1.2.4, foo/1.2.5".to_string()
+        );
+
+        // A locked version is still tried first, even if it is discouraged.
+        vp.prefer_package_id(pkgid("foo", "1.2.4"));
+        vp.sort_summaries(&mut summaries, VersionOrdering::MaximumVersionsFirst, false);
+        assert_eq!(
+            describe(&summaries),
+            "foo/1.2.4, foo/1.2.3, foo/1.1.0, foo/1.0.9, foo/1.2.5".to_string()
+        );
+    }
+
+    #[test]
+    fn test_avoid_major() {
+        let mut vp = VersionPreferences::default();
+        vp.avoid_major(InternedString::new("foo"), 2);
+
+        let mut summaries = vec![
+            summ("foo", "3.0.0"),
+            summ("foo", "2.1.0"),
+            summ("foo", "2.0.0"),
+            summ("foo", "1.0.0"),
+        ];
+        vp.sort_summaries(&mut summaries, VersionOrdering::MaximumVersionsFirst, false);
+        assert_eq!(
+            describe(&summaries),
+            "foo/3.0.0, foo/1.0.0, foo/2.1.0, foo/2.0.0".to_string()
+        );
+
+        // When only the avoided major fits, it is still used.
+        let mut summaries = vec![summ("foo", "2.0.0"), summ("foo", "2.1.0")];
+        vp.sort_summaries(&mut summaries, VersionOrdering::MaximumVersionsFirst, true);
+        assert_eq!(describe(&summaries), "foo/2.1.0".to_string());
+    }
+
+    #[test]
+    fn test_compare() {
+        let mut vp = VersionPreferences::default();
+        vp.prefer_package_id(pkgid("foo", "1.1.0"));
+
+        let max = VersionOrdering::MaximumVersionsFirst;
+        let min = VersionOrdering::MinimumVersionsFirst;
+        let (old, locked, new) = (
+            summ("foo", "1.0.0"),
+            summ("foo", "1.1.0"),
+            summ("foo", "1.2.0"),
+        );
+        assert_eq!(vp.compare(&new, &old, max), Ordering::Less);
+        assert_eq!(vp.compare(&new, &old, min), Ordering::Greater);
+        assert_eq!(vp.compare(&locked, &new, max), Ordering::Less);
+        assert_eq!(vp.compare(&locked, &old, min), Ordering::Less);
+        assert_eq!(vp.compare(&new, &new, max), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_influence() {
+        let mut vp = VersionPreferences::default();
+        vp.prefer_package_id(pkgid("foo", "1.2.3"));
+        vp.prefer_dependency(dep("foo", "=1.1.0"));
+
+        assert_eq!(
+            vp.influence(pkgid("foo", "1.2.3")),
+            PreferenceInfluence::Locked
+        );
+        assert_eq!(
+            vp.influence(pkgid("foo", "1.1.0")),
+            PreferenceInfluence::Pinned
+        );
+        assert_eq!(
+            vp.influence(pkgid("foo", "1.2.4")),
+            PreferenceInfluence::VersionOrder
+        );
+    }
+
+    #[test]
+    fn test_float_direct_dependencies() {
+        let mut vp = VersionPreferences::default();
+        vp.prefer_package_id(pkgid("foo", "1.2.3"));
+        vp.prefer_package_id(pkgid("bar", "1.2.3"));
+        vp.float_direct_dependencies([InternedString::new("foo")]);
+
+        let mut summaries = vec![
+            summ("foo", "1.2.4"),
+            summ("foo", "1.2.3"),
+            summ("foo", "1.1.0"),
+        ];
+        vp.sort_summaries(&mut summaries, VersionOrdering::MaximumVersionsFirst, false);
+        assert_eq!(
+            describe(&summaries),
+            "foo/1.2.4, foo/1.2.3, foo/1.1.0".to_string()
+        );
+
+        let mut summaries = vec![
+            summ("bar", "1.2.4"),
+            summ("bar", "1.2.3"),
+            summ("bar", "1.1.0"),
+        ];
+        vp.sort_summaries(&mut summaries, VersionOrdering::MaximumVersionsFirst, false);
+        assert_eq!(
+            describe(&summaries),
+            "bar/1.2.3, bar/1.2.4, bar/1.1.0".to_string()
+        );
+    }
+
     #[test]
     fn test_prefer_both() {
         let mut vp = VersionPreferences::default();
@@ -192,4 +579,216 @@ mod test {
             "foo/1.1.0, foo/1.2.3, foo/1.0.9, foo/1.2.4".to_string()
         );
     }
+
+    #[test]
+    fn test_oldest_published() {
+        let mut vp = VersionPreferences::default();
+        vp.prefer_package_id(pkgid("foo", "1.2.3"));
+        vp.discourage_package_id(pkgid("foo", "1.0.9"));
+        vp.mark_yanked(pkgid("foo", "1.0.8"));
+
+        let mut summaries = vec![
+            summ("foo", "1.2.4"),
+            summ("foo", "1.2.3"),
+            summ("foo", "1.1.0"),
+            summ("foo", "1.0.9"),
+            summ("foo", "1.0.8"),
+        ];
+
+        vp.sort_summaries(&mut summaries, VersionOrdering::MinimumVersionsFirst, false);
+        assert_eq!(
+            describe(&summaries),
+            "foo/1.2.3, foo/1.1.0, foo/1.2.4, foo/1.0.9, foo/1.0.8".to_string()
+        );
+
+        vp.sort_summaries(&mut summaries, VersionOrdering::OldestPublished, false);
+        assert_eq!(
+            describe(&summaries),
+            "foo/1.0.9, foo/1.1.0, foo/1.2.3, foo/1.2.4, foo/1.0.8".to_string()
+        );
+    }
+
+    #[test]
+    fn test_build_metadata_preference() {
+        let mut vp = VersionPreferences::default();
+        let mut summaries = vec![
+            summ("foo", "1.0.0+patched"),
+            summ("foo", "1.0.0"),
+            summ("foo", "1.0.1"),
+            summ("foo", "0.9.0+patched"),
+            summ("foo", "0.9.0"),
+        ];
+
+        vp.set_build_metadata_preference(BuildMetadataPreference::PreferAbsent);
+        vp.sort_summaries(&mut summaries, VersionOrdering::MaximumVersionsFirst, false);
+        assert_eq!(
+            describe(&summaries),
+            "foo/1.0.1, foo/1.0.0, foo/1.0.0+patched, foo/0.9.0, foo/0.9.0+patched".to_string()
+        );
+
+        vp.set_build_metadata_preference(BuildMetadataPreference::PreferPresent);
+        vp.sort_summaries(&mut summaries, VersionOrdering::MinimumVersionsFirst, false);
+        assert_eq!(
+            describe(&summaries),
+            "foo/0.9.0+patched, foo/0.9.0, foo/1.0.0+patched, foo/1.0.0, foo/1.0.1".to_string()
+        );
+
+        vp.set_build_metadata_preference(BuildMetadataPreference::Ignore);
+        vp.sort_summaries(&mut summaries, VersionOrdering::MaximumVersionsFirst, false);
+        assert_eq!(
+            describe(&summaries),
+            "foo/1.0.1, foo/1.0.0, foo/1.0.0+patched, foo/0.9.0, foo/0.9.0+patched".to_string()
+        );
+    }
+
+    #[test]
+    fn test_sort_is_deterministic() {
+        let mirror = SourceId::from_url("registry+https://example.com/index").unwrap();
+        let mirrored = |version: &str| {
+            Summary::new(
+                PackageId::new("foo", version, mirror).unwrap(),
+                Vec::new(),
+                &BTreeMap::new(),
+                None::<&String>,
+                None::<PartialVersion>,
+            )
+            .unwrap()
+        };
+        let mut vp = VersionPreferences::default();
+        vp.discourage_package_id(pkgid("foo", "1.0.1"));
+        let summaries = vec![
+            summ("foo", "1.0.0"),
+            mirrored("1.0.0"),
+            summ("foo", "1.0.0+a"),
+            summ("foo", "1.0.1"),
+            mirrored("1.0.1"),
+            summ("foo", "1.0.2"),
+        ];
+
+        let mut expected = summaries.clone();
+        vp.sort_summaries(&mut expected, VersionOrdering::MaximumVersionsFirst, false);
+        let expected: Vec<_> = expected.iter().map(|s| s.package_id()).collect();
+        for shift in 1..summaries.len() {
+            let mut shuffled = summaries.clone();
+            shuffled.rotate_left(shift);
+            shuffled.reverse();
+            vp.sort_summaries(&mut shuffled, VersionOrdering::MaximumVersionsFirst, false);
+            let got: Vec<_> = shuffled.iter().map(|s| s.package_id()).collect();
+            assert_eq!(got, expected);
+        }
+    }
+
+    #[test]
+    fn test_canonical_source() {
+        let mirror = SourceId::from_url("registry+https://example.com/index").unwrap();
+        let mirrored = |version: &str| {
+            Summary::new(
+                PackageId::new("foo", version, mirror).unwrap(),
+                Vec::new(),
+                &BTreeMap::new(),
+                None::<&String>,
+                None::<PartialVersion>,
+            )
+            .unwrap()
+        };
+        let describe_sources = |summaries: &Vec<Summary>| {
+            summaries
+                .iter()
+                .map(|s| {
+                    let source = if s.source_id() == mirror {
+                        "mirror"
+                    } else {
+                        "canonical"
+                    };
+                    format!("{}/{}", s.version(), source)
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let mut vp = VersionPreferences::default();
+        let mut summaries = vec![mirrored("1.0.0"), summ("foo", "1.0.0"), mirrored("1.0.1")];
+
+        // `example.com` comes first without a canonical source.
+        vp.sort_summaries(&mut summaries, VersionOrdering::MinimumVersionsFirst, false);
+        assert_eq!(
+            describe_sources(&summaries),
+            "1.0.0/mirror, 1.0.0/canonical, 1.0.1/mirror"
+        );
+
+        vp.set_canonical_source(summ("foo", "1.0.0").source_id());
+        vp.sort_summaries(&mut summaries, VersionOrdering::MinimumVersionsFirst, false);
+        assert_eq!(
+            describe_sources(&summaries),
+            "1.0.0/canonical, 1.0.0/mirror, 1.0.1/mirror"
+        );
+        vp.sort_summaries(&mut summaries, VersionOrdering::MaximumVersionsFirst, false);
+        assert_eq!(
+            describe_sources(&summaries),
+            "1.0.1/mirror, 1.0.0/canonical, 1.0.0/mirror"
+        );
+    }
+
+    #[test]
+    fn test_patch_only_updates() {
+        let mut vp = VersionPreferences::default();
+        vp.patch_only_updates(&[pkgid("foo", "1.2.3")]);
+
+        let mut summaries = vec![
+            summ("foo", "1.1.0"),
+            summ("foo", "1.2.3"),
+            summ("foo", "1.2.9"),
+            summ("foo", "1.3.0"),
+        ];
+        vp.sort_summaries(&mut summaries, VersionOrdering::MaximumVersionsFirst, false);
+        assert_eq!(
+            describe(&summaries),
+            "foo/1.2.9, foo/1.2.3, foo/1.3.0, foo/1.1.0".to_string()
+        );
+        assert_eq!(
+            vp.influence(pkgid("foo", "1.2.9")),
+            PreferenceInfluence::LockedMinor
+        );
+
+        // A requirement like `^1.3` leaves no version of the locked minor.
+        let mut summaries = vec![summ("foo", "1.3.0"), summ("foo", "1.3.1")];
+        vp.sort_summaries(&mut summaries, VersionOrdering::MaximumVersionsFirst, true);
+        assert_eq!(describe(&summaries), "foo/1.3.1".to_string());
+    }
+
+    #[test]
+    fn test_preferred_edition() {
+        let with_edition = |version: &str, edition: Edition| {
+            let mut s = summ("foo", version);
+            s.set_edition(edition);
+            s
+        };
+        let mut vp = VersionPreferences::default();
+        let mut summaries = vec![
+            with_edition("1.0.0", Edition::Edition2018),
+            with_edition("1.1.0", Edition::Edition2021),
+            summ("foo", "1.2.0"),
+            with_edition("1.3.0", Edition::Edition2021),
+        ];
+
+        vp.sort_summaries(&mut summaries, VersionOrdering::MaximumVersionsFirst, false);
+        assert_eq!(
+            describe(&summaries),
+            "foo/1.3.0, foo/1.2.0, foo/1.1.0, foo/1.0.0".to_string()
+        );
+
+        vp.set_preferred_edition(Edition::Edition2018);
+        vp.sort_summaries(&mut summaries, VersionOrdering::MaximumVersionsFirst, false);
+        assert_eq!(
+            describe(&summaries),
+            "foo/1.0.0, foo/1.2.0, foo/1.3.0, foo/1.1.0".to_string()
+        );
+
+        // Preferred versions still come first.
+        vp.prefer_package_id(pkgid("foo", "1.1.0"));
+        vp.sort_summaries(&mut summaries, VersionOrdering::MaximumVersionsFirst, false);
+        assert_eq!(
+            describe(&summaries),
+            "foo/1.1.0, foo/1.0.0, foo/1.2.0, foo/1.3.0".to_string()
+        );
+    }
 }
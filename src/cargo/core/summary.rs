@@ -1,14 +1,17 @@
+use crate::core::resolver::{RequirementError, RequirementsBuilder};
 use crate::core::{Dependency, PackageId, SourceId};
 use crate::util::interning::InternedString;
 use crate::util::CargoResult;
 use crate::util::PartialVersion;
-use anyhow::bail;
+use anyhow::{bail, Context as _};
+use cargo_platform::CfgExpr;
 use semver::Version;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::mem;
 use std::rc::Rc;
+use std::time::SystemTime;
 
 /// Subset of a `Manifest`. Contains only the most important information about
 /// a package.
@@ -24,9 +27,19 @@ struct Inner {
     package_id: PackageId,
     dependencies: Vec<Dependency>,
     features: Rc<FeatureMap>,
+    /// The features that were created for optional dependencies rather than
+    /// declared in the manifest.
+    implicit_features: Rc<BTreeSet<InternedString>>,
     checksum: Option<String>,
     links: Option<InternedString>,
     rust_version: Option<PartialVersion>,
+    published: Option<SystemTime>,
+    license: Option<InternedString>,
+    edition: Option<Edition>,
+    deprecated_features: Rc<HashSet<InternedString>>,
+    exclusive_feature_groups: Rc<Vec<BTreeSet<InternedString>>>,
+    feature_preconditions: Rc<BTreeMap<InternedString, BTreeSet<InternedString>>>,
+    feature_aliases: Rc<BTreeMap<InternedString, InternedString>>,
 }
 
 impl Summary {
@@ -50,14 +63,27 @@ impl Summary {
             }
         }
         let feature_map = build_feature_map(pkg_id, features, &dependencies)?;
+        let implicit_features = feature_map
+            .keys()
+            .filter(|feature| !features.contains_key(*feature))
+            .copied()
+            .collect();
         Ok(Summary {
             inner: Rc::new(Inner {
                 package_id: pkg_id,
                 dependencies,
                 features: Rc::new(feature_map),
+                implicit_features: Rc::new(implicit_features),
                 checksum: None,
                 links: links.map(|l| l.into()),
                 rust_version,
+                published: None,
+                license: None,
+                edition: None,
+                deprecated_features: Rc::default(),
+                exclusive_feature_groups: Rc::default(),
+                feature_preconditions: Rc::default(),
+                feature_aliases: Rc::default(),
             }),
         })
     }
@@ -92,6 +118,182 @@ impl Summary {
         self.inner.rust_version
     }
 
+    /// The features named after an optional dependency that only exist
+    /// because no feature enables the dependency with `dep:`.
+    pub fn implicit_features(&self) -> &BTreeSet<InternedString> {
+        &self.inner.implicit_features
+    }
+
+    /// When this version was published, if the source knows.
+    ///
+    /// No source built into cargo fills this in, it is set with
+    /// [`Summary::set_published`] by whoever builds the summary.
+    pub fn published(&self) -> Option<SystemTime> {
+        self.inner.published
+    }
+
+    /// The edition this version is written in, if the source knows.
+    ///
+    /// This is `package.edition` for packages read from their manifest. A
+    /// registry only knows it when its index has an `edition` field, which
+    /// crates.io's does not.
+    pub fn edition(&self) -> Option<Edition> {
+        self.inner.edition
+    }
+
+    /// The SPDX license expression of this version, if the source knows.
+    ///
+    /// This is `package.license` for packages read from their manifest. A
+    /// registry only knows it when its index has a `license` field, which
+    /// crates.io's does not.
+    pub fn license(&self) -> Option<InternedString> {
+        self.inner.license
+    }
+
+    /// The features the author asked dependents to stop enabling.
+    pub fn deprecated_features(&self) -> &HashSet<InternedString> {
+        &self.inner.deprecated_features
+    }
+
+    /// Groups of features of which at most one may be enabled at a time.
+    pub fn exclusive_feature_groups(&self) -> &[BTreeSet<InternedString>] {
+        &self.inner.exclusive_feature_groups
+    }
+
+    /// The features that must be enabled along with a feature, by the same
+    /// dependent. Unlike the features it enables, these are not turned on for
+    /// the user.
+    pub fn feature_preconditions(&self) -> &BTreeMap<InternedString, BTreeSet<InternedString>> {
+        &self.inner.feature_preconditions
+    }
+
+    /// Feature names that are kept for compatibility and enable an optional
+    /// dependency of another name, as if by a `dep:` value.
+    pub fn feature_aliases(&self) -> &BTreeMap<InternedString, InternedString> {
+        &self.inner.feature_aliases
+    }
+
+    /// The feature table as a graph, mapping each feature to what it enables,
+    /// for tools that draw or walk it.
+    ///
+    /// A value naming a feature alias becomes an edge to the dependency the
+    /// alias enables, and a `cfg(...):` value the edge of the value it wraps,
+    /// keeping its `cfg`.
+    pub fn feature_graph(&self) -> HashMap<InternedString, Vec<FeatureEdge>> {
+        let edge = |fv: &FeatureValue| {
+            let cfg = match fv {
+                FeatureValue::TargetFeature { cfg, .. } => Some(cfg.clone()),
+                _ => None,
+            };
+            match fv.without_cfg() {
+                FeatureValue::Feature(feature) => match self.feature_aliases().get(feature) {
+                    Some(&dep_name) => FeatureEdge::Dep { dep_name, cfg },
+                    None => FeatureEdge::Feature {
+                        feature: *feature,
+                        cfg,
+                    },
+                },
+                FeatureValue::Dep { dep_name } => FeatureEdge::Dep {
+                    dep_name: *dep_name,
+                    cfg,
+                },
+                FeatureValue::DepFeature {
+                    dep_name,
+                    dep_feature,
+                    weak,
+                } => FeatureEdge::DepFeature {
+                    dep_name: *dep_name,
+                    dep_feature: *dep_feature,
+                    weak: *weak,
+                    cfg,
+                },
+                // Nested `cfg(...):` values are rejected when the summary
+                // is built.
+                FeatureValue::TargetFeature { value, .. } => panic!("nested cfg value {}", value),
+            }
+        };
+        self.features()
+            .iter()
+            .map(|(name, values)| (*name, values.iter().map(edge).collect()))
+            .collect()
+    }
+
+    /// Returns the names of the optional dependencies that can be enabled
+    /// through some combination of features.
+    ///
+    /// Weak `dep?/feat` values are skipped, as they never enable a dependency
+    /// on their own. An optional dependency missing from this set can never be
+    /// enabled, which likely points at a mistake in the manifest.
+    pub fn optionally_enabled_deps(&self) -> HashSet<InternedString> {
+        let optional: HashSet<_> = self
+            .dependencies()
+            .iter()
+            .filter(|dep| dep.is_optional())
+            .map(|dep| dep.name_in_toml())
+            .collect();
+        self.features()
+            .values()
+            .flatten()
+            .filter_map(|fv| match fv.without_cfg() {
+                FeatureValue::Dep { dep_name }
+                | FeatureValue::DepFeature {
+                    dep_name,
+                    weak: false,
+                    ..
+                } => Some(*dep_name),
+                _ => None,
+            })
+            .filter(|dep_name| optional.contains(dep_name))
+            .collect()
+    }
+
+    /// Every feature enabled when the package is built with its default
+    /// features only, `default` included. Nothing is enabled without a
+    /// `default` feature.
+    ///
+    /// A feature table that cannot be enabled this way fails with a
+    /// [`RequirementError`].
+    pub fn default_feature_set(&self) -> CargoResult<HashSet<InternedString>> {
+        let default = InternedString::new("default");
+        if !self.features().contains_key(&default) {
+            return Ok(HashSet::new());
+        }
+        let mut reqs = RequirementsBuilder::new(self);
+        reqs.add_feature(default).with_context(|| {
+            format!(
+                "failed to enable the default features of `{}`",
+                self.package_id()
+            )
+        })?;
+        Ok(reqs.features().clone())
+    }
+
+    /// Checks that every `dep:name` and `name/feature` value in the feature
+    /// table names one of the dependencies, reporting each missing one once,
+    /// in the order of the table.
+    ///
+    /// This catches typos like `dep:serede` without resolving anything.
+    pub fn validate_feature_references(&self) -> Vec<RequirementError> {
+        let deps: HashSet<_> = self
+            .dependencies()
+            .iter()
+            .map(|dep| dep.name_in_toml())
+            .collect();
+        let mut reported = HashSet::new();
+        self.features()
+            .values()
+            .flatten()
+            .filter_map(|fv| match fv.without_cfg() {
+                FeatureValue::Dep { dep_name } | FeatureValue::DepFeature { dep_name, .. } => {
+                    Some(*dep_name)
+                }
+                _ => None,
+            })
+            .filter(|dep_name| !deps.contains(dep_name) && reported.insert(*dep_name))
+            .map(RequirementError::MissingDependency)
+            .collect()
+    }
+
     pub fn override_id(mut self, id: PackageId) -> Summary {
         Rc::make_mut(&mut self.inner).package_id = id;
         self
@@ -101,6 +303,45 @@ impl Summary {
         Rc::make_mut(&mut self.inner).checksum = Some(cksum);
     }
 
+    pub fn set_published(&mut self, published: SystemTime) {
+        Rc::make_mut(&mut self.inner).published = Some(published);
+    }
+
+    pub fn set_edition(&mut self, edition: Edition) {
+        Rc::make_mut(&mut self.inner).edition = Some(edition);
+    }
+
+    pub fn set_license(&mut self, license: InternedString) {
+        Rc::make_mut(&mut self.inner).license = Some(license);
+    }
+
+    pub fn set_exclusive_feature_groups(
+        &mut self,
+        groups: impl IntoIterator<Item = BTreeSet<InternedString>>,
+    ) {
+        Rc::make_mut(&mut self.inner).exclusive_feature_groups =
+            Rc::new(groups.into_iter().collect());
+    }
+
+    pub fn set_feature_preconditions(
+        &mut self,
+        preconditions: impl IntoIterator<Item = (InternedString, BTreeSet<InternedString>)>,
+    ) {
+        Rc::make_mut(&mut self.inner).feature_preconditions =
+            Rc::new(preconditions.into_iter().collect());
+    }
+
+    pub fn set_feature_aliases(
+        &mut self,
+        aliases: impl IntoIterator<Item = (InternedString, InternedString)>,
+    ) {
+        Rc::make_mut(&mut self.inner).feature_aliases = Rc::new(aliases.into_iter().collect());
+    }
+
+    pub fn set_deprecated_features(&mut self, features: impl IntoIterator<Item = InternedString>) {
+        Rc::make_mut(&mut self.inner).deprecated_features = Rc::new(features.into_iter().collect());
+    }
+
     pub fn map_dependencies<F>(mut self, f: F) -> Summary
     where
         F: FnMut(Dependency) -> Dependency,
@@ -169,7 +410,7 @@ fn build_feature_map(
     let explicitly_listed: HashSet<_> = map
         .values()
         .flatten()
-        .filter_map(|fv| match fv {
+        .filter_map(|fv| match fv.without_cfg() {
             Dep { dep_name } => Some(*dep_name),
             _ => None,
         })
@@ -205,12 +446,29 @@ fn build_feature_map(
         }
         validate_feature_name(pkg_id, feature)?;
         for fv in fvs {
+            // A `cfg(...):` value is checked as the value it wraps, except
+            // that a feature it enables must be declared in the table, since
+            // enabling an optional dependency through its implicit feature
+            // could not be limited to matching targets.
+            if let TargetFeature { value, .. } = fv {
+                if let Feature(target_feature) = **value {
+                    if !features.contains_key(&target_feature) {
+                        bail!(
+                            "feature `{}` includes `{}`, but `{}` is not another feature",
+                            feature,
+                            fv,
+                            target_feature
+                        );
+                    }
+                }
+            }
             // Find data for the referenced dependency...
             let dep_data = {
-                match fv {
+                match fv.without_cfg() {
                     Feature(dep_name) | Dep { dep_name, .. } | DepFeature { dep_name, .. } => {
                         dep_map.get(dep_name)
                     }
+                    TargetFeature { .. } => None,
                 }
             };
             let is_optional_dep = dep_data
@@ -218,7 +476,7 @@ fn build_feature_map(
                 .flat_map(|d| d.iter())
                 .any(|d| d.is_optional());
             let is_any_dep = dep_data.is_some();
-            match fv {
+            match fv.without_cfg() {
                 Feature(f) => {
                     if !features.contains_key(f) {
                         if !is_any_dep {
@@ -329,6 +587,13 @@ fn build_feature_map(
                             feature, fv, dep_name);
                     }
                 }
+                TargetFeature { .. } => {
+                    bail!(
+                        "feature `{}` includes `{}`, but `cfg(...):` values cannot be nested",
+                        feature,
+                        fv
+                    );
+                }
             }
         }
     }
@@ -337,7 +602,7 @@ fn build_feature_map(
     let used: HashSet<_> = map
         .values()
         .flatten()
-        .filter_map(|fv| match fv {
+        .filter_map(|fv| match fv.without_cfg() {
             Dep { dep_name } | DepFeature { dep_name, .. } => Some(dep_name),
             _ => None,
         })
@@ -373,10 +638,32 @@ pub enum FeatureValue {
         /// activated through some other means.
         weak: bool,
     },
+    /// Any of the values above, applied only when resolving for a target
+    /// matching `cfg`, with `cfg(target_pointer_width = "32"):feat_name` or
+    /// `cfg(windows):dep_name/feat_name` syntax.
+    ///
+    /// When the target is not known, a dependency enabled this way is
+    /// resolved with its platform restricted to `cfg`, the same as if it had
+    /// been declared under `[target.'cfg(...)'.dependencies]`.
+    TargetFeature {
+        cfg: CfgExpr,
+        value: Box<FeatureValue>,
+    },
 }
 
 impl FeatureValue {
     pub fn new(feature: InternedString) -> FeatureValue {
+        if let Some((cfg, value)) = feature
+            .strip_prefix("cfg(")
+            .and_then(|rest| rest.rsplit_once("):"))
+        {
+            if let Ok(cfg) = cfg.parse() {
+                return FeatureValue::TargetFeature {
+                    cfg,
+                    value: Box::new(FeatureValue::new(InternedString::new(value))),
+                };
+            }
+        }
         match feature.find('/') {
             Some(pos) => {
                 let (dep, dep_feat) = feature.split_at(pos);
@@ -408,6 +695,14 @@ impl FeatureValue {
     pub fn has_dep_prefix(&self) -> bool {
         matches!(self, FeatureValue::Dep { .. })
     }
+
+    /// The value a `cfg(...):` value wraps, or the value itself.
+    pub fn without_cfg(&self) -> &FeatureValue {
+        match self {
+            FeatureValue::TargetFeature { value, .. } => value,
+            fv => fv,
+        }
+    }
 }
 
 impl fmt::Display for FeatureValue {
@@ -424,10 +719,36 @@ impl fmt::Display for FeatureValue {
                 let weak = if *weak { "?" } else { "" };
                 write!(f, "{}{}/{}", dep_name, weak, dep_feature)
             }
+            TargetFeature { cfg, value } => write!(f, "cfg({}):{}", cfg, value),
         }
     }
 }
 
+/// What a feature enables, an edge of [`Summary::feature_graph`].
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum FeatureEdge {
+    /// Another feature of the same package, only when resolving for a target
+    /// matching `cfg` if there is one.
+    Feature {
+        feature: InternedString,
+        cfg: Option<CfgExpr>,
+    },
+    /// An optional dependency, through a `dep:` value or a feature alias,
+    /// only on targets matching `cfg` if there is one.
+    Dep {
+        dep_name: InternedString,
+        cfg: Option<CfgExpr>,
+    },
+    /// A feature of a dependency, which also enables the dependency unless
+    /// `weak`, only on targets matching `cfg` if there is one.
+    DepFeature {
+        dep_name: InternedString,
+        dep_feature: InternedString,
+        weak: bool,
+        cfg: Option<CfgExpr>,
+    },
+}
+
 pub type FeatureMap = BTreeMap<InternedString, Vec<FeatureValue>>;
 
 fn validate_feature_name(pkg_id: PackageId, name: &str) -> CargoResult<()> {
@@ -489,4 +810,192 @@ mod tests {
         assert!(validate_feature_name(pkg_id, "ⒶⒷⒸ").is_err());
         assert!(validate_feature_name(pkg_id, "a¼").is_err());
     }
+
+    #[test]
+    fn optionally_enabled_deps() {
+        let loc = CRATES_IO_INDEX.into_url().unwrap();
+        let source_id = SourceId::for_registry(&loc).unwrap();
+        let pkg_id = PackageId::new("foo", "1.0.0", source_id).unwrap();
+        let optional = |name: &str| {
+            let mut dep = Dependency::parse(name, Some("1.0"), source_id).unwrap();
+            dep.set_optional(true);
+            dep
+        };
+        let deps = vec![
+            Dependency::parse("required", Some("1.0"), source_id).unwrap(),
+            optional("implicit"),
+            optional("explicit"),
+        ];
+        let features = BTreeMap::from([
+            ("a".into(), vec!["dep:explicit".into()]),
+            (
+                "b".into(),
+                vec!["implicit?/std".into(), "required/std".into()],
+            ),
+        ]);
+        let summary = Summary::new(pkg_id, deps, &features, None::<&String>, None).unwrap();
+        assert_eq!(
+            summary.optionally_enabled_deps(),
+            HashSet::from(["implicit".into(), "explicit".into()])
+        );
+    }
+
+    #[test]
+    fn validate_feature_references() {
+        let loc = CRATES_IO_INDEX.into_url().unwrap();
+        let source_id = SourceId::for_registry(&loc).unwrap();
+        let pkg_id = PackageId::new("foo", "1.0.0", source_id).unwrap();
+        let mut serde = Dependency::parse("serde", Some("1.0"), source_id).unwrap();
+        serde.set_optional(true);
+        let features = BTreeMap::from([
+            ("a".into(), vec!["dep:serde".into()]),
+            ("b".into(), vec!["serde/std".into()]),
+        ]);
+        let summary = Summary::new(pkg_id, vec![serde], &features, None::<&String>, None).unwrap();
+        assert!(summary.validate_feature_references().is_empty());
+
+        // Only possible once the summary is built, as `Summary::new` checks this too.
+        let summary = summary.map_dependencies(|mut dep| {
+            dep.set_explicit_name_in_toml("serde_json");
+            dep
+        });
+        assert_eq!(
+            summary.validate_feature_references(),
+            [RequirementError::MissingDependency("serde".into())]
+        );
+    }
+
+    #[test]
+    fn default_feature_set() {
+        let loc = CRATES_IO_INDEX.into_url().unwrap();
+        let source_id = SourceId::for_registry(&loc).unwrap();
+        let pkg_id = PackageId::new("foo", "1.0.0", source_id).unwrap();
+        let features = BTreeMap::from([
+            ("default".into(), vec!["std".into()]),
+            ("std".into(), vec!["alloc".into()]),
+            ("alloc".into(), vec![]),
+            ("extra".into(), vec![]),
+        ]);
+        let summary = Summary::new(pkg_id, vec![], &features, None::<&String>, None).unwrap();
+        assert_eq!(
+            summary.default_feature_set().unwrap(),
+            HashSet::from(["default".into(), "std".into(), "alloc".into()])
+        );
+
+        let features = BTreeMap::from([("default".into(), vec!["default".into()])]);
+        let summary = Summary::new(pkg_id, vec![], &features, None::<&String>, None).unwrap();
+        let err = summary.default_feature_set().unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RequirementError>(),
+            Some(&RequirementError::Cycle("default".into()))
+        );
+    }
+
+    #[test]
+    fn feature_graph() {
+        let loc = CRATES_IO_INDEX.into_url().unwrap();
+        let source_id = SourceId::for_registry(&loc).unwrap();
+        let pkg_id = PackageId::new("foo", "1.0.0", source_id).unwrap();
+        let mut serde = Dependency::parse("serde", Some("1.0"), source_id).unwrap();
+        serde.set_optional(true);
+        let features = BTreeMap::from([
+            (
+                "std".into(),
+                vec![
+                    "alloc".into(),
+                    "cfg(unix):extra".into(),
+                    "serde?/std".into(),
+                ],
+            ),
+            ("alloc".into(), vec![]),
+            ("extra".into(), vec![]),
+            (
+                "json".into(),
+                vec!["dep:serde".into(), "cfg(windows):serde/derive".into()],
+            ),
+        ]);
+        let summary = Summary::new(pkg_id, vec![serde], &features, None::<&String>, None).unwrap();
+        let graph = summary.feature_graph();
+        assert_eq!(graph.len(), 4);
+        assert!(graph["alloc"].is_empty());
+        assert_eq!(
+            graph["std"],
+            [
+                FeatureEdge::Feature {
+                    feature: "alloc".into(),
+                    cfg: None
+                },
+                FeatureEdge::Feature {
+                    feature: "extra".into(),
+                    cfg: Some("unix".parse().unwrap())
+                },
+                FeatureEdge::DepFeature {
+                    dep_name: "serde".into(),
+                    dep_feature: "std".into(),
+                    weak: true,
+                    cfg: None
+                },
+            ]
+        );
+        assert_eq!(
+            graph["json"],
+            [
+                FeatureEdge::Dep {
+                    dep_name: "serde".into(),
+                    cfg: None
+                },
+                FeatureEdge::DepFeature {
+                    dep_name: "serde".into(),
+                    dep_feature: "derive".into(),
+                    weak: false,
+                    cfg: Some("windows".parse().unwrap())
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn target_feature_values() {
+        let loc = CRATES_IO_INDEX.into_url().unwrap();
+        let source_id = SourceId::for_registry(&loc).unwrap();
+        let pkg_id = PackageId::new("foo", "1.0.0", source_id).unwrap();
+        let mut serde = Dependency::parse("serde", Some("1.0"), source_id).unwrap();
+        serde.set_optional(true);
+
+        let fv = FeatureValue::new("cfg(windows):dep:serde".into());
+        assert_eq!(
+            fv,
+            FeatureValue::TargetFeature {
+                cfg: "windows".parse().unwrap(),
+                value: Box::new(FeatureValue::Dep {
+                    dep_name: "serde".into()
+                }),
+            }
+        );
+        assert_eq!(fv.to_string(), "cfg(windows):dep:serde");
+
+        // The only mention of `serde` is behind a `cfg`, which still counts
+        // as listing it.
+        let features = BTreeMap::from([("a".into(), vec!["cfg(windows):dep:serde".into()])]);
+        let summary = Summary::new(
+            pkg_id,
+            vec![serde.clone()],
+            &features,
+            None::<&String>,
+            None,
+        )
+        .unwrap();
+        assert!(!summary.features().contains_key("serde"));
+        assert_eq!(
+            summary.optionally_enabled_deps(),
+            HashSet::from(["serde".into()])
+        );
+
+        let features = BTreeMap::from([("a".into(), vec!["cfg(windows):serde".into()])]);
+        let err = Summary::new(pkg_id, vec![serde], &features, None::<&String>, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "feature `a` includes `cfg(windows):serde`, but `serde` is not another feature"
+        );
+    }
 }
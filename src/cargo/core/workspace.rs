@@ -1204,6 +1204,9 @@ impl<'cfg> Workspace<'cfg> {
                 }
                 // This should be enforced by CliFeatures.
                 FeatureValue::Dep { .. } => panic!("unexpected dep: syntax {}", feature),
+                FeatureValue::TargetFeature { .. } => {
+                    panic!("unexpected cfg(...): syntax {}", feature)
+                }
                 FeatureValue::DepFeature {
                     dep_name,
                     dep_feature,
@@ -1321,6 +1324,9 @@ impl<'cfg> Workspace<'cfg> {
                         .collect::<Vec<_>>()
                 }
                 FeatureValue::Dep { .. } => panic!("unexpected dep: syntax {}", feature),
+                FeatureValue::TargetFeature { .. } => {
+                    panic!("unexpected cfg(...): syntax {}", feature)
+                }
                 FeatureValue::DepFeature {
                     dep_name,
                     dep_feature,
@@ -1480,6 +1486,9 @@ impl<'cfg> Workspace<'cfg> {
                 }
                 // This should be enforced by CliFeatures.
                 FeatureValue::Dep { .. } => panic!("unexpected dep: syntax {}", feature),
+                FeatureValue::TargetFeature { .. } => {
+                    panic!("unexpected cfg(...): syntax {}", feature)
+                }
                 FeatureValue::DepFeature {
                     dep_name,
                     dep_feature,
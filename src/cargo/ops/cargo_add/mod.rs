@@ -794,9 +794,11 @@ impl DependencyUI {
                 (
                     k.as_str().to_owned(),
                     v.iter()
-                        .filter_map(|v| match v {
+                        .filter_map(|v| match v.without_cfg() {
                             FeatureValue::Feature(f) => Some(f.as_str().to_owned()),
-                            FeatureValue::Dep { .. } | FeatureValue::DepFeature { .. } => None,
+                            FeatureValue::Dep { .. }
+                            | FeatureValue::DepFeature { .. }
+                            | FeatureValue::TargetFeature { .. } => None,
                         })
                         .collect::<Vec<_>>(),
                 )
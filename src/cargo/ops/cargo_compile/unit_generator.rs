@@ -583,6 +583,14 @@ Rustdoc did not scrape the following examples because they require dev-dependenc
                         target_name
                     );
                 }
+                FeatureValue::TargetFeature { .. } => {
+                    anyhow::bail!(
+                        "invalid feature `{}` in required-features of target `{}`: \
+                  `cfg(...):` prefixed feature values are not allowed in required-features",
+                        fv,
+                        target_name
+                    );
+                }
                 FeatureValue::DepFeature { weak: true, .. } => {
                     anyhow::bail!(
                         "invalid feature `{}` in required-features of target `{}`: \
@@ -20,6 +20,7 @@ use crate::core::dependency::DepKind;
 use crate::core::manifest::ManifestMetadata;
 use crate::core::resolver::CliFeatures;
 use crate::core::Dependency;
+use crate::core::FeatureValue;
 use crate::core::Package;
 use crate::core::QueryKind;
 use crate::core::SourceId;
@@ -111,6 +112,21 @@ pub fn publish(ws: &Workspace<'_>, opts: &PublishOpts<'_>) -> CargoResult<()> {
             );
         }
     }
+    // `cfg(...):` feature values are unstable, and registries do not know
+    // about them yet.
+    let target_features = pkg
+        .summary()
+        .features()
+        .values()
+        .flatten()
+        .any(|fv| matches!(fv, FeatureValue::TargetFeature { .. }));
+    if target_features {
+        bail!(
+            "`{}` cannot be published.\n\
+             `cfg(...):` feature values are unstable and cannot be published yet.",
+            pkg.name(),
+        );
+    }
     // This is only used to confirm that we can create a token before we build the package.
     // This causes the credential provider to be called an extra time, but keeps the same order of errors.
     let ver = pkg.version().to_string();
@@ -476,10 +476,7 @@ pub fn resolve_with_previous<'cfg>(
             let summary = registry.lock(member.summary().clone());
             (
                 summary,
-                ResolveOpts {
-                    dev_deps,
-                    features: RequestedFeatures::CliFeatures(features),
-                },
+                ResolveOpts::new(dev_deps, RequestedFeatures::CliFeatures(features)),
             )
         })
         .collect();
@@ -514,6 +511,7 @@ pub fn resolve_with_previous<'cfg>(
             .require(Feature::public_dependency())
             .is_ok(),
         max_rust_version,
+        None,
     )?;
     let patches: Vec<_> = registry
         .patches()
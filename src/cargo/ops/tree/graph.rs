@@ -518,6 +518,7 @@ fn add_cli_features(
             }
             // This is enforced by CliFeatures.
             FeatureValue::Dep { .. } => panic!("unexpected cli dep feature {}", fv),
+            FeatureValue::TargetFeature { .. } => panic!("unexpected cli target feature {}", fv),
             FeatureValue::DepFeature {
                 dep_name,
                 dep_feature,
@@ -603,7 +604,9 @@ fn add_feature_rec(
         None => return,
     };
     for fv in fvs {
-        match fv {
+        // A value that depends on the target is shown whether or not the
+        // target matches, like dependencies under `[target]` are.
+        match fv.without_cfg() {
             FeatureValue::Feature(dep_name) => {
                 let (missing, feat_index) = add_feature(
                     graph,
@@ -629,6 +632,8 @@ fn add_feature_rec(
             // (like feature `"somefeat" = ["dep:somedep"]`), so maybe in the
             // future consider explicitly showing this?
             FeatureValue::Dep { .. } => {}
+            // `cfg(...):` values cannot be nested.
+            FeatureValue::TargetFeature { .. } => {}
             FeatureValue::DepFeature {
                 dep_name,
                 dep_feature,
@@ -308,6 +308,17 @@ pub struct IndexPackage<'a> {
     /// Added in 2023 (see <https://github.com/rust-lang/crates.io/pull/6267>),
     /// can be `None` if published before then or if not set in the manifest.
     rust_version: Option<PartialVersion>,
+    /// The SPDX license expression of the package.
+    ///
+    /// Corresponds to `package.license`. The crates.io index does not have
+    /// it, so this is only `Some` for registries that choose to add it.
+    license: Option<InternedString>,
+    /// The edition the package is written in, like `"2021"`.
+    ///
+    /// Corresponds to `package.edition`. The crates.io index does not have
+    /// it, so this is only `Some` for registries that choose to add it. An
+    /// edition this version of cargo does not know is ignored.
+    edition: Option<InternedString>,
     /// The schema version for this entry.
     ///
     /// If this is None, it defaults to version `1`. Entries with unknown
@@ -922,6 +933,8 @@ impl IndexSummary {
             yanked,
             links,
             rust_version,
+            license,
+            edition,
             v,
         } = serde_json::from_slice(line)?;
         let v = v.unwrap_or(1);
@@ -938,6 +951,12 @@ impl IndexSummary {
         }
         let mut summary = Summary::new(pkgid, deps, &features, links, rust_version)?;
         summary.set_checksum(cksum);
+        if let Some(license) = license {
+            summary.set_license(license);
+        }
+        if let Some(edition) = edition.and_then(|e| e.parse().ok()) {
+            summary.set_edition(edition);
+        }
         Ok(IndexSummary {
             summary,
             yanked: yanked.unwrap_or(false),
@@ -1076,3 +1095,44 @@ fn escaped_char_in_index_json_blob() {
     )
     .unwrap();
 }
+
+#[test]
+fn license_in_index_json_blob() {
+    let source_id = SourceId::for_registry(&"https://example.com".into_url().unwrap()).unwrap();
+    let summary = IndexSummary::parse(
+        br#"{"name":"a","vers":"0.0.1","deps":[],"cksum":"bae3","features":{},"license":"MIT"}"#,
+        source_id,
+    )
+    .unwrap()
+    .summary;
+    assert_eq!(summary.license().as_deref(), Some("MIT"));
+
+    let summary = IndexSummary::parse(
+        br#"{"name":"a","vers":"0.0.1","deps":[],"cksum":"bae3","features":{}}"#,
+        source_id,
+    )
+    .unwrap()
+    .summary;
+    assert_eq!(summary.license(), None);
+}
+
+#[test]
+fn edition_in_index_json_blob() {
+    let source_id = SourceId::for_registry(&"https://example.com".into_url().unwrap()).unwrap();
+    let summary = IndexSummary::parse(
+        br#"{"name":"a","vers":"0.0.1","deps":[],"cksum":"bae3","features":{},"edition":"2018"}"#,
+        source_id,
+    )
+    .unwrap()
+    .summary;
+    assert_eq!(summary.edition(), Some(crate::core::Edition::Edition2018));
+
+    // Not an error, the entry would be left out of the index cache.
+    let summary = IndexSummary::parse(
+        br#"{"name":"a","vers":"0.0.1","deps":[],"cksum":"bae3","features":{},"edition":"3000"}"#,
+        source_id,
+    )
+    .unwrap()
+    .summary;
+    assert_eq!(summary.edition(), None);
+}
@@ -1,4 +1,4 @@
-use semver::{Comparator, Op, Version, VersionReq};
+use semver::{BuildMetadata, Comparator, Op, Version, VersionReq};
 use std::fmt::{self, Display};
 
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
@@ -90,6 +90,82 @@ impl OptVersionReq {
             }
         }
     }
+
+    /// Like [`Self::matches`], but a pre-release version is also accepted when
+    /// the requirement contains it by version precedence alone.
+    ///
+    /// Semver only lets a pre-release match a comparator that names a
+    /// pre-release of the same `major.minor.patch`, so `^1.0` does not match
+    /// `1.1.0-alpha` although `1.0.0 < 1.1.0-alpha < 2.0.0`. Here it does, as
+    /// long as `1.1.0` itself would match. The pre-releases of the version a
+    /// requirement starts at are still not accepted, as they come before it:
+    /// `^1.2` matches neither `1.2.0-alpha` nor `2.0.0-alpha`, but does match
+    /// `1.2.1-alpha`.
+    pub fn matches_prerelease(&self, version: &Version) -> bool {
+        if self.matches(version) {
+            return true;
+        }
+        let OptVersionReq::Req(req) = self else {
+            return false;
+        };
+        if !version.is_prerelease() {
+            return false;
+        }
+        let release = Version::new(version.major, version.minor, version.patch);
+        req.matches(&release)
+            && req
+                .comparators
+                .iter()
+                .all(|cmp| lower_bound(cmp) != Some((version.major, version.minor, version.patch)))
+    }
+
+    /// Whether `version` is older than the versions this requirement matches,
+    /// as opposed to newer, assuming it does not match.
+    pub fn is_below(&self, version: &Version) -> bool {
+        match self {
+            OptVersionReq::Any => false,
+            OptVersionReq::Req(req) => req
+                .comparators
+                .iter()
+                .any(|cmp| is_below_comparator(cmp, version)),
+            OptVersionReq::Locked(v, _) => {
+                (&version.major, &version.minor, &version.patch, &version.pre)
+                    < (&v.major, &v.minor, &v.patch, &v.pre)
+            }
+        }
+    }
+}
+
+/// The `major.minor.patch` of the lowest release `cmp` matches, if it has a
+/// lower bound that a release can be equal to.
+fn lower_bound(cmp: &Comparator) -> Option<(u64, u64, u64)> {
+    match (cmp.op, cmp.minor, cmp.patch) {
+        (Op::Less | Op::LessEq, _, _) => None,
+        // `>1.2.3` has no lowest release, only versions above one.
+        (Op::Greater, Some(_), Some(_)) => None,
+        (Op::Greater, Some(minor), None) => Some((cmp.major, minor + 1, 0)),
+        (Op::Greater, None, _) => Some((cmp.major + 1, 0, 0)),
+        (_, minor, patch) => Some((cmp.major, minor.unwrap_or(0), patch.unwrap_or(0))),
+    }
+}
+
+/// Whether `version` fails `cmp` for being too old.
+fn is_below_comparator(cmp: &Comparator, version: &Version) -> bool {
+    match cmp.op {
+        Op::Greater | Op::GreaterEq => !cmp.matches(version),
+        Op::Less | Op::LessEq => false,
+        // The others match from their partial version on.
+        _ => {
+            let lowest = Version {
+                major: cmp.major,
+                minor: cmp.minor.unwrap_or(0),
+                patch: cmp.patch.unwrap_or(0),
+                pre: cmp.pre.clone(),
+                build: BuildMetadata::EMPTY,
+            };
+            *version < lowest
+        }
+    }
 }
 
 impl Display for OptVersionReq {
@@ -262,4 +338,47 @@ mod tests {
         );
         test_versions("0.1.0+meta", &["0.1.0", "0.1.0+meta", "0.1.0+any"]);
     }
+
+    #[test]
+    fn is_below() {
+        let below = |req: &str, version: &str| {
+            let req = OptVersionReq::Req(VersionReq::parse(req).unwrap());
+            req.is_below(&Version::parse(version).unwrap())
+        };
+        assert!(below("^2.0", "1.9.3"));
+        assert!(!below("^2.0", "3.0.0"));
+        assert!(below(">=1.0, <1.5", "0.9.0"));
+        assert!(!below(">=1.0, <1.5", "1.5.0"));
+        assert!(below(">1.2", "1.2.5"));
+        assert!(below("=0.0.2", "0.0.1"));
+
+        let mut locked = OptVersionReq::Req(VersionReq::STAR);
+        locked.lock_to(&Version::parse("0.1.0").unwrap());
+        assert!(locked.is_below(&Version::parse("0.0.1").unwrap()));
+        assert!(!locked.is_below(&Version::parse("0.2.0").unwrap()));
+    }
+
+    #[test]
+    fn matches_prerelease() {
+        let matches = |req: &str, version: &str| {
+            let req = OptVersionReq::Req(VersionReq::parse(req).unwrap());
+            req.matches_prerelease(&Version::parse(version).unwrap())
+        };
+        assert!(matches("^1.0", "1.1.0-alpha"));
+        assert!(matches("^1.2", "1.2.1-alpha"));
+        assert!(!matches("^1.2", "1.2.0-alpha"));
+        assert!(!matches("^1.2", "2.0.0-alpha"));
+        assert!(matches(">=1.2.0-beta", "1.2.0-beta.2"));
+        assert!(!matches(">=1.2.0-beta", "1.2.0-alpha"));
+        assert!(matches(">1.2.3", "1.2.4-rc.1"));
+        assert!(!matches(">1.2", "1.3.0-rc.1"));
+        assert!(matches("<=1.5.0", "1.5.0-rc.1"));
+        assert!(!matches("<1.5.0", "1.5.0-rc.1"));
+        assert!(matches("*", "0.1.0-alpha"));
+        assert!(matches("1.0.0", "1.0.0"));
+
+        let mut locked = OptVersionReq::Req(VersionReq::STAR);
+        locked.lock_to(&Version::parse("1.0.0").unwrap());
+        assert!(!locked.matches_prerelease(&Version::parse("1.0.1-alpha").unwrap()));
+    }
 }
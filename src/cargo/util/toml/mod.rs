@@ -24,7 +24,7 @@ use crate::core::dependency::{Artifact, ArtifactTarget, DepKind};
 use crate::core::manifest::{ManifestMetadata, TargetSourcePath, Warnings};
 use crate::core::resolver::ResolveBehavior;
 use crate::core::{find_workspace_root, resolve_relative_path, CliUnstable};
-use crate::core::{Dependency, Manifest, PackageId, Summary, Target};
+use crate::core::{Dependency, FeatureValue, Manifest, PackageId, Summary, Target};
 use crate::core::{Edition, EitherManifest, Feature, Features, VirtualManifest, Workspace};
 use crate::core::{GitReference, PackageIdSpec, SourceId, WorkspaceConfig, WorkspaceRootConfig};
 use crate::sources::{CRATES_IO_INDEX, CRATES_IO_REGISTRY};
@@ -2374,14 +2374,24 @@ impl TomlManifest {
             .transpose()?
             .unwrap_or_default();
         let empty_features = BTreeMap::new();
+        let toml_features = me.features.as_ref().unwrap_or(&empty_features);
+        if toml_features.values().flatten().any(|value| {
+            matches!(
+                FeatureValue::new(*value),
+                FeatureValue::TargetFeature { .. }
+            )
+        }) {
+            features.require(Feature::cfg_features())?;
+        }
 
-        let summary = Summary::new(
+        let mut summary = Summary::new(
             pkgid,
             deps,
-            me.features.as_ref().unwrap_or(&empty_features),
+            toml_features,
             package.links.as_deref(),
             rust_version,
         )?;
+        summary.set_edition(edition);
 
         let metadata = ManifestMetadata {
             description: package
@@ -2453,6 +2463,9 @@ impl TomlManifest {
                 .map(|mw| mw.resolve("rust-version", || inherit()?.rust_version()))
                 .transpose()?,
         };
+        if let Some(license) = &metadata.license {
+            summary.set_license(InternedString::new(license));
+        }
         package.description = metadata
             .description
             .clone()
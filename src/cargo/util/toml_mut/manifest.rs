@@ -483,9 +483,9 @@ fn fix_feature_activations(
                 DependencyStatus::Required => match (parsed_value, explicit_dep_activation) {
                     (FeatureValue::Feature(dep_name), false)
                     | (FeatureValue::Dep { dep_name }, _) => dep_name == dep_key,
-                    (FeatureValue::Feature(_), true) | (FeatureValue::DepFeature { .. }, _) => {
-                        false
-                    }
+                    (FeatureValue::Feature(_), true)
+                    | (FeatureValue::DepFeature { .. }, _)
+                    | (FeatureValue::TargetFeature { .. }, _) => false,
                 },
             }
             .then(|| idx)
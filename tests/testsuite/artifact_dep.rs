@@ -1549,6 +1549,7 @@ fn index_version_filtering() {
 [UPDATING] [..]
 error: failed to select a version for the requirement `bar = \"^1.0\"` (locked to 1.0.1)
 candidate versions found which didn't match: 1.0.0
+closest versions: 1.0.0 (too low)
 location searched: [..]
 required by package `foo v0.1.0 [..]`
 perhaps a crate was updated and forgotten to be re-vendored?",
@@ -768,6 +768,7 @@ To reuse those artifacts with a future compilation, set the environment variable
 Caused by:
   failed to select a version for the requirement `foo = \"^2\"`
   candidate versions found which didn't match: 0.0.1
+  closest versions: 0.0.1 (too low)
   location searched: directory source `[..] (which is replacing registry `[..]`)
   required by package `bar v0.1.0`
   perhaps a crate was updated and forgotten to be re-vendored?
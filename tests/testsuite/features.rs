@@ -1,7 +1,7 @@
 //! Tests for `[features]` table.
 
 use cargo_test_support::paths::CargoPathExt;
-use cargo_test_support::registry::{Dependency, Package};
+use cargo_test_support::registry::{Dependency, Package, RegistryBuilder};
 use cargo_test_support::{basic_manifest, project};
 
 #[cargo_test]
@@ -1225,6 +1225,22 @@ fn dep_feature_in_cmd_line() {
         .with_status(101)
         .with_stderr("[ERROR] multiple slashes in feature `derived/bar/some-feat` is not allowed")
         .run();
+
+    // Malformed dependency features are rejected before resolving
+    p.cargo("check --features dep:/some-feat")
+        .with_status(101)
+        .with_stderr(
+            "[ERROR] feature `dep:/some-feat` is not allowed to use explicit `dep:` syntax",
+        )
+        .run();
+    p.cargo("check --features derived/")
+        .with_status(101)
+        .with_stderr("[ERROR] feature `derived/` is missing a feature name after `/`")
+        .run();
+    p.cargo("check --features /derived-feat")
+        .with_status(101)
+        .with_stderr("[ERROR] feature `/derived-feat` is missing a dependency name before `/`")
+        .run();
 }
 
 #[cargo_test]
@@ -2067,3 +2083,90 @@ fn default_features_conflicting_warning() {
         )
         .run();
 }
+
+#[cargo_test]
+fn cfg_feature_values_gated() {
+    let p = project()
+        .file(
+            "Cargo.toml",
+            r#"
+                [package]
+                name = "foo"
+                version = "0.0.1"
+
+                [features]
+                small = []
+                embedded = ["cfg(unix):small"]
+            "#,
+        )
+        .file("src/lib.rs", "")
+        .build();
+
+    p.cargo("check")
+        .masquerade_as_nightly_cargo(&["cfg-features"])
+        .with_status(101)
+        .with_stderr(
+            "\
+error: failed to parse manifest at `[..]`
+
+Caused by:
+  feature `cfg-features` is required
+
+  The package requires the Cargo feature called `cfg-features`, \
+  but that feature is not stabilized in this version of Cargo (1.[..]).
+  Consider adding `cargo-features = [\"cfg-features\"]` to the top of Cargo.toml \
+  (above the [package] table) to tell Cargo you are opting in to use this unstable feature.
+  See https://doc.rust-lang.org/nightly/cargo/reference/unstable.html#cfg-features \
+  for more information about the status of this feature.
+",
+        )
+        .run();
+}
+
+#[cargo_test]
+fn cfg_feature_values_cannot_be_published() {
+    let registry = RegistryBuilder::new().http_api().http_index().build();
+
+    let p = project()
+        .file(
+            "Cargo.toml",
+            r#"
+                cargo-features = ["cfg-features"]
+
+                [package]
+                name = "foo"
+                version = "0.0.1"
+                description = "foo"
+                license = "MIT"
+                homepage = "https://example.com/"
+
+                [features]
+                small = []
+                embedded = ["cfg(unix):small"]
+            "#,
+        )
+        .file("src/lib.rs", "")
+        .build();
+
+    p.cargo("check")
+        .masquerade_as_nightly_cargo(&["cfg-features"])
+        .with_stderr(
+            "\
+[CHECKING] foo [..]
+[FINISHED] [..]
+",
+        )
+        .run();
+
+    p.cargo("publish --no-verify")
+        .masquerade_as_nightly_cargo(&["cfg-features"])
+        .replace_crates_io(registry.index_url())
+        .with_status(101)
+        .with_stderr(
+            "\
+[ERROR] `foo` cannot be published.
+`cfg(...):` feature values are unstable and cannot be published yet.
+",
+        )
+        .run();
+}
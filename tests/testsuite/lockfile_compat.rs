@@ -881,6 +881,7 @@ dependencies = [
 [..]
 [ERROR] failed to select a version for the requirement `bar = \"*\"` (locked to 0.1.0)
 candidate versions found which didn't match: 0.0.1
+closest versions: 0.0.1 (too low)
 location searched: `dummy-registry` index (which is replacing registry `crates-io`)
 required by package `test v0.0.0 ([..])`
 perhaps a crate was updated and forgotten to be re-vendored?
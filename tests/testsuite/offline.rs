@@ -569,6 +569,7 @@ fn offline_resolve_optional_fail() {
             "\
 [ERROR] failed to select a version for the requirement `dep = \"^2.0\"`
 candidate versions found which didn't match: 1.0.0
+closest versions: 1.0.0 (too low)
 location searched: `[..]` index (which is replacing registry `crates-io`)
 required by package `foo v0.1.0 ([..]/foo)`
 perhaps a crate was updated and forgotten to be re-vendored?
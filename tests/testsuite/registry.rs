@@ -305,6 +305,7 @@ fn wrong_version() {
             "\
 error: failed to select a version for the requirement `foo = \">=1.0.0\"`
 candidate versions found which didn't match: 0.0.2, 0.0.1
+closest versions: 0.0.2 (too low)
 location searched: `[..]` index (which is replacing registry `[..]`)
 required by package `foo v0.0.1 ([..])`
 ",
@@ -320,6 +321,7 @@ required by package `foo v0.0.1 ([..])`
             "\
 error: failed to select a version for the requirement `foo = \">=1.0.0\"`
 candidate versions found which didn't match: 0.0.4, 0.0.3, 0.0.2, ...
+closest versions: 0.0.4 (too low)
 location searched: `[..]` index (which is replacing registry `[..]`)
 required by package `foo v0.0.1 ([..])`
 ",
@@ -695,6 +697,7 @@ fn relying_on_a_yank_is_bad() {
             "\
 error: failed to select a version for the requirement `baz = \"=0.0.2\"`
 candidate versions found which didn't match: 0.0.1
+closest versions: 0.0.1 (too low)
 location searched: `[..]` index (which is replacing registry `[..]`)
 required by package `bar v0.0.1`
     ... which satisfies dependency `bar = \"*\"` of package `foo [..]`